@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+drop TABLE if EXISTS sync_state;
\ No newline at end of file
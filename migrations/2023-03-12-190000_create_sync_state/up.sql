@@ -0,0 +1,10 @@
+-- Your SQL goes here
+create table if not EXISTS sync_state(
+    	id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account text not null,
+        folder text not null,
+        uid_validity bigint not null,
+        highest_uid bigint not null,
+        last_synced_at text,
+        unique(account, folder)
+);
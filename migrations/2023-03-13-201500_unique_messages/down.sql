@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS raw_messages_account_message_id;
+DROP INDEX IF EXISTS messages_account_message_id;
+ALTER TABLE raw_messages DROP COLUMN account;
@@ -0,0 +1,42 @@
+-- Your SQL goes here
+-- placeholder ids can't be told apart anymore, give every existing one its own id.
+-- a message and its raw copy were written one after the other, so they share a row id
+-- and end up with the same legacy id.
+UPDATE messages
+SET message_id = '<legacy-' || id || '@bes.local>'
+WHERE message_id = 'no id found!';
+
+UPDATE raw_messages
+SET message_id = '<legacy-' || id || '@bes.local>'
+WHERE message_id = 'no id found!';
+
+-- the same message can reach more than one account
+ALTER TABLE raw_messages ADD COLUMN account TEXT;
+
+UPDATE raw_messages
+SET account = coalesce(
+    (SELECT m.account FROM messages m
+     WHERE m.id = raw_messages.id AND m.message_id = raw_messages.message_id),
+    (SELECT m.account FROM messages m
+     WHERE m.message_id = raw_messages.message_id ORDER BY m.id LIMIT 1));
+
+-- keep the oldest copy but carry over any local state set on a duplicate
+UPDATE messages
+SET pinned_at = (SELECT max(d.pinned_at) FROM messages d
+                 WHERE d.account = messages.account AND d.message_id = messages.message_id),
+    done_at = (SELECT max(d.done_at) FROM messages d
+               WHERE d.account = messages.account AND d.message_id = messages.message_id),
+    reminder_at = (SELECT max(d.reminder_at) FROM messages d
+                   WHERE d.account = messages.account AND d.message_id = messages.message_id)
+WHERE id IN (SELECT min(id) FROM messages GROUP BY account, message_id HAVING count(*) > 1);
+
+DELETE FROM messages
+WHERE id NOT IN (SELECT min(id) FROM messages GROUP BY account, message_id);
+
+DELETE FROM raw_messages
+WHERE message_id IS NOT NULL
+  AND id NOT IN (SELECT min(id) FROM raw_messages WHERE message_id IS NOT NULL
+                 GROUP BY account, message_id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS messages_account_message_id ON messages(account, message_id);
+CREATE UNIQUE INDEX IF NOT EXISTS raw_messages_account_message_id ON raw_messages(account, message_id);
@@ -0,0 +1,35 @@
+-- Your SQL goes here
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+    subject,
+    content,
+    message_from,
+    message_to,
+    message_cc,
+    content='messages',
+    content_rowid='id',
+    tokenize='unicode61 remove_diacritics 2'
+);
+
+INSERT INTO messages_fts(messages_fts) VALUES ('rebuild');
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts(rowid, subject, content, message_from, message_to, message_cc)
+    VALUES (new.id, new.subject, new.content, new.message_from, new.message_to, new.message_cc);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, subject, content, message_from, message_to, message_cc)
+    VALUES ('delete', old.id, old.subject, old.content, old.message_from, old.message_to, old.message_cc);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_update
+AFTER UPDATE OF subject, content, message_from, message_to, message_cc ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, subject, content, message_from, message_to, message_cc)
+    VALUES ('delete', old.id, old.subject, old.content, old.message_from, old.message_to, old.message_cc);
+    INSERT INTO messages_fts(rowid, subject, content, message_from, message_to, message_cc)
+    VALUES (new.id, new.subject, new.content, new.message_from, new.message_to, new.message_cc);
+END;
+
+-- thread lookups by key and date ordering happen on every search
+CREATE INDEX IF NOT EXISTS messages_parent_thread_key ON messages(parent_thread_key);
+CREATE INDEX IF NOT EXISTS messages_sent_date ON messages(sent_date);
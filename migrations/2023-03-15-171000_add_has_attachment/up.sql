@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE messages
+ADD COLUMN has_attachment boolean not null default 0;
+
+-- best effort for mail stored before the column existed
+UPDATE messages
+SET has_attachment = 1
+WHERE message_id IN (
+    SELECT message_id FROM raw_messages
+    WHERE instr(lower(CAST(message AS TEXT)), 'content-disposition: attachment') > 0
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS messages_parent_id;
+ALTER TABLE messages DROP COLUMN message_references;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- every id from References and In-Reply-To, space separated. filled in for
+-- older rows by the rethread command.
+ALTER TABLE messages
+ADD COLUMN message_references text;
+
+CREATE INDEX IF NOT EXISTS messages_parent_id ON messages(parent_id);
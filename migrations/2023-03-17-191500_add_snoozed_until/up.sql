@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- reminder_at of a snooze that already woke up, shown as a badge in the inbox
+ALTER TABLE messages
+ADD COLUMN snoozed_until text;
+
+CREATE INDEX IF NOT EXISTS messages_reminder_at ON messages(reminder_at);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+drop TABLE if EXISTS pending_changes;
\ No newline at end of file
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- local pin/done/snooze/read changes waiting to be written back to the server
+create table if not EXISTS pending_changes(
+    	id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account text not null,
+        message_id text not null,
+        change text not null,
+        created_at text not null,
+        attempts integer not null default 0,
+        last_error text
+);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE messages
+ADD COLUMN seen_at text;
+
+-- everything stored so far was read somewhere already
+UPDATE messages SET seen_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now');
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+drop TABLE if EXISTS message_folders;
\ No newline at end of file
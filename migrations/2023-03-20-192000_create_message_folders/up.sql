@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- one row per folder a message lives in, the message itself is stored once
+create table if not EXISTS message_folders(
+    	id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account text not null,
+        message_id text not null,
+        folder text not null,
+        uid bigint,
+        unique(account, message_id, folder)
+);
+
+INSERT OR IGNORE INTO message_folders(account, message_id, folder)
+SELECT account, message_id, folders FROM messages WHERE folders IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS message_folders_folder ON message_folders(account, folder);
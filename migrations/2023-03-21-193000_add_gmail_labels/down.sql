@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+drop TABLE if EXISTS labels;
+ALTER TABLE messages DROP COLUMN labels;
+ALTER TABLE messages DROP COLUMN gm_thrid;
+ALTER TABLE messages DROP COLUMN gm_msgid;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- gmail's own ids, X-GM-THRID decides the thread when present
+ALTER TABLE messages
+ADD COLUMN gm_msgid bigint;
+ALTER TABLE messages
+ADD COLUMN gm_thrid bigint;
+-- comma separated copy of the labels table for display
+ALTER TABLE messages
+ADD COLUMN labels text;
+
+create table if not EXISTS labels(
+    	id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account text not null,
+        message_id text not null,
+        label text not null,
+        unique(account, message_id, label)
+);
+
+CREATE INDEX IF NOT EXISTS labels_label ON labels(account, label);
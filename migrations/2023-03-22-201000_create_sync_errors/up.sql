@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- messages that failed to store, kept with their raw bytes for `sync --retry-failed`
+create table if not EXISTS sync_errors(
+    	id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account text not null,
+        folder text not null,
+        uid bigint,
+        error text not null,
+        raw blob,
+        attempts integer not null default 1,
+        created_at text not null,
+        last_attempt_at text not null
+);
+
+CREATE INDEX IF NOT EXISTS sync_errors_account ON sync_errors(account, folder, uid);
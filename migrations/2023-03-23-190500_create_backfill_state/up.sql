@@ -0,0 +1,12 @@
+-- Your SQL goes here
+create table if not EXISTS backfill_state(
+    	id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account text not null,
+        folder text not null,
+        since text not null,
+        before text not null,
+        uid_validity bigint not null,
+        last_uid bigint not null,
+        updated_at text,
+        unique(account, folder, since, before)
+);
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+create table if not EXISTS drafts(
+    	id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account text not null,
+        message_id text not null,
+        message_to text,
+        message_cc text,
+        message_bcc text,
+        subject text,
+        body text,
+        in_reply_to text,
+        message_references text,
+        forward_of text,
+        parent_thread_key text,
+        created_at text not null,
+        updated_at text not null,
+        uploaded_at text,
+        unique(message_id)
+);
+create index if not EXISTS drafts_thread on drafts(parent_thread_key);
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- built messages waiting for their send time, delivered by the app or the daemon
+create table if not EXISTS outbox(
+    	id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account text not null,
+        message_id text not null unique,
+        draft_id integer,
+        subject text,
+        message_to text,
+        message_bcc text,
+        -- envelope recipients, bcc included
+        recipients text not null,
+        raw blob not null,
+        send_at text not null,
+        created_at text not null,
+        -- set while a delivery is in flight so the app and the daemon don't both send
+        claimed_at text,
+        attempts integer not null default 0,
+        last_error text,
+        -- given up on, waits for the user to retry or cancel
+        failed_at text
+);
+create index if not EXISTS outbox_due on outbox(send_at);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- set while a write back is in flight so two replays don't apply the same change
+ALTER TABLE pending_changes ADD COLUMN claimed_at text;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- one row per id in a message's References/In-Reply-To, so the replies to a message
+-- can be found without scanning message_references
+create table if not EXISTS thread_references(
+    	id INTEGER PRIMARY KEY AUTOINCREMENT,
+        account text not null,
+        message_id text not null,
+        reference text not null,
+        unique(account, message_id, reference)
+);
+
+-- message_references is space separated
+WITH RECURSIVE split(account, message_id, reference, rest) AS (
+    SELECT account, message_id, '', message_references || ' '
+    FROM messages WHERE message_references IS NOT NULL
+    UNION ALL
+    SELECT account, message_id, substr(rest, 1, instr(rest, ' ') - 1), substr(rest, instr(rest, ' ') + 1)
+    FROM split WHERE rest != ''
+)
+INSERT OR IGNORE INTO thread_references(account, message_id, reference)
+SELECT account, message_id, reference FROM split WHERE reference != '';
+
+CREATE INDEX IF NOT EXISTS thread_references_reference ON thread_references(account, reference);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- HIGHESTMODSEQ at the last sync, gmail labels are refreshed with CHANGEDSINCE from it
+ALTER TABLE sync_state ADD COLUMN highest_modseq bigint;
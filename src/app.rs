@@ -1,31 +1,49 @@
 use crate::{
-    components::{email::Email, email_thread::EmailThread, utils::*},
+    components::{
+        actions::{self, Action},
+        compose::{Compose, ComposeErrorState, ComposeState},
+        drafts::{self, DraftList},
+        email::Email,
+        email_thread::EmailThread,
+        outbox::{OutboxList, UndoSend, UndoState},
+        utils::*,
+    },
     config::{AccountConfig, DatabaseConfig},
-    database,
+    database::{self, MessageFilter, Target},
     log::debug_log,
+    outbox, push, snooze,
+    sync::{Progress, SyncProgress},
 };
 use dioxus::prelude::*;
 use dioxus_daisyui::prelude::*;
 use itertools::Itertools;
-use std::{cell::Cell, collections::HashSet};
-use tokio::runtime::Handle;
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 // filter ideas
 // filter img src urls https://github.com/rust-ammonia/ammonia/issues/175 ?
 
 pub fn App(cx: Scope<AppProps>) -> Element {
     let account_config: &UseState<Option<_>> = {
         let initial = cx.props.account_config.take();
-        use_state(&cx, || initial)
+        use_state(cx, || initial)
     };
 
     let database_config: &UseState<Option<_>> = {
         let initial = cx.props.database_config.take();
-        use_state(&cx, || initial)
+        use_state(cx, || initial)
+    };
+
+    let accounts: &UseState<Vec<_>> = {
+        let initial = cx.props.accounts.take();
+        use_state(cx, || initial)
     };
 
     let filter: &UseState<Option<_>> = {
         let initial = cx.props.view_filter.take();
-        use_state(&cx, || initial)
+        use_state(cx, || initial)
     };
 
     // state! why a state? login/setup/config process. inspired by twitvault
@@ -33,7 +51,8 @@ pub fn App(cx: Scope<AppProps>) -> Element {
         (Some(filter), Some(account_config), Some(database_config)) => {
             use_shared_state_provider(cx, || ViewFilterState(filter.clone()));
             cx.render(rsx! { EmailContent {
-                account_config: account_config.clone(), database_config: database_config.clone()
+                account_config: account_config.clone(), database_config: database_config.clone(),
+                accounts: accounts.get().clone()
             }})
         }
         (_, _, _) => {
@@ -48,7 +67,7 @@ pub fn App(cx: Scope<AppProps>) -> Element {
 
     cx.render(rsx! {
     section { class: "header",
-        Header{  pinned: false},
+        Header{},
     }
     section { class: "content",
         view
@@ -60,15 +79,42 @@ pub struct AppProps {
     pub account_config: Cell<Option<AccountConfig>>,
     pub database_config: Cell<Option<DatabaseConfig>>,
     pub view_filter: Cell<Option<ViewFilter>>,
+    // every account of the config, for the outbox and push
+    pub accounts: Cell<Vec<AccountConfig>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum View {
+    #[default]
+    Inbox,
+    Pinned,
+    Snoozed,
+    Done,
+    Drafts,
+    Outbox,
+    Folder(String),
+}
+
+impl View {
+    pub fn label(&self) -> String {
+        match self {
+            View::Inbox => "Inbox".into(),
+            View::Pinned => "Pinned".into(),
+            View::Snoozed => "Snoozed".into(),
+            View::Done => "Done".into(),
+            View::Drafts => "Drafts".into(),
+            View::Outbox => "Outbox".into(),
+            View::Folder(folder) => folder.clone(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct ViewFilter {
     pub query: Option<String>,
-    pub pinned: bool,
+    pub view: View,
     pub expanded_email: Option<HashSet<String>>,
     pub expanded_thread: Option<HashSet<String>>,
-    pub folder: Option<String>,
     pub account: Option<String>,
 }
 
@@ -78,25 +124,20 @@ pub struct Config {
     pub page_loade: usize,
 }
 
-#[derive(PartialEq, Props)]
-struct HeaderProps {
-    pinned: bool,
-}
-
-fn Header(cx: Scope<HeaderProps>) -> Element {
-    let mut view_filter_state = use_shared_state::<ViewFilterState>(cx).unwrap();
+fn Header(cx: Scope) -> Element {
+    let view_filter_state = use_shared_state::<ViewFilterState>(cx).unwrap();
     let view_filter = &view_filter_state.read().0;
-    let search_text = use_state(&cx, || view_filter.query.clone());
-    let pinned = use_state(&cx, || view_filter.pinned.clone());
+    let search_text = use_state(cx, || view_filter.query.clone());
     cx.render(rsx! {
         div{
         input {
-            r#type: "text",
+            r#type: "search",
+            value: "{search_text.get().clone().unwrap_or_default()}",
             class: class!(w_80 h_12 text_2xl m_4 px_2 input input_primary input_bordered),
-            placeholder: "Type to search",
+            placeholder: "Search, e.g. from:alice label:receipts after:2023-03-01",
             oninput: move | evt | {
                 let text =  evt.value.clone();
-                if text.len()>0{
+                if !text.is_empty(){
                     view_filter_state.write().0.query = Some(text.clone());
                     search_text.set(Some(text));
                 }else{
@@ -108,34 +149,176 @@ fn Header(cx: Scope<HeaderProps>) -> Element {
     }
     })
 }
+const SNOOZE_CHECK_SECS: u64 = 30;
+// often enough that an undone send's window is close to what it says
+const OUTBOX_CHECK_SECS: u64 = 2;
+
 pub struct AccountConfigState(pub AccountConfig);
 pub struct DatabaseConfigState(pub DatabaseConfig);
+// every configured account, write backs go to whichever one a message is in
+pub struct AccountsState(pub Vec<AccountConfig>);
 pub struct ViewFilterState(pub ViewFilter);
 #[inline_props]
 async fn EmailContent(
     cx: Scope,
     account_config: AccountConfig,
     database_config: DatabaseConfig,
-) -> Element {
+    accounts: Vec<AccountConfig>,
+) -> Element<'a> {
     use_shared_state_provider(cx, || AccountConfigState(account_config.clone()));
     use_shared_state_provider(cx, || DatabaseConfigState(database_config.clone()));
+    use_shared_state_provider(cx, || AccountsState(accounts.clone()));
+    use_shared_state_provider(cx, || ComposeState(None));
+    use_shared_state_provider(cx, || ComposeErrorState(None));
+    use_shared_state_provider(cx, || UndoState(None));
 
     let view_filter_state = use_shared_state::<ViewFilterState>(cx).unwrap();
+    // redrawn when a compose window closes, its draft shows under the thread it answers
+    use_shared_state::<ComposeState>(cx).unwrap();
+    // shared state borrows the scope and can't go into a future, background tasks bump
+    // this instead when they changed the database and the lists are read again
+    let revision = use_state(cx, || 0u64);
+
+    // wake snoozed mail while the app is open
+    let wake_revision = revision.clone();
+    let wake_database_config = database_config.clone();
+    use_future(cx, (), move |_| async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(SNOOZE_CHECK_SECS));
+        loop {
+            ticker.tick().await;
+            if snooze::wake_due(&wake_database_config).unwrap_or_default() > 0 {
+                wake_revision.modify(|revision| revision + 1);
+            }
+        }
+    });
+
+    // deliver what's due in the outbox, the daemon does the same when the app is closed
+    let outbox_revision = revision.clone();
+    let outbox_database_config = database_config.clone();
+    let outbox_accounts = accounts.clone();
+    use_future(cx, (), move |_| async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(OUTBOX_CHECK_SECS));
+        loop {
+            ticker.tick().await;
+            let database_config = outbox_database_config.clone();
+            let accounts = outbox_accounts.clone();
+            let delivered = tokio::task::spawn_blocking(move || {
+                outbox::deliver_due(&database_config, &accounts)
+            })
+            .await;
+            match delivered {
+                Ok(Ok(0)) => {}
+                // the sent copies show up in their threads, the outbox empties
+                Ok(Ok(_)) => outbox_revision.modify(|revision| revision + 1),
+                Ok(Err(e)) => debug_log(format!("outbox: {e}")),
+                Err(e) => debug_log(format!("outbox: {e}")),
+            }
+        }
+    });
+
+    // new mail from IDLE or polling, redraw with it
+    let sync_progress = use_state(cx, || None::<SyncProgress>);
+    let push_revision = revision.clone();
+    let push_progress = sync_progress.clone();
+    let push_database_config = database_config.clone();
+    let push_accounts = accounts.clone();
+    use_future(cx, (), move |_| async move {
+        let (changed, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (progress_sender, mut progress_receiver) = tokio::sync::mpsc::unbounded_channel();
+        // dropped with this future, which stops the watchers
+        let _watchers = push::watch_all(
+            &push_database_config,
+            push_accounts,
+            changed,
+            Progress::new(progress_sender),
+        );
+        // not select!, its bindings resolve to the daisyui class constants in scope
+        let new_mail = async {
+            while let Some(account) = receiver.recv().await {
+                debug_log(format!("new mail for {account}"));
+                push_revision.modify(|revision| revision + 1);
+            }
+        };
+        let sync_updates = async {
+            while let Some(update) = progress_receiver.recv().await {
+                push_progress.set((update.done < update.total).then_some(update));
+            }
+        };
+        tokio::join!(new_mail, sync_updates);
+    });
+    let progress_bar = sync_progress.get().as_ref().and_then(|current| {
+        cx.render(rsx!(div {
+            class: class!(flex items_center gap_2 px_3 py_1 text_sm text_gray_500),
+            "Syncing {current.account} {current.folder}"
+            progress {
+                class: class!(progress progress_info w_48),
+                value: "{current.done}",
+                max: "{current.total}",
+            }
+        }))
+    });
+
     let view_filter = &view_filter_state.read().0;
-    let groups = database::list_threads(&database_config, view_filter.into()).unwrap_or_default();
+    let searching = view_filter.query.is_some();
+    // drafts aren't messages yet, they get a list of their own
+    let showing_drafts = view_filter.view == View::Drafts;
+    let showing_outbox = view_filter.view == View::Outbox;
+    let threads = if showing_drafts || showing_outbox {
+        Ok((vec![], HashMap::new()))
+    } else {
+        MessageFilter::try_from(view_filter)
+            .and_then(|filter| database::list_threads(database_config, filter))
+    };
+    let failed_sends =
+        database::count_failed_outbox(database_config, &account_config.name).unwrap_or_default();
+    // one query for the drafts of every thread shown
+    let mut thread_drafts = if showing_drafts || showing_outbox {
+        HashMap::new()
+    } else {
+        database::list_drafts(database_config, &account_config.name)
+            .map(drafts::by_thread)
+            .unwrap_or_default()
+    };
+    let (groups, snippets, error) = match threads {
+        Ok((groups, snippets)) => (groups, snippets, String::new()),
+        Err(error) => (vec![], HashMap::new(), error),
+    };
     debug_log(groups.len());
+    let unread_threads = groups
+        .iter()
+        .filter(|thread| thread.iter().any(|m| m.seen_at.is_none()))
+        .filter_map(|thread| thread.first().and_then(|m| m.parent_thread_key.clone()))
+        .map(Target::Thread)
+        .collect::<Vec<_>>();
     let mut list = vec![];
-    for (key, group) in &groups
-        .into_iter()
-        .group_by(|e| date_group(&e.first().unwrap().sent_at.clone().unwrap_or_default()))
-    {
+    // ranked results make no sense split up by date
+    for (key, group) in &groups.into_iter().group_by(|e| {
+        if searching {
+            "Search results".to_string()
+        } else {
+            date_group(&e.first().unwrap().sent_at.clone().unwrap_or_default())
+        }
+    }) {
         let threads = group
             .into_iter()
             .map(|e| EmailThread {
+                drafts: e
+                    .first()
+                    .and_then(|m| m.parent_thread_key.as_ref())
+                    .and_then(|key| thread_drafts.remove(key))
+                    .unwrap_or_default(),
+                thread_key: e
+                    .first()
+                    .unwrap()
+                    .parent_thread_key
+                    .clone()
+                    .unwrap_or_default(),
                 subject: e.first().unwrap().subject.clone().unwrap_or_default(),
                 children: e
                     .into_iter()
                     .map(|message| Email {
+                        snippet: snippets.get(&message.message_id).cloned(),
+                        account: message.account,
                         message_id: message.message_id,
                         subject: message.subject.unwrap_or_default(),
                         from: message.message_from.unwrap_or_default(),
@@ -148,6 +331,12 @@ async fn EmailContent(
                         done: message.done_at.map(|_| true).unwrap_or(false),
                         pinned: message.pinned_at.map(|_| true).unwrap_or(false),
                         reminder_at: message.reminder_at.unwrap_or_default(),
+                        snoozed_until: message.snoozed_until.unwrap_or_default(),
+                        seen: message.seen_at.is_some(),
+                        labels: message
+                            .labels
+                            .map(|labels| labels.split(',').map(|l| l.to_owned()).collect())
+                            .unwrap_or_default(),
                     })
                     .collect::<Vec<_>>(),
             })
@@ -157,7 +346,6 @@ async fn EmailContent(
             children: threads,
         });
     }
-    let handle = Handle::current();
     let views = list
         .into_iter()
         .map(|group| {
@@ -169,16 +357,113 @@ async fn EmailContent(
 
     cx.render(rsx!(
         div {
-            class: class!(text_slate_600),
+        class: class!(flex),
+        ViewList {}
+        div {
+            class: class!(grow text_slate_600),
+            progress_bar
+            if !error.is_empty() {
+                rsx!(div {
+                    class: class!(mx_4 px_3 py_2 text_red_700 bg_red_100 rounded),
+                    "{error}"
+                })
+            }
+            if !unread_threads.is_empty() {
+                rsx!(div {
+                    class: class!(flex justify_end px_3 py_1),
+                    button {
+                        class: class!(text_sm text_sky_600 hover(underline)),
+                        onclick: move |_| {
+                            actions::apply_all(database_config, accounts, &unread_threads, Action::Seen(true));
+                            view_filter_state.write();
+                        },
+                        "Mark all read"
+                    }
+                })
+            }
+            if failed_sends > 0 && !showing_outbox {
+                rsx!(div {
+                    class: class!(mx_4 px_3 py_2 text_red_700 bg_red_100 rounded cursor_pointer),
+                    onclick: move |_| {
+                        view_filter_state.write().0.view = View::Outbox;
+                    },
+                    "{failed_sends} message(s) couldn't be sent, see the Outbox"
+                })
+            }
+            if showing_drafts {
+                rsx!(DraftList { revision: *revision.get() })
+            }
+            if showing_outbox {
+                rsx!(OutboxList { revision: *revision.get() })
+            }
             for view in views.iter(){
                 div{
                     class: "email-group",
                     view
                 }
             }
+        }
+        Compose {}
+        UndoSend {}
     }))
 }
 
+// sidebar switching between the inbox, the pin/snooze/done states and folders
+fn ViewList(cx: Scope) -> Element {
+    let database_config = use_shared_state::<DatabaseConfigState>(cx).unwrap();
+    let account_config = use_shared_state::<AccountConfigState>(cx).unwrap();
+    let compose_state = use_shared_state::<ComposeState>(cx).unwrap();
+    let view_filter_state = use_shared_state::<ViewFilterState>(cx).unwrap();
+    let current = view_filter_state.read().0.view.clone();
+    let can_send = account_config.read().0.can_send();
+    let folders = database::list_folders(&database_config.read().0).unwrap_or_default();
+    let views = [
+        View::Inbox,
+        View::Pinned,
+        View::Snoozed,
+        View::Done,
+        View::Drafts,
+        View::Outbox,
+    ]
+    .into_iter()
+    .chain(folders.into_iter().map(View::Folder))
+    .map(|view| {
+        let class = if view == current {
+            class!(px_4 py_2 cursor_pointer rounded_r_full bg_sky_100 text_sky_700 font_bold)
+        } else {
+            class!(px_4 py_2 cursor_pointer rounded_r_full hover(bg_slate_200))
+        };
+        (view.label(), class, view)
+    })
+    .collect::<Vec<_>>();
+
+    cx.render(rsx! {
+        div {
+            class: class!(w_48 shrink_0 flex flex_col py_2),
+            if can_send {
+                rsx!(button {
+                    class: class!(btn btn_primary mx_4 mb_2),
+                    onclick: move |_| {
+                        if compose_state.read().0.is_none() {
+                            compose_state.write().0 = Some(Default::default());
+                        }
+                    },
+                    "Compose"
+                })
+            }
+            for (name, class, view) in views.into_iter() {
+                div {
+                    class: "{class}",
+                    onclick: move |_| {
+                        view_filter_state.write().0.view = view.clone();
+                    },
+                    "{name}"
+                }
+            }
+        }
+    })
+}
+
 #[derive(PartialEq, Clone)]
 struct EmailGroup {
     pub name: String,
@@ -186,8 +471,8 @@ struct EmailGroup {
 }
 
 #[inline_props]
-fn EmailGroup(cx: Scope, group: EmailGroup) -> Element {
-    let expanded = use_state(&cx, || true);
+fn EmailGroup(cx: Scope, group: EmailGroup) -> Element<'a> {
+    let expanded = use_state(cx, || true);
     cx.render(rsx! {
         div {
             class: class!(mb_5),
@@ -204,7 +489,7 @@ fn EmailGroup(cx: Scope, group: EmailGroup) -> Element {
             if *expanded.get(){
                 rsx!(div {
                     class: class!(flex flex_col),
-                    for email_thread in group.children.to_owned().into_iter().rev(){
+                    for email_thread in group.children.iter().cloned().rev(){
                         div{
                             class: "email-thread",
                             EmailThread{ thread: email_thread}
@@ -0,0 +1,175 @@
+use crate::{
+    app::{AccountsState, DatabaseConfigState, ViewFilterState},
+    config::{AccountConfig, DatabaseConfig},
+    database::{self, Target},
+    log::log,
+    snooze::{self, PRESETS},
+    write_back,
+};
+use chrono::{DateTime, Local, Utc};
+use dioxus::prelude::*;
+pub use dioxus_tailwindcss;
+pub use dioxus_tailwindcss::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Pin(bool),
+    Done(bool),
+    Snooze(Option<DateTime<Utc>>),
+    Seen(bool),
+}
+
+pub fn apply(
+    database_config: &DatabaseConfig,
+    accounts: &[AccountConfig],
+    target: &Target,
+    action: Action,
+) {
+    apply_all(
+        database_config,
+        accounts,
+        std::slice::from_ref(target),
+        action,
+    )
+}
+
+// changes local state right away, the server follows in the background
+pub fn apply_all(
+    database_config: &DatabaseConfig,
+    accounts: &[AccountConfig],
+    targets: &[Target],
+    action: Action,
+) {
+    for target in targets.iter() {
+        let result = match action {
+            Action::Pin(pinned) => database::set_pinned(database_config, target, pinned),
+            Action::Done(done) => database::set_done(database_config, target, done),
+            Action::Snooze(until) => database::snooze(database_config, target, until),
+            Action::Seen(seen) => database::set_seen(database_config, target, seen),
+        };
+        if let Err(e) = result {
+            log(format!("{action:?} on {target:?} failed: {e}"));
+        }
+    }
+    let (database_config, accounts) = (database_config.clone(), accounts.to_vec());
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = write_back::replay(&database_config, &accounts) {
+            log(format!("write back failed: {e}"));
+        }
+    });
+}
+
+// the ☰ menu on threads and emails
+#[inline_props]
+pub fn ActionMenu(
+    cx: Scope,
+    target: Target,
+    pinned: bool,
+    done: bool,
+    snoozed: bool,
+    seen: bool,
+) -> Element<'a> {
+    let database_config = use_shared_state::<DatabaseConfigState>(cx).unwrap();
+    let accounts = use_shared_state::<AccountsState>(cx).unwrap();
+    let view_filter_state = use_shared_state::<ViewFilterState>(cx).unwrap();
+    let open = use_state(cx, || false);
+
+    let run = move |action: Action| {
+        apply(
+            &database_config.read().0,
+            &accounts.read().0,
+            target,
+            action,
+        );
+        open.set(false);
+        // nothing in the filter changed, writing just makes the list reload
+        view_filter_state.write();
+    };
+    let pin_label = if *pinned { "Unpin" } else { "Pin" };
+    let done_label = if *done { "Move to inbox" } else { "Done" };
+    let earliest = Local::now().format(snooze::CUSTOM_FORMAT).to_string();
+    let seen_label = if *seen {
+        "Mark as unread"
+    } else {
+        "Mark as read"
+    };
+
+    cx.render(rsx! {
+        div {
+            class: class!(relative shrink),
+            div {
+                class: class!(cursor_pointer px_1),
+                onclick: move |evt| {
+                    evt.stop_propagation();
+                    open.set(!*open.get());
+                },
+                "☰"
+            }
+            if *open.get() {
+                rsx!(div {
+                    class: class!(absolute right_0 z_10 flex flex_col bg_white border border_gray_200 rounded shadow whitespace_nowrap),
+                    div {
+                        class: class!(px_3 py_1 cursor_pointer hover(bg_slate_200)),
+                        onclick: move |evt| {
+                            evt.stop_propagation();
+                            run(Action::Pin(!*pinned));
+                        },
+                        "{pin_label}"
+                    }
+                    div {
+                        class: class!(px_3 py_1 cursor_pointer hover(bg_slate_200)),
+                        onclick: move |evt| {
+                            evt.stop_propagation();
+                            run(Action::Done(!*done));
+                        },
+                        "{done_label}"
+                    }
+                    div {
+                        class: class!(px_3 py_1 cursor_pointer hover(bg_slate_200)),
+                        onclick: move |evt| {
+                            evt.stop_propagation();
+                            run(Action::Seen(!*seen));
+                        },
+                        "{seen_label}"
+                    }
+                    div {
+                        class: class!(px_3 pt_2 text_xs text_gray_500 uppercase),
+                        "Snooze"
+                    }
+                    for preset in PRESETS.iter() {
+                        div {
+                            class: class!(px_3 py_1 cursor_pointer hover(bg_slate_200)),
+                            onclick: move |evt| {
+                                evt.stop_propagation();
+                                run(Action::Snooze(Some(preset.until(Local::now()))));
+                            },
+                            "{preset.label()}"
+                        }
+                    }
+                    input {
+                        r#type: "datetime-local",
+                        class: class!(mx_3 my_1 text_sm),
+                        min: "{earliest}",
+                        onclick: move |evt| evt.stop_propagation(),
+                        onchange: move |evt| {
+                            let now = Local::now();
+                            if let Some(preset) = snooze::parse_custom(&evt.value, now) {
+                                run(Action::Snooze(Some(preset.until(now))));
+                            }
+                        },
+                    }
+                    if *snoozed {
+                        rsx!(div {
+                            class: class!(px_3 py_1 cursor_pointer hover(bg_slate_200)),
+                            onclick: move |evt| {
+                                evt.stop_propagation();
+                                run(Action::Snooze(None));
+                            },
+                            "Unsnooze"
+                        })
+                    }
+                })
+            }
+        }
+    })
+}
@@ -0,0 +1,300 @@
+use crate::{
+    app::{AccountConfigState, DatabaseConfigState},
+    components::outbox::UndoState,
+    drafts::{self, AUTOSAVE_SECS},
+    log::log,
+    outbox,
+    send::OutgoingMessage,
+};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use dioxus::prelude::*;
+use dioxus_daisyui::prelude::*;
+pub use dioxus_tailwindcss;
+pub use dioxus_tailwindcss::prelude::*;
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+// the message being written, None while the compose window is closed
+pub struct ComposeState(pub Option<OutgoingMessage>);
+// shown above the buttons of the compose window, whoever opened it can set it too
+pub struct ComposeErrorState(pub Option<String>);
+
+// autosave writes on another thread, closing, discarding and sending wait for it here.
+// `generation` moves on whenever the window closes so a save that was already under
+// way doesn't bring the message back.
+#[derive(Default)]
+struct SaveSlot {
+    generation: u64,
+    // the draft autosave made for a message that didn't have one yet
+    draft: Option<(i32, String)>,
+}
+
+impl SaveSlot {
+    // the ids autosave gave the message after it was read from the window
+    fn fill(&self, outgoing: &mut OutgoingMessage) {
+        if let (None, Some((id, message_id))) = (outgoing.draft_id, &self.draft) {
+            outgoing.draft_id = Some(*id);
+            outgoing.message_id = Some(message_id.clone());
+        }
+    }
+
+    fn close(&mut self) {
+        self.generation += 1;
+        self.draft = None;
+    }
+}
+
+// a save that panicked left nothing half written, the slot is still good
+fn lock(slot: &Mutex<SaveSlot>) -> MutexGuard<SaveSlot> {
+    slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// the value of a datetime-local input, in local time
+fn parse_send_at(value: &str) -> Result<DateTime<Utc>, String> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+        .ok()
+        .and_then(|naive| Local.from_local_datetime(&naive).single())
+        .map(|local| local.with_timezone(&Utc))
+        .ok_or_else(|| format!("send later: \"{value}\" isn't a date and time"))
+}
+
+pub fn Compose(cx: Scope) -> Element {
+    let compose_state = use_shared_state::<ComposeState>(cx).unwrap();
+    let account_config = use_shared_state::<AccountConfigState>(cx).unwrap();
+    let database_config = use_shared_state::<DatabaseConfigState>(cx).unwrap();
+    let undo_state = use_shared_state::<UndoState>(cx).unwrap();
+    let error = use_shared_state::<ComposeErrorState>(cx).unwrap();
+    let send_later = use_state(cx, String::new);
+    let save_slot: &Arc<Mutex<SaveSlot>> =
+        cx.use_hook(|| Arc::new(Mutex::new(SaveSlot::default())));
+
+    // saves the open message whenever it changed since the last save. shared state
+    // can't go into a future, the value behind it can.
+    let autosave_state = compose_state.inner();
+    let autosave_slot = save_slot.clone();
+    let autosave_account = account_config.read().0.clone();
+    let autosave_database_config = database_config.read().0.clone();
+    use_future(cx, (), move |_| async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(AUTOSAVE_SECS));
+        let mut saved = None::<OutgoingMessage>;
+        loop {
+            ticker.tick().await;
+            let Some(outgoing) = autosave_state.borrow().read().0.clone() else {
+                saved = None;
+                continue;
+            };
+            if drafts::is_blank(&outgoing) || saved.as_ref() == Some(&outgoing) {
+                continue;
+            }
+            let generation = lock(&autosave_slot).generation;
+            let (slot, database_config, account) = (
+                autosave_slot.clone(),
+                autosave_database_config.clone(),
+                autosave_account.clone(),
+            );
+            let mut writing = outgoing.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let mut slot = lock(&slot);
+                // closed, discarded or sent while this waited
+                if slot.generation != generation {
+                    return Ok(());
+                }
+                slot.fill(&mut writing);
+                slot.draft = Some(drafts::save(&database_config, &account, &writing)?);
+                Ok(())
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|result: Result<(), String>| result);
+            match result {
+                Ok(()) => saved = Some(outgoing),
+                Err(e) => log(format!("saving draft: {e}")),
+            }
+        }
+    });
+
+    let outgoing = match &compose_state.read().0 {
+        Some(outgoing) => outgoing.clone(),
+        None => return None,
+    };
+    let edit = move |change: &dyn Fn(&mut OutgoingMessage)| {
+        if let Some(outgoing) = compose_state.write().0.as_mut() {
+            change(outgoing);
+        }
+    };
+
+    // into the outbox, right away it can be undone for a few seconds
+    let queue = move |later: bool| {
+        let account_config = account_config.read().0.clone();
+        let send_at = if later {
+            match parse_send_at(send_later.get()) {
+                Ok(send_at) => send_at,
+                Err(e) => {
+                    error.write().0 = Some(e);
+                    return;
+                }
+            }
+        } else {
+            Utc::now() + chrono::Duration::seconds(account_config.outbox.undo_secs as i64)
+        };
+        let mut slot = lock(save_slot);
+        // what's in the window now, with the ids autosave gave it
+        let Some(mut outgoing) = compose_state.read().0.clone() else {
+            return;
+        };
+        slot.fill(&mut outgoing);
+        let queued = outbox::queue(
+            &database_config.read().0,
+            &account_config,
+            &outgoing,
+            send_at,
+        );
+        let id = match queued {
+            Ok(id) => id,
+            Err(e) => {
+                error.write().0 = Some(e);
+                return;
+            }
+        };
+        slot.close();
+        compose_state.write().0 = None;
+        error.write().0 = None;
+        send_later.set(String::new());
+        undo_state.write().0 = (!later && account_config.outbox.undo_secs > 0).then_some(id);
+    };
+    let title = if outgoing.draft_id.is_some() {
+        "Draft"
+    } else {
+        "New message"
+    };
+
+    cx.render(rsx! {
+        div {
+            class: class!(fixed bottom_0 right_8 z_20 w_96 flex flex_col gap_2 p_3 bg_white border border_gray_200 rounded_t shadow_lg),
+            div {
+                class: class!(flex justify_between text_sm font_bold text_slate_600),
+                "{title}"
+                div {
+                    class: class!(cursor_pointer px_1),
+                    // closing keeps the draft, saved one last time and uploaded
+                    onclick: move |_| {
+                        let mut slot = lock(save_slot);
+                        let closed = compose_state.write().0.take();
+                        error.write().0 = None;
+                        let closed = closed.filter(|o| !drafts::is_blank(o)).map(|mut o| {
+                            slot.fill(&mut o);
+                            o
+                        });
+                        slot.close();
+                        drop(slot);
+                        let Some(outgoing) = closed else {
+                            return;
+                        };
+                        let account_config = account_config.read().0.clone();
+                        let database_config = database_config.read().0.clone();
+                        match drafts::save(&database_config, &account_config, &outgoing) {
+                            Ok((id, _)) => {
+                                thread::spawn(move || {
+                                    if let Err(e) = drafts::upload(&database_config, &account_config, id) {
+                                        log(format!("uploading draft: {e}"));
+                                    }
+                                });
+                            }
+                            Err(e) => log(format!("saving draft: {e}")),
+                        }
+                    },
+                    "✕"
+                }
+            }
+            input {
+                class: class!(input input_sm input_bordered),
+                placeholder: "To",
+                value: "{outgoing.to}",
+                oninput: move |evt| edit(&|o| o.to = evt.value.clone()),
+            }
+            input {
+                class: class!(input input_sm input_bordered),
+                placeholder: "Cc",
+                value: "{outgoing.cc}",
+                oninput: move |evt| edit(&|o| o.cc = evt.value.clone()),
+            }
+            input {
+                class: class!(input input_sm input_bordered),
+                placeholder: "Bcc",
+                value: "{outgoing.bcc}",
+                oninput: move |evt| edit(&|o| o.bcc = evt.value.clone()),
+            }
+            input {
+                class: class!(input input_sm input_bordered),
+                placeholder: "Subject",
+                value: "{outgoing.subject}",
+                oninput: move |evt| edit(&|o| o.subject = evt.value.clone()),
+            }
+            textarea {
+                class: class!(textarea textarea_bordered h_64),
+                value: "{outgoing.body}",
+                oninput: move |evt| edit(&|o| o.body = evt.value.clone()),
+            }
+            for attachment in outgoing.attachments.iter() {
+                div {
+                    class: class!(text_sm text_slate_600),
+                    "📎 {attachment.name}"
+                }
+            }
+            if let Some(e) = &error.read().0 {
+                rsx!(div {
+                    class: class!(px_3 py_2 text_sm text_red_700 bg_red_100 rounded),
+                    "{e}"
+                })
+            }
+            div {
+                class: class!(flex justify_end gap_2),
+                button {
+                    class: class!(btn btn_sm btn_ghost),
+                    onclick: move |_| {
+                        let mut slot = lock(save_slot);
+                        let discarded = compose_state.write().0.take();
+                        error.write().0 = None;
+                        let id = discarded.and_then(|mut o| {
+                            slot.fill(&mut o);
+                            o.draft_id
+                        });
+                        slot.close();
+                        drop(slot);
+                        let Some(id) = id else {
+                            return;
+                        };
+                        match drafts::forget(&database_config.read().0, id) {
+                            Ok(draft) => {
+                                let account_config = account_config.read().0.clone();
+                                thread::spawn(move || drafts::remove_uploaded(&account_config, &draft));
+                            }
+                            Err(e) => log(format!("discarding draft: {e}")),
+                        }
+                    },
+                    "Discard"
+                }
+                input {
+                    class: class!(input input_sm input_bordered),
+                    r#type: "datetime-local",
+                    value: "{send_later.get()}",
+                    oninput: move |evt| send_later.set(evt.value.clone()),
+                }
+                button {
+                    class: class!(btn btn_sm),
+                    disabled: "{send_later.get().is_empty()}",
+                    onclick: move |_| queue(true),
+                    "Send later"
+                }
+                button {
+                    class: class!(btn btn_sm btn_primary),
+                    onclick: move |_| queue(false),
+                    "Send"
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,117 @@
+use crate::{
+    app::{AccountConfigState, DatabaseConfigState},
+    components::{compose::ComposeState, utils::snoozed_date_format},
+    database, drafts,
+    log::log,
+    models::Draft,
+};
+use dioxus::prelude::*;
+pub use dioxus_tailwindcss;
+pub use dioxus_tailwindcss::prelude::*;
+use itertools::Itertools;
+use std::collections::HashMap;
+
+#[derive(Props, PartialEq)]
+pub struct DraftListProps {
+    // changes when a delivery in the background removed sent drafts, only there to redraw
+    revision: u64,
+}
+
+// every draft of the account, newest first
+pub fn DraftList(cx: Scope<DraftListProps>) -> Element {
+    let account_config = use_shared_state::<AccountConfigState>(cx).unwrap();
+    let database_config = use_shared_state::<DatabaseConfigState>(cx).unwrap();
+    // redrawn when a compose window is opened or closed
+    use_shared_state::<ComposeState>(cx).unwrap();
+    let drafts = database::list_drafts(&database_config.read().0, &account_config.read().0.name);
+
+    let rows = match drafts {
+        Ok(drafts) if drafts.is_empty() => cx.render(rsx!(div {
+            class: class!(px_3 py_2 text_sm text_gray_500),
+            "No drafts"
+        })),
+        Ok(drafts) => cx.render(rsx!(for draft in drafts.into_iter() {
+            DraftRow { draft: draft }
+        })),
+        Err(e) => cx.render(rsx!(div {
+            class: class!(mx_4 px_3 py_2 text_red_700 bg_red_100 rounded),
+            "{e}"
+        })),
+    };
+
+    cx.render(rsx! {
+        div {
+            class: class!(mb_5),
+            div {
+                class: class!(uppercase px_3 py_2 w_full text_sm text_sky_600 border_b border_b_slate_200),
+                "Drafts"
+            }
+            rows
+        }
+    })
+}
+
+// the drafts answering a thread, shown under its messages. the list loads them for
+// every thread at once.
+#[inline_props]
+pub fn ThreadDrafts(cx: Scope, drafts: Vec<Draft>) -> Element<'a> {
+    cx.render(rsx! {
+        for draft in drafts.iter().cloned() {
+            DraftRow { draft: draft }
+        }
+    })
+}
+
+// drafts by the thread they answer
+pub fn by_thread(drafts: Vec<Draft>) -> HashMap<String, Vec<Draft>> {
+    drafts
+        .into_iter()
+        .filter_map(|draft| Some((draft.parent_thread_key.clone()?, draft)))
+        .into_group_map()
+}
+
+#[inline_props]
+fn DraftRow(cx: Scope, draft: Draft) -> Element<'a> {
+    let compose_state = use_shared_state::<ComposeState>(cx).unwrap();
+    let database_config = use_shared_state::<DatabaseConfigState>(cx).unwrap();
+    let to = draft.message_to.clone().unwrap_or_default();
+    let subject = draft
+        .subject
+        .clone()
+        .unwrap_or_else(|| "(no subject)".into());
+    let date = snoozed_date_format(&draft.updated_at);
+    let id = draft.id;
+
+    cx.render(rsx! {
+        div {
+            class: class!(flex justify_between px_3 py_2 gap_5 w_full border_t border_t_gray_200 cursor_pointer hover(bg_slate_200)),
+            onclick: move |_| {
+                let Some(id) = id else { return };
+                // the open one is saved on its own, don't swap it out from under it
+                if compose_state.read().0.is_some() {
+                    return;
+                }
+                match drafts::open(&database_config.read().0, id) {
+                    Ok(outgoing) => compose_state.write().0 = Some(outgoing),
+                    Err(e) => log(e),
+                }
+            },
+            div {
+                class: class!(text_red_600),
+                "Draft"
+            }
+            div {
+                class: class!(w_3__12 overflow_hidden text_ellipsis whitespace_nowrap),
+                "{to}"
+            }
+            div {
+                class: class!(w_full grow overflow_hidden text_ellipsis whitespace_nowrap),
+                "{subject}"
+            }
+            div {
+                class: class!(w_2__12 text_right overflow_hidden whitespace_nowrap),
+                "{date}"
+            }
+        }
+    })
+}
@@ -1,11 +1,23 @@
-use crate::{app::DatabaseConfigState, components::utils::*, database, messages::parse_emails};
+use crate::{
+    app::DatabaseConfigState,
+    app::{AccountConfigState, AccountsState, ViewFilterState},
+    components::{
+        actions::{self, Action, ActionMenu},
+        compose::{ComposeErrorState, ComposeState},
+        utils::*,
+    },
+    database::{self, Target},
+    messages::parse_emails,
+    send,
+};
 use dioxus::prelude::*;
+use dioxus_daisyui::prelude::*;
 pub use dioxus_tailwindcss;
-pub use dioxus_tailwindcss::build;
 pub use dioxus_tailwindcss::prelude::*;
 
 #[derive(PartialEq, Clone)]
 pub struct Email {
+    pub account: String,
     pub message_id: String,
     pub subject: String,
     pub from: String,
@@ -18,19 +30,63 @@ pub struct Email {
     pub done: bool,
     pub pinned: bool,
     pub reminder_at: String,
+    // set once a snooze woke up and put it back in the inbox
+    pub snoozed_until: String,
+    pub seen: bool,
+    // gmail labels
+    pub labels: Vec<String>,
+    // search match with <mark> highlighting, already escaped
+    pub snippet: Option<String>,
 }
+impl Email {
+    pub fn target(&self) -> Target {
+        Target::Message {
+            account: self.account.clone(),
+            message_id: self.message_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Respond {
+    Reply,
+    ReplyAll,
+    Forward,
+}
+
 // share click state and make subject its own thing.
 #[inline_props]
-pub fn Email(cx: Scope, email: Email, start_expanded: bool) -> Element {
-    let database_config = use_shared_state::<DatabaseConfigState>(cx).unwrap();
-    let database_config = &database_config.read().0;
-    let expanded = use_state(&cx, || start_expanded.clone());
+pub fn Email(cx: Scope, email: Email, start_expanded: bool) -> Element<'a> {
+    let database_state = use_shared_state::<DatabaseConfigState>(cx).unwrap();
+    let database_config = &database_state.read().0;
+    let view_filter_state = use_shared_state::<ViewFilterState>(cx).unwrap();
+    let account_config = use_shared_state::<AccountConfigState>(cx).unwrap();
+    let accounts = use_shared_state::<AccountsState>(cx).unwrap();
+    let compose_state = use_shared_state::<ComposeState>(cx).unwrap();
+    let compose_error = use_shared_state::<ComposeErrorState>(cx).unwrap();
+    let expanded = use_state(cx, || *start_expanded);
+    let marked_seen = use_state(cx, || false);
+    let seen = email.seen || *marked_seen.get();
+    // an email that opens expanded is read once it shows
+    use_effect(cx, (), |_| {
+        let unread = *start_expanded && !email.seen;
+        let database_config = database_config.clone();
+        let accounts = accounts.read().0.clone();
+        let target = email.target();
+        let marked_seen = marked_seen.clone();
+        async move {
+            if unread {
+                actions::apply(&database_config, &accounts, &target, Action::Seen(true));
+                marked_seen.set(true);
+            }
+        }
+    });
     let from = parse_emails(&email.from)
         .first()
         .map(|f| f.1.clone().unwrap_or_else(|| f.0.clone()))
         .unwrap_or_else(|| email.from.clone());
 
-    let icon = from.chars().nth(0).unwrap_or_default();
+    let icon = from.chars().next().unwrap_or_default();
     let to = parse_emails(&email.to)
         .iter()
         .map(|f| f.1.clone().unwrap_or_else(|| f.0.clone()))
@@ -55,6 +111,29 @@ pub fn Email(cx: Scope, email: Email, start_expanded: bool) -> Element {
 
         let clean = if clean.len() < 10 { content.0 } else { clean };
 
+        // reply, reply all or forward, opens the compose window filled in. when that
+        // fails the window opens anyway to say why, unless a message is already open.
+        let can_send = account_config.read().0.can_send();
+        let respond = move |kind: Respond| {
+            let database_config = &database_state.read().0;
+            let account = &account_config.read().0;
+            let outgoing = match kind {
+                Respond::Reply => send::reply(database_config, account, &email.message_id, false),
+                Respond::ReplyAll => send::reply(database_config, account, &email.message_id, true),
+                Respond::Forward => send::forward(database_config, account, &email.message_id),
+            };
+            match outgoing {
+                Ok(outgoing) => {
+                    compose_state.write().0 = Some(outgoing);
+                    compose_error.write().0 = None;
+                }
+                Err(e) => {
+                    compose_state.write().0.get_or_insert_with(Default::default);
+                    compose_error.write().0 = Some(format!("{kind:?}: {e}"));
+                }
+            }
+        };
+
         cx.render(rsx! {
             div {
                 key: "{email.message_id}",
@@ -82,7 +161,13 @@ pub fn Email(cx: Scope, email: Email, start_expanded: bool) -> Element {
                     }
                     div{
                         class: "email-expaned-actions",
-                        "☰"
+                        ActionMenu {
+                            target: email.target(),
+                            pinned: email.pinned,
+                            done: email.done,
+                            snoozed: !email.reminder_at.is_empty(),
+                            seen: seen,
+                        }
                     }
                 }
 
@@ -103,6 +188,26 @@ pub fn Email(cx: Scope, email: Email, start_expanded: bool) -> Element {
                     div {
                         dangerous_inner_html: "{clean}"
                     }
+                    if can_send {
+                        rsx!(div {
+                            class: class!(flex gap_2 py_2),
+                            button {
+                                class: class!(btn btn_sm btn_outline),
+                                onclick: move |_| respond(Respond::Reply),
+                                "Reply"
+                            }
+                            button {
+                                class: class!(btn btn_sm btn_outline),
+                                onclick: move |_| respond(Respond::ReplyAll),
+                                "Reply all"
+                            }
+                            button {
+                                class: class!(btn btn_sm btn_outline),
+                                onclick: move |_| respond(Respond::Forward),
+                                "Forward"
+                            }
+                        })
+                    }
                 }
             }
         })
@@ -110,6 +215,8 @@ pub fn Email(cx: Scope, email: Email, start_expanded: bool) -> Element {
         let date = relative_date_format(&email.date_sent);
         let mut from = from;
         from.truncate(30);
+        let snippet = email.snippet.clone().unwrap_or_default();
+        let weight = if seen { "" } else { "font-bold" };
 
         cx.render(rsx! {
             div {
@@ -119,8 +226,20 @@ pub fn Email(cx: Scope, email: Email, start_expanded: bool) -> Element {
                     // class: class!() "email-expaned-header flex gap-20",
                     div{
                         class: class!(flex justify_between px_3 py_2 grow gap_5 hover(bg_slate_200)),
+                        class: "{weight}",
                         onclick:  move |_| {
                             expanded.set(true);
+                            // opening an email reads it
+                            if !seen {
+                                marked_seen.set(true);
+                                actions::apply(
+                                    &database_state.read().0,
+                                    &accounts.read().0,
+                                    &email.target(),
+                                    Action::Seen(true),
+                                );
+                                view_filter_state.write();
+                            }
                         },
                         div{
                             class: class!( h_5 w_7 font_bold text_gray_700 rounded_full bg_sky_600 flex items_center justify_center ),
@@ -133,7 +252,26 @@ pub fn Email(cx: Scope, email: Email, start_expanded: bool) -> Element {
 
                         div{
                             class: class!(w_full grow overflow_hidden text_ellipsis whitespace_nowrap),
+                            if email.pinned {
+                                rsx!(span { class: class!(mr_1), "📌" })
+                            }
+                            if !email.snoozed_until.is_empty() {
+                                rsx!(span {
+                                    class: class!(mr_2 px_1 text_xs text_amber_700 bg_amber_100 rounded),
+                                    "Snoozed until {snoozed_date_format(&email.snoozed_until)}"
+                                })
+                            }
+                            for name in email.labels.iter().filter(|l| *l != "Inbox") {
+                                span {
+                                    class: class!(mr_1 px_1 text_xs text_slate_700 bg_slate_200 rounded),
+                                    "{name}"
+                                }
+                            }
                             "{email.subject}"
+                            span {
+                                class: class!(ml_2 text_gray_500),
+                                dangerous_inner_html: "{snippet}"
+                            }
                         }
 
                         div{
@@ -141,9 +279,12 @@ pub fn Email(cx: Scope, email: Email, start_expanded: bool) -> Element {
                             "{date}"
                         }
 
-                        div {
-                            class: class!(shrink),
-                            "☰"
+                        ActionMenu {
+                            target: email.target(),
+                            pinned: email.pinned,
+                            done: email.done,
+                            snoozed: !email.reminder_at.is_empty(),
+                            seen: seen,
                         }
                     }
                 }
@@ -1,32 +1,46 @@
-use crate::components::utils::relative_date_format;
+use crate::components::{
+    actions::ActionMenu,
+    drafts::ThreadDrafts,
+    utils::{relative_date_format, snoozed_date_format},
+};
+use crate::database::Target;
 use crate::messages::parse_emails;
+use crate::models::Draft;
 use dioxus::prelude::*;
 pub use dioxus_tailwindcss;
-pub use dioxus_tailwindcss::build;
 pub use dioxus_tailwindcss::prelude::*;
 
 use super::email::Email;
 #[derive(PartialEq, Clone)]
 pub struct EmailThread {
+    pub thread_key: String,
     pub subject: String,
     pub children: Vec<Email>,
+    pub drafts: Vec<Draft>,
 }
 #[inline_props]
-pub fn EmailThread(cx: Scope, thread: EmailThread) -> Element {
+pub fn EmailThread(cx: Scope, thread: EmailThread) -> Element<'a> {
     cx.render(rsx! {
         if thread.children.len()>1{
-            let expanded = use_state(&cx, || false);
-            let expanded_list = use_state(&cx, || false);
+            let expanded = use_state(cx, || false);
+            let expanded_list = use_state(cx, || false);
             let raw_from = thread.children.first().as_ref().map(|e| e.from.clone()).unwrap_or_default();
             let from = parse_emails(&raw_from)
                 .first()
                 .map(|f| f.1.clone().unwrap_or_else(|| f.0.clone()))
                 .unwrap_or_else(|| raw_from.clone());
 
-            let icon = from.chars().nth(0).unwrap_or_default();
+            let icon = from.chars().next().unwrap_or_default();
 
             if !*expanded.get(){
                 let date = relative_date_format(&thread.children.first().unwrap().date_sent);
+                let weight = if thread.children.iter().all(|e| e.seen) { "" } else { "font-bold" };
+                let snoozed_until = thread
+                    .children
+                    .iter()
+                    .map(|e| e.snoozed_until.clone())
+                    .max()
+                    .unwrap_or_default();
                 let mut from = from;
                 from.truncate(30);
 
@@ -38,6 +52,7 @@ pub fn EmailThread(cx: Scope, thread: EmailThread) -> Element {
                             class: "parent_hover",
                             div{
                                 class: class!(flex justify_between px_3 py_2 grow gap_5 hover(bg_slate_200)),
+                                class: "{weight}",
                                 onclick:  move |_| {
                                     expanded.set(true);
                                 },
@@ -53,6 +68,12 @@ pub fn EmailThread(cx: Scope, thread: EmailThread) -> Element {
                                 div{
                                     class: "email-expaned-subject",
                                     class: class!(w_full grow overflow_hidden text_ellipsis whitespace_nowrap),
+                                    if !snoozed_until.is_empty() {
+                                        rsx!(span {
+                                            class: class!(mr_2 px_1 text_xs text_amber_700 bg_amber_100 rounded),
+                                            "Snoozed until {snoozed_date_format(&snoozed_until)}"
+                                        })
+                                    }
                                     "{thread.children.first().unwrap().subject}"
                                 }
 
@@ -61,9 +82,12 @@ pub fn EmailThread(cx: Scope, thread: EmailThread) -> Element {
                                     "{date}"
                                 }
 
-                                div {
-                                    class: class!(shrink),
-                                    "☰"
+                                ActionMenu {
+                                    target: Target::Thread(thread.thread_key.clone()),
+                                    pinned: thread.children.iter().any(|e| e.pinned),
+                                    done: thread.children.iter().all(|e| e.done),
+                                    snoozed: thread.children.iter().any(|e| !e.reminder_at.is_empty()),
+                                    seen: thread.children.iter().all(|e| e.seen),
                                 }
                             }
                         }
@@ -96,7 +120,7 @@ pub fn EmailThread(cx: Scope, thread: EmailThread) -> Element {
                         rsx!(div {
                             class: "email-expaned-list",
                                 class: class!(ml_2 w_full border_t border_t_gray_200 bg_white),
-                                for (i,email) in thread.children.to_owned().into_iter().rev().enumerate(){
+                                for (i,email) in thread.children.iter().cloned().rev().enumerate(){
 
                                     div{
                                         class: "email-list",
@@ -135,6 +159,7 @@ pub fn EmailThread(cx: Scope, thread: EmailThread) -> Element {
                                     }
                             })
                         }
+                        ThreadDrafts { drafts: thread.drafts.clone() }
                     }
 
                 )
@@ -143,6 +168,7 @@ pub fn EmailThread(cx: Scope, thread: EmailThread) -> Element {
             rsx!(div{
                 class: "email-list",
                 Email{ email: thread.children.first().unwrap().to_owned(), start_expanded: false}
+                ThreadDrafts { drafts: thread.drafts.clone() }
             })
         }
     })
@@ -1,3 +1,7 @@
+pub mod actions;
+pub mod compose;
+pub mod drafts;
 pub mod email;
 pub mod email_thread;
+pub mod outbox;
 pub mod utils;
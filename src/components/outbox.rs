@@ -0,0 +1,204 @@
+use crate::{
+    app::{AccountConfigState, DatabaseConfigState},
+    components::{compose::ComposeState, utils::snoozed_date_format},
+    database,
+    models::OutboxEntry,
+    outbox,
+};
+use dioxus::prelude::*;
+pub use dioxus_tailwindcss;
+pub use dioxus_tailwindcss::prelude::*;
+use std::time::Duration;
+
+// the outbox id of a message that was just sent and can still be taken back
+pub struct UndoState(pub Option<i32>);
+
+// back into the compose window, unless it went out already
+fn take_back(
+    database_config: &UseSharedState<DatabaseConfigState>,
+    compose_state: &UseSharedState<ComposeState>,
+    id: i32,
+) -> Result<(), String> {
+    if compose_state.read().0.is_some() {
+        return Err("close the open message first".into());
+    }
+    let outgoing = outbox::cancel(&database_config.read().0, id)?;
+    compose_state.write().0 = Some(outgoing.unwrap_or_default());
+    Ok(())
+}
+
+pub fn UndoSend(cx: Scope) -> Element {
+    let undo_state = use_shared_state::<UndoState>(cx).unwrap();
+    let compose_state = use_shared_state::<ComposeState>(cx).unwrap();
+    let database_config = use_shared_state::<DatabaseConfigState>(cx).unwrap();
+    let account_config = use_shared_state::<AccountConfigState>(cx).unwrap();
+    let error = use_state(cx, || None::<String>);
+    // the last id whose undo window ran out
+    let expired = use_state(cx, || None::<i32>);
+    let id = undo_state.read().0;
+    let undo_secs = account_config.read().0.outbox.undo_secs;
+    use_future(cx, (&id,), |(id,)| {
+        let expired = expired.clone();
+        async move {
+            if id.is_some() {
+                tokio::time::sleep(Duration::from_secs(undo_secs)).await;
+                expired.set(id);
+            }
+        }
+    });
+    let id = id.filter(|id| Some(*id) != *expired.get());
+    let message = match (id, error.get()) {
+        (_, Some(e)) => e.clone(),
+        (Some(_), None) => "Sending…".to_owned(),
+        (None, None) => return None,
+    };
+
+    cx.render(rsx! {
+        div {
+            class: class!(fixed bottom_4 left_4 z_30 flex items_center gap_4 px_4 py_2 text_white bg_slate_700 rounded shadow_lg),
+            "{message}"
+            if let Some(id) = id {
+                rsx!(button {
+                    class: class!(font_bold text_sky_300),
+                    onclick: move |_| {
+                        undo_state.write().0 = None;
+                        match take_back(&database_config, &compose_state, id) {
+                            Ok(()) => error.set(None),
+                            Err(e) => error.set(Some(format!("Couldn't undo: {e}"))),
+                        }
+                    },
+                    "Undo"
+                })
+            }
+            if error.get().is_some() {
+                rsx!(button {
+                    class: class!(px_1),
+                    onclick: move |_| error.set(None),
+                    "✕"
+                })
+            }
+        }
+    })
+}
+
+#[derive(Props, PartialEq)]
+pub struct OutboxListProps {
+    // changes when a delivery in the background emptied some of it, only there to redraw
+    revision: u64,
+}
+
+// queued, scheduled and failed messages of the account
+pub fn OutboxList(cx: Scope<OutboxListProps>) -> Element {
+    let account_config = use_shared_state::<AccountConfigState>(cx).unwrap();
+    let database_config = use_shared_state::<DatabaseConfigState>(cx).unwrap();
+    // redrawn when something is queued or taken back
+    use_shared_state::<ComposeState>(cx).unwrap();
+    use_shared_state::<UndoState>(cx).unwrap();
+    let entries = database::list_outbox(&database_config.read().0, &account_config.read().0.name);
+
+    let rows = match entries {
+        Ok(entries) if entries.is_empty() => cx.render(rsx!(div {
+            class: class!(px_3 py_2 text_sm text_gray_500),
+            "Nothing waiting to be sent"
+        })),
+        Ok(entries) => cx.render(rsx!(for entry in entries.into_iter() {
+            OutboxRow { entry: entry }
+        })),
+        Err(e) => cx.render(rsx!(div {
+            class: class!(mx_4 px_3 py_2 text_red_700 bg_red_100 rounded),
+            "{e}"
+        })),
+    };
+
+    cx.render(rsx! {
+        div {
+            class: class!(mb_5),
+            div {
+                class: class!(uppercase px_3 py_2 w_full text_sm text_sky_600 border_b border_b_slate_200),
+                "Outbox"
+            }
+            rows
+        }
+    })
+}
+
+#[inline_props]
+fn OutboxRow(cx: Scope, entry: OutboxEntry) -> Element<'a> {
+    let compose_state = use_shared_state::<ComposeState>(cx).unwrap();
+    let database_config = use_shared_state::<DatabaseConfigState>(cx).unwrap();
+    let error = use_state(cx, || None::<String>);
+    let to = entry.message_to.clone().unwrap_or_default();
+    let subject = entry
+        .subject
+        .clone()
+        .unwrap_or_else(|| "(no subject)".into());
+    let failed = entry.failed_at.is_some();
+    let status = if failed {
+        format!("Not sent: {}", entry.last_error.clone().unwrap_or_default())
+    } else if let Some(e) = &entry.last_error {
+        format!(
+            "Retrying {} after: {}",
+            snoozed_date_format(&entry.send_at),
+            e
+        )
+    } else {
+        format!("Sending {}", snoozed_date_format(&entry.send_at))
+    };
+    let status_class = if failed {
+        class!(text_red_600)
+    } else {
+        class!(text_gray_500)
+    };
+    let id = entry.id.unwrap_or_default();
+
+    cx.render(rsx! {
+        div {
+            class: class!(flex flex_col px_3 py_2 gap_1 w_full border_t border_t_gray_200),
+            div {
+                class: class!(flex justify_between gap_5),
+                div {
+                    class: class!(w_3__12 overflow_hidden text_ellipsis whitespace_nowrap),
+                    "{to}"
+                }
+                div {
+                    class: class!(w_full grow overflow_hidden text_ellipsis whitespace_nowrap),
+                    "{subject}"
+                }
+                if failed {
+                    rsx!(button {
+                        class: class!(text_sm text_sky_600 hover(underline)),
+                        onclick: move |_| {
+                            match outbox::retry(&database_config.read().0, id) {
+                                Ok(()) => {
+                                    error.set(None);
+                                    compose_state.write();
+                                }
+                                Err(e) => error.set(Some(e)),
+                            }
+                        },
+                        "Retry"
+                    })
+                }
+                button {
+                    class: class!(text_sm text_sky_600 hover(underline)),
+                    onclick: move |_| match take_back(&database_config, &compose_state, id) {
+                        Ok(()) => error.set(None),
+                        Err(e) => error.set(Some(e)),
+                    },
+                    "Edit"
+                }
+            }
+            div {
+                class: "{status_class}",
+                class: class!(text_sm),
+                "{status}"
+            }
+            if let Some(e) = error.get() {
+                rsx!(div {
+                    class: class!(text_sm text_red_700),
+                    "{e}"
+                })
+            }
+        }
+    })
+}
@@ -24,7 +24,7 @@ pub fn date_group(sent_at: &str) -> String {
 pub fn date_format(sent_at: &str) -> String {
     let sent: Option<DateTime<Local>> = DateTime::parse_from_rfc2822(sent_at).ok().map(Into::into);
     sent.map(|date| date.format("%a, %b %d, %Y, %I:%M %p").to_string())
-        .unwrap_or_else(|| "".to_string())
+        .unwrap_or_default()
 }
 pub fn relative_date_format(sent_at: &str) -> String {
     let sent: Option<DateTime<Local>> = DateTime::parse_from_rfc2822(sent_at).ok().map(Into::into);
@@ -38,5 +38,12 @@ pub fn relative_date_format(sent_at: &str) -> String {
             date.format("%m/%d/%Y").to_string()
         }
     })
-    .unwrap_or_else(|| "".to_string())
+    .unwrap_or_default()
+}
+
+// stored timestamps are rfc3339 utc, not the rfc2822 from mail headers
+pub fn snoozed_date_format(at: &str) -> String {
+    let at: Option<DateTime<Local>> = DateTime::parse_from_rfc3339(at).ok().map(Into::into);
+    at.map(|date| date.format("%b %d, %I:%M %p").to_string())
+        .unwrap_or_default()
 }
@@ -1,9 +1,13 @@
 use himalaya_lib::{BackendConfig, EmailSender, ImapConfig};
 use serde_derive::Deserialize;
+use std::collections::HashMap;
 use std::fs;
+use std::process::Command;
 use std::{path::PathBuf, str::FromStr};
 use toml::{self, Table};
 
+use crate::credentials;
+
 pub fn default_account_path() -> PathBuf {
     dirs::home_dir()
         .unwrap_or_else(|| PathBuf::from_str(".").expect("no home dir path buff issues"))
@@ -20,6 +24,22 @@ pub fn default_config_path() -> PathBuf {
         .join("config.toml")
 }
 
+pub fn default_token_dir() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from_str(".").expect("no home dir path buff issues"))
+        .join(".config")
+        .join("bes")
+        .join("tokens")
+}
+
+pub fn default_credentials_path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from_str(".").expect("no home dir path buff issues"))
+        .join(".config")
+        .join("bes")
+        .join("credentials.age")
+}
+
 pub fn default_database_path() -> PathBuf {
     dirs::home_dir()
         .unwrap_or_else(|| PathBuf::from_str(".").expect("no home dir path buff issues"))
@@ -34,6 +54,21 @@ pub struct AccountConfig {
     pub default: bool,
     pub account: Account,
     pub imap: Imap,
+    #[serde(default)]
+    pub write_back: WriteBack,
+    #[serde(default)]
+    pub sync: SyncConfig,
+    // sending is off without it
+    pub smtp: Option<Smtp>,
+    #[serde(default)]
+    pub drafts: DraftsConfig,
+    #[serde(default)]
+    pub outbox: OutboxConfig,
+    // sign in with XOAUTH2 instead of a password
+    pub oauth2: Option<OAuth2Config>,
+    // the file this was read from
+    #[serde(skip)]
+    pub file: PathBuf,
 }
 impl AccountConfig {
     // gmail focused
@@ -51,22 +86,9 @@ impl AccountConfig {
             starttls: Some(false),
             //insecure: Some(true),
             login: self.imap.login.clone(),
-            passwd_cmd: self
-                .imap
-                .passwd_cmd
-                .as_ref()
-                .map(|s| s.clone())
-                .or_else(|| {
-                    Some(format!(
-                        "echo \"{}\"",
-                        self.imap
-                            .passwd
-                            .as_ref()
-                            .map(|s| s.clone())
-                            .unwrap_or_default()
-                    ))
-                })
-                .unwrap(),
+            // himalaya only takes a shell command, it calls back into this binary
+            // so the password is resolved here and never ends up in a command line
+            passwd_cmd: self.passwd_callback(),
             ssl: Some(true),
             // ssl: Some(false),
             ..Default::default()
@@ -74,8 +96,150 @@ impl AccountConfig {
         let backend_config = BackendConfig::Imap(imap_config);
         (account_config, backend_config)
     }
+
+    pub fn imap_host(&self) -> (String, u16) {
+        (self.imap.host.clone(), self.imap.port as u16)
+    }
+
+    pub fn imap_login(&self) -> String {
+        self.imap.login.clone()
+    }
+
+    pub fn imap_password(&self) -> Result<String, String> {
+        self.imap
+            .password
+            .credential()
+            .map_err(|e| format!("imap password for {}: {}", self.name, e))?
+            .ok_or_else(|| {
+                format!(
+                    "no imap password for {}, set passwd_cmd, passwd_env, passwd_file or passwd in [imap]",
+                    self.name
+                )
+            })?
+            .resolve()
+            .map_err(|e| format!("imap password for {}: {}", self.name, e))
+    }
+
+    pub fn email(&self) -> String {
+        self.account.email.clone()
+    }
+
+    pub fn display_name(&self) -> Option<String> {
+        self.account.display_name.clone()
+    }
+
+    pub fn sender(&self) -> Result<Sender, String> {
+        let smtp = || {
+            self.smtp
+                .as_ref()
+                .map(Sender::Smtp)
+                .ok_or_else(|| format!("{} has no [smtp] config", self.name))
+        };
+        match self.account.sender {
+            SenderKind::Smtp => smtp(),
+            SenderKind::None if self.smtp.is_some() => smtp(),
+            SenderKind::None => Err(format!(
+                "sending is off for {}, add [smtp] or set sender",
+                self.name
+            )),
+            SenderKind::Sendmail => {
+                let args = match &self.account.sendmail_cmd {
+                    Some(cmd) => shell_words::split(cmd)
+                        .map_err(|e| format!("sendmail_cmd for {}: {}", self.name, e))?,
+                    None => default_sendmail_cmd(),
+                };
+                if args.is_empty() {
+                    return Err(format!("sendmail_cmd for {} is empty", self.name));
+                }
+                Ok(Sender::Command(args))
+            }
+        }
+    }
+
+    pub fn can_send(&self) -> bool {
+        self.sender().is_ok()
+    }
+
+    // where the local copy of sent mail goes, if the config says
+    pub fn sent_folder(&self) -> Option<String> {
+        self.smtp.as_ref().and_then(|smtp| smtp.sent_folder.clone())
+    }
+
+    pub fn smtp_login(&self, smtp: &Smtp) -> String {
+        smtp.login
+            .clone()
+            .unwrap_or_else(|| self.imap.login.clone())
+    }
+
+    // smtp without its own passwd* keys signs in like imap
+    pub fn smtp_password(&self, smtp: &Smtp) -> Result<String, String> {
+        let credential = smtp
+            .password
+            .credential()
+            .map_err(|e| format!("smtp password for {}: {}", self.name, e))?;
+        match credential {
+            Some(credential) => credential
+                .resolve()
+                .map_err(|e| format!("smtp password for {}: {}", self.name, e)),
+            None => self.imap_password(),
+        }
+    }
+
+    // `bes password <account file>`, quoted for the shell himalaya runs it in
+    fn passwd_callback(&self) -> String {
+        let exe = std::env::current_exe()
+            .map(|exe| exe.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "bes".into());
+        let file = self.file.to_string_lossy();
+        shell_words::join([exe.as_str(), "password", &file])
+    }
+}
+
+// where a secret comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Credential {
+    // plain text in the account file
+    Inline(String),
+    // program and arguments, run without a shell, the first line it prints is used
+    Command(Vec<String>),
+    // name of an environment variable
+    Env(String),
+    // entry in the encrypted credentials file
+    Encrypted(String),
 }
 
+impl Credential {
+    pub fn resolve(&self) -> Result<String, String> {
+        match self {
+            Credential::Inline(secret) => Ok(secret.clone()),
+            Credential::Command(args) => {
+                let (program, args) = args.split_first().ok_or("the command is empty")?;
+                let output = Command::new(program)
+                    .args(args)
+                    .output()
+                    .map_err(|e| format!("running {program}: {e}"))?;
+                if !output.status.success() {
+                    return Err(format!(
+                        "{} {}: {}",
+                        program,
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .next()
+                    .filter(|line| !line.is_empty())
+                    .map(|line| line.to_owned())
+                    .ok_or_else(|| format!("{program} printed nothing"))
+            }
+            Credential::Env(name) => {
+                std::env::var(name).map_err(|e| format!("environment variable {name}: {e}"))
+            }
+            Credential::Encrypted(name) => credentials::get(&default_credentials_path(), name),
+        }
+    }
+}
 #[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct DatabaseConfig {
     pub path: String,
@@ -86,8 +250,11 @@ pub struct DatabaseConfig {
 pub struct Account {
     email: String,
     display_name: Option<String>,
-    #[serde(default = "default_sender")]
-    sender: String,
+    // how mail goes out, "smtp" or "sendmail". unset uses [smtp] when it's there.
+    #[serde(default)]
+    sender: SenderKind,
+    // program and arguments for "sendmail", "--" and the recipients are added after them
+    sendmail_cmd: Option<String>,
     name: Option<String>,
     signature_delim: Option<String>,
     signature: Option<String>,
@@ -115,10 +282,201 @@ pub struct Imap {
     host: String,
     starttls: Option<bool>, //Some(false),
     login: String,
+    #[serde(flatten)]
+    password: Password,
+    ssl: Option<bool>, // Some(true),
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SenderKind {
+    // older account files say "None"
+    #[default]
+    #[serde(alias = "None")]
+    None,
+    Smtp,
+    Sendmail,
+}
+
+// what delivers an account's mail
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sender<'a> {
+    Smtp(&'a Smtp),
+    // program and arguments, run without a shell, the message goes to its stdin
+    Command(Vec<String>),
+}
+
+// the passwd* keys of [imap] and [smtp]
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct Password {
+    // split like a shell would but run without one, so no $, pipes or globs
     passwd_cmd: Option<String>,
+    passwd_env: Option<String>,
+    // entry name in the encrypted credentials file
+    passwd_file: Option<String>,
     passwd: Option<String>,
-    ssl: Option<bool>, // Some(true),
 }
+impl Password {
+    // first one set wins: command, environment, credentials file, plain text
+    pub fn credential(&self) -> Result<Option<Credential>, String> {
+        if let Some(cmd) = &self.passwd_cmd {
+            let args = shell_words::split(cmd).map_err(|e| format!("passwd_cmd \"{cmd}\": {e}"))?;
+            return Ok(Some(Credential::Command(args)));
+        }
+        Ok(self
+            .passwd_env
+            .clone()
+            .map(Credential::Env)
+            .or_else(|| self.passwd_file.clone().map(Credential::Encrypted))
+            .or_else(|| self.passwd.clone().map(Credential::Inline)))
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Smtp {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: TlsMode,
+    // the imap login when unset
+    pub login: Option<String>,
+    #[serde(flatten)]
+    pub password: Password,
+    // sent mail is stored under this folder locally, the one the server marks as sent
+    // when unset
+    pub sent_folder: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct DraftsConfig {
+    // also keep drafts in the server's drafts folder so other devices see them
+    #[serde(default)]
+    pub upload: bool,
+    #[serde(default = "default_drafts_folder")]
+    pub folder: String,
+}
+impl Default for DraftsConfig {
+    fn default() -> Self {
+        DraftsConfig {
+            upload: false,
+            folder: default_drafts_folder(),
+        }
+    }
+}
+fn default_drafts_folder() -> String {
+    "[Gmail]/Drafts".into()
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct OutboxConfig {
+    // how long Send can still be undone, 0 sends right away
+    #[serde(default = "default_undo_secs")]
+    pub undo_secs: u64,
+}
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        OutboxConfig {
+            undo_secs: default_undo_secs(),
+        }
+    }
+}
+fn default_undo_secs() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    // tls from the start, usually port 465
+    #[default]
+    Tls,
+    // plain connection upgraded with STARTTLS, usually port 587
+    Starttls,
+    // unencrypted, for local relays only
+    None,
+}
+
+fn default_smtp_port() -> u16 {
+    465
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct OAuth2Config {
+    // "gmail" or "outlook" fill in the urls and scopes below
+    pub provider: Option<String>,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub auth_url: Option<String>,
+    pub token_url: Option<String>,
+    pub device_url: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    // defaults to tokens/<account name>.json next to config.toml
+    pub token_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct SyncConfig {
+    // "*" syncs every folder the server lists. unset is the inbox and the folders the
+    // server marks as sent and all mail
+    pub folders: Option<Vec<String>>,
+    // wait for new mail with IDLE while the app is open, polls when off or unsupported
+    #[serde(default = "default_true")]
+    pub idle: bool,
+    // seconds between polls
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+}
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            folders: None,
+            idle: true,
+            poll_interval: default_poll_interval(),
+        }
+    }
+}
+fn default_poll_interval() -> u64 {
+    300
+}
+
+// how local pin/done/snooze/read state is mirrored on the server
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct WriteBack {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    // done moves the message here, gmail archives by leaving the inbox
+    #[serde(default = "default_archive_folder")]
+    pub archive_folder: String,
+    // set this flag for done instead of moving the message
+    pub done_flag: Option<String>,
+    // snoozed messages are moved here while they sleep, left alone when unset
+    pub snooze_folder: Option<String>,
+    #[serde(default = "default_inbox")]
+    pub inbox_folder: String,
+}
+impl Default for WriteBack {
+    fn default() -> Self {
+        WriteBack {
+            enabled: true,
+            archive_folder: default_archive_folder(),
+            done_flag: None,
+            snooze_folder: None,
+            inbox_folder: default_inbox(),
+        }
+    }
+}
+fn default_true() -> bool {
+    true
+}
+fn default_archive_folder() -> String {
+    "[Gmail]/All Mail".into()
+}
+fn default_inbox() -> String {
+    "INBOX".into()
+}
+
 fn default_port() -> i32 {
     993
 }
@@ -126,16 +484,15 @@ fn default_port() -> i32 {
 fn default_host() -> String {
     "imap.gmail.com".into()
 }
-fn default_sender() -> String {
-    //EmailSender::None
-    "None".into()
+fn default_sendmail_cmd() -> Vec<String> {
+    vec!["/usr/sbin/sendmail".into(), "-i".into()]
 }
 
 pub fn get_database(config_file: &Option<PathBuf>) -> DatabaseConfig {
     let config_file = config_file
         .as_ref()
         .cloned()
-        .unwrap_or_else(|| default_config_path());
+        .unwrap_or_else(default_config_path);
     let contents = match fs::read_to_string(config_file) {
         Ok(c) => c,
         Err(_) => {
@@ -163,8 +520,74 @@ pub fn get_database(config_file: &Option<PathBuf>) -> DatabaseConfig {
     }
 }
 
+// [daemon] in config.toml. schedules are either an interval like "15m", "2h", "30s"
+// or a cron expression with seconds, "0 */10 * * * *".
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct DaemonConfig {
+    #[serde(default = "default_schedule")]
+    pub schedule: String,
+    // account name -> schedule, overrides `schedule`
+    #[serde(default)]
+    pub schedules: HashMap<String, String>,
+    // seconds between snooze wake ups and write backs
+    #[serde(default = "default_wake_interval")]
+    pub wake_interval: u64,
+    // accounts syncing at the same time
+    #[serde(default = "default_jobs")]
+    pub jobs: usize,
+    // defaults to the database path with .pid added
+    pub pid_file: Option<PathBuf>,
+}
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            schedule: default_schedule(),
+            schedules: HashMap::new(),
+            wake_interval: default_wake_interval(),
+            jobs: default_jobs(),
+            pid_file: None,
+        }
+    }
+}
+impl DaemonConfig {
+    pub fn schedule_for(&self, account: &str) -> &str {
+        self.schedules
+            .get(account)
+            .map(|s| s.as_str())
+            .unwrap_or(&self.schedule)
+    }
+}
+fn default_schedule() -> String {
+    "15m".into()
+}
+fn default_wake_interval() -> u64 {
+    60
+}
+pub fn default_jobs() -> usize {
+    4
+}
+
+pub fn get_daemon(config_file: &Option<PathBuf>) -> Result<DaemonConfig, String> {
+    let config_file = config_file
+        .as_ref()
+        .cloned()
+        .unwrap_or_else(default_config_path);
+    let contents = match fs::read_to_string(config_file) {
+        Ok(c) => c,
+        Err(_) => return Ok(DaemonConfig::default()),
+    };
+    let mut data: Table = toml::from_str(&contents).map_err(|e| e.to_string())?;
+    let daemon: DaemonConfig = match data.remove("daemon") {
+        Some(daemon) => daemon.try_into().map_err(|e| format!("[daemon]: {e}"))?,
+        None => DaemonConfig::default(),
+    };
+    if daemon.wake_interval == 0 {
+        return Err("[daemon]: wake_interval has to be longer than 0".to_string());
+    }
+    Ok(daemon)
+}
+
 pub fn get_accounts(config_file: PathBuf) -> Vec<AccountConfig> {
-    dbg!(&config_file);
     let contents = match fs::read_to_string(config_file) {
         Ok(c) => c,
         Err(_) => {
@@ -176,14 +599,13 @@ pub fn get_accounts(config_file: PathBuf) -> Vec<AccountConfig> {
         Err(_) => None,
     };
     let data = data.and_then(|mut d| d.remove("accounts"));
-    data.map(|d| {
+    data.and_then(|d| {
         d.as_array().map(|d| {
             d.iter()
-                .flat_map(|s| s.as_str().map(|str| get_account_config(str)).flatten())
+                .flat_map(|s| s.as_str().and_then(get_account_config))
                 .collect::<Vec<_>>()
         })
     })
-    .flatten()
     .unwrap_or_default()
 }
 
@@ -198,5 +620,74 @@ pub fn get_account_config(account_file: &str) -> Option<AccountConfig> {
         Ok(d) => Some(d),
         Err(_) => None,
     };
-    data
+    data.map(|account| AccountConfig {
+        file: PathBuf::from(account_file),
+        ..account
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credential(keys: &str) -> Result<Option<Credential>, String> {
+        toml::from_str::<Password>(keys).unwrap().credential()
+    }
+
+    fn resolve(keys: &str) -> Result<String, String> {
+        credential(keys)?.expect("a credential").resolve()
+    }
+
+    #[test]
+    fn picks_the_first_provider_set() {
+        assert_eq!(credential(""), Ok(None));
+        assert_eq!(
+            credential("passwd = \"plain\"\npasswd_file = \"work\""),
+            Ok(Some(Credential::Encrypted("work".to_string())))
+        );
+        assert_eq!(
+            credential("passwd = \"plain\"\npasswd_cmd = \"pass show 'mail/my work'\""),
+            Ok(Some(Credential::Command(vec![
+                "pass".to_string(),
+                "show".to_string(),
+                "mail/my work".to_string()
+            ])))
+        );
+    }
+
+    #[test]
+    fn resolves_plain_and_env() {
+        assert_eq!(resolve("passwd = \"plain\""), Ok("plain".to_string()));
+        std::env::set_var("BES_TEST_PASSWD", "from env");
+        assert_eq!(
+            resolve("passwd_env = \"BES_TEST_PASSWD\""),
+            Ok("from env".to_string())
+        );
+        assert!(resolve("passwd_env = \"BES_TEST_UNSET_PASSWD\"")
+            .unwrap_err()
+            .contains("BES_TEST_UNSET_PASSWD"));
+    }
+
+    #[test]
+    fn resolves_commands() {
+        assert_eq!(
+            resolve("passwd_cmd = \"printf 'first line\\\\nsecond'\""),
+            Ok("first line".to_string())
+        );
+        // a command that can't be split names itself
+        let error = credential("passwd_cmd = \"pass show 'mail\"").unwrap_err();
+        assert!(error.contains("pass show 'mail"), "{error}");
+    }
+
+    #[test]
+    fn rejects_missing_and_empty_output() {
+        let error = resolve("passwd_cmd = \"bes-no-such-program\"").unwrap_err();
+        assert!(error.contains("running bes-no-such-program"), "{error}");
+        let error = resolve("passwd_cmd = \"sh -c 'echo denied >&2; exit 3'\"").unwrap_err();
+        assert!(error.contains("denied"), "{error}");
+        for cmd in ["true", "echo"] {
+            let error = resolve(&format!("passwd_cmd = \"{cmd}\"")).unwrap_err();
+            assert!(error.contains("printed nothing"), "{cmd}: {error}");
+        }
+    }
 }
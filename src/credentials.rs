@@ -0,0 +1,100 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use age::secrecy::SecretString;
+use toml::{Table, Value};
+
+// secrets live in one age encrypted toml file, `name = "secret"`. the passphrase
+// comes from this variable, or is asked for on the terminal
+pub const PASSPHRASE_ENV: &str = "BES_CREDENTIALS_PASSPHRASE";
+
+fn passphrase() -> Result<String, String> {
+    match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => rpassword::prompt_password("credentials passphrase: ").map_err(|e| {
+            format!("no terminal to ask for the passphrase ({e}), set {PASSPHRASE_ENV}")
+        }),
+    }
+}
+
+// a missing file has no entries yet
+fn read_all(path: &Path, passphrase: &str) -> Result<Table, String> {
+    let encrypted = match fs::read(path) {
+        Ok(encrypted) => encrypted,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Table::new()),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let decryptor =
+        age::Decryptor::new(&encrypted[..]).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if !decryptor.is_scrypt() {
+        return Err(format!("{} is not passphrase encrypted", path.display()));
+    }
+    let identity = age::scrypt::Identity::new(SecretString::from(passphrase.to_owned()));
+    let mut contents = String::new();
+    decryptor
+        .decrypt(std::iter::once(&identity as &dyn age::Identity))
+        .map_err(|e| format!("{}: {}", path.display(), e))?
+        .read_to_string(&mut contents)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn get(path: &Path, name: &str) -> Result<String, String> {
+    let entries = read_all(path, &passphrase()?)?;
+    match entries.get(name) {
+        Some(Value::String(secret)) => Ok(secret.clone()),
+        _ => Err(format!("no \"{}\" in {}", name, path.display())),
+    }
+}
+
+// adds or replaces one entry
+pub fn set(path: &PathBuf, name: &str, secret: &str) -> Result<(), String> {
+    let passphrase = passphrase()?;
+    let mut entries = read_all(path, &passphrase)?;
+    entries.insert(name.to_owned(), Value::String(secret.to_owned()));
+    let contents = toml::to_string(&entries).map_err(|e| e.to_string())?;
+
+    let mut encrypted = vec![];
+    let mut writer = age::Encryptor::with_user_passphrase(SecretString::from(passphrase))
+        .wrap_output(&mut encrypted)
+        .map_err(|e| e.to_string())?;
+    writer
+        .write_all(contents.as_bytes())
+        .and_then(|_| writer.finish())
+        .map_err(|e| e.to_string())?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    fs::write(path, encrypted).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_the_encrypted_file() {
+        let path =
+            std::env::temp_dir().join(format!("bes-test-{}-credentials.age", std::process::id()));
+        let _ = fs::remove_file(&path);
+        std::env::set_var(PASSPHRASE_ENV, "correct horse");
+
+        // nothing there yet
+        assert!(get(&path, "work").unwrap_err().contains("no \"work\""));
+        set(&path, "work", "s3cret").unwrap();
+        set(&path, "home", "other").unwrap();
+        assert_eq!(get(&path, "work"), Ok("s3cret".to_string()));
+        assert_eq!(get(&path, "home"), Ok("other".to_string()));
+        // the file is not readable as it is
+        let raw = fs::read(&path).unwrap();
+        assert!(!raw.windows(6).any(|w| w == b"s3cret"));
+        // nor with another passphrase
+        assert!(read_all(&path, "wrong horse").is_err());
+
+        fs::remove_file(&path).ok();
+    }
+}
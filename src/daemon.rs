@@ -0,0 +1,330 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use tokio::{
+    sync::{watch, Semaphore},
+    task::JoinHandle,
+};
+
+use crate::{
+    config::{self, AccountConfig, DatabaseConfig},
+    log::log,
+    outbox, snooze, sync, write_back,
+};
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(cron::Schedule),
+}
+
+impl Schedule {
+    // "90s", "15m", "2h", "1d" or a cron expression
+    pub fn parse(value: &str) -> Result<Schedule, String> {
+        let value = value.trim();
+        let interval = value
+            .char_indices()
+            .last()
+            .and_then(|(i, unit)| Some((value[..i].parse::<u64>().ok()?, unit)))
+            .and_then(|(n, unit)| match unit {
+                's' => Some((n, 1)),
+                'm' => Some((n, 60)),
+                'h' => Some((n, 60 * 60)),
+                'd' => Some((n, 60 * 60 * 24)),
+                _ => None,
+            });
+        match interval {
+            Some((0, _)) => Err(format!("schedule \"{value}\" has to be longer than 0")),
+            // chrono durations top out well below u64 seconds
+            Some((n, unit)) => n
+                .checked_mul(unit)
+                .map(Duration::from_secs)
+                .filter(|interval| chrono::Duration::from_std(*interval).is_ok())
+                .map(Schedule::Every)
+                .ok_or_else(|| format!("schedule \"{value}\" is too long")),
+            None => cron::Schedule::from_str(value)
+                .map(Schedule::Cron)
+                .map_err(|e| format!("schedule \"{value}\": {e}")),
+        }
+    }
+
+    fn next_after(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Every(interval) => chrono::Duration::from_std(*interval)
+                .ok()
+                .and_then(|interval| now.checked_add_signed(interval)),
+            Schedule::Cron(schedule) => schedule.after(&now).next(),
+        }
+    }
+}
+
+// held for as long as the daemon runs, the lock goes away with the process
+struct PidFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl PidFile {
+    fn acquire(path: PathBuf) -> Result<PidFile, String> {
+        // emptied once the lock is ours, not before
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| format!("pid file {}: {}", path.display(), e))?;
+        file.try_lock_exclusive().map_err(|_| {
+            format!(
+                "another daemon is using this database, see {}",
+                path.display()
+            )
+        })?;
+        file.set_len(0).map_err(|e| e.to_string())?;
+        writeln!(file, "{}", std::process::id()).map_err(|e| e.to_string())?;
+        Ok(PidFile { file, path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        FileExt::unlock(&self.file).ok();
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+// syncs every account on its schedule and wakes snoozed mail until SIGTERM or ctrl-c.
+// running syncs are finished before it returns.
+pub async fn run(
+    database_config: DatabaseConfig,
+    config_file: Option<PathBuf>,
+) -> Result<(), String> {
+    let daemon_config = config::get_daemon(&config_file)?;
+    let pid_path = daemon_config
+        .pid_file
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("{}.pid", database_config.path)));
+    let _pid_file = PidFile::acquire(pid_path)?;
+
+    let accounts = config::get_accounts(config_file.unwrap_or_else(config::default_config_path));
+    if accounts.is_empty() {
+        return Err("no accounts configured".into());
+    }
+    // check every schedule before starting anything
+    let schedules = accounts
+        .iter()
+        .map(|account| Schedule::parse(daemon_config.schedule_for(&account.name)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (shutdown, stopping) = watch::channel(false);
+    let jobs = Arc::new(Semaphore::new(daemon_config.jobs.max(1)));
+    let mut tasks: Vec<JoinHandle<()>> = accounts
+        .clone()
+        .into_iter()
+        .zip(schedules.into_iter())
+        .map(|(account, schedule)| {
+            tokio::spawn(sync_on_schedule(
+                database_config.clone(),
+                account,
+                schedule,
+                jobs.clone(),
+                stopping.clone(),
+            ))
+        })
+        .collect();
+    tasks.push(tokio::spawn(maintain(
+        database_config.clone(),
+        accounts,
+        Duration::from_secs(daemon_config.wake_interval),
+        stopping,
+    )));
+    log(format!("daemon started, pid {}", std::process::id()));
+
+    wait_for_stop().await?;
+    log("daemon stopping");
+    shutdown.send(true).ok();
+    for task in tasks.into_iter() {
+        task.await.ok();
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn wait_for_stop() -> Result<(), String> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).map_err(|e| e.to_string())?;
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(())
+}
+
+// there is no SIGTERM, service managers send ctrl-c
+#[cfg(not(unix))]
+async fn wait_for_stop() -> Result<(), String> {
+    tokio::signal::ctrl_c().await.map_err(|e| e.to_string())
+}
+
+async fn sync_on_schedule(
+    database_config: DatabaseConfig,
+    account: AccountConfig,
+    schedule: Schedule,
+    jobs: Arc<Semaphore>,
+    mut stopping: watch::Receiver<bool>,
+) {
+    // the first sync runs right away
+    let mut next = Utc::now();
+    loop {
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = stopping.changed() => return,
+        }
+        // accounts that come due together take turns past `jobs`
+        let permit = tokio::select! {
+            permit = jobs.clone().acquire_owned() => match permit {
+                Ok(permit) => permit,
+                Err(_) => return,
+            },
+            _ = stopping.changed() => return,
+        };
+        let (sync_config, sync_account) = (database_config.clone(), account.clone());
+        let result = tokio::task::spawn_blocking(move || {
+            sync::sync_account(
+                &sync_config,
+                &sync_account,
+                sync::INITIAL_COUNT,
+                &sync::Progress::default(),
+            )
+        })
+        .await;
+        drop(permit);
+        match result {
+            Ok(report) => match report.error {
+                Some(e) => log(format!("sync {}: {}", account.name, e)),
+                None => log(format!(
+                    "{}: {} new messages",
+                    account.name,
+                    report.inserted()
+                )),
+            },
+            Err(e) => log(format!("sync {}: {}", account.name, e)),
+        }
+        next = match schedule.next_after(Utc::now()) {
+            Some(next) => next,
+            None => {
+                log(format!("{}: schedule has no next run", account.name));
+                return;
+            }
+        };
+    }
+}
+
+// snooze wake ups, due outbox messages and queued write backs, one place so replays
+// never overlap
+async fn maintain(
+    database_config: DatabaseConfig,
+    accounts: Vec<AccountConfig>,
+    interval: Duration,
+    mut stopping: watch::Receiver<bool>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = stopping.changed() => return,
+        }
+        let (database_config, accounts) = (database_config.clone(), accounts.clone());
+        // one failing step doesn't hold up the others
+        let result = tokio::task::spawn_blocking(move || {
+            if let Err(e) = snooze::wake_due(&database_config) {
+                log(format!("daemon snooze wake: {e}"));
+            }
+            if let Err(e) = outbox::deliver_due(&database_config, &accounts) {
+                log(format!("daemon outbox delivery: {e}"));
+            }
+            if let Err(e) = write_back::replay(&database_config, &accounts) {
+                log(format!("daemon write back: {e}"));
+            }
+        })
+        .await;
+        if let Err(e) = result {
+            log(format!("daemon upkeep: {e}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every(value: &str) -> Option<Duration> {
+        match Schedule::parse(value) {
+            Ok(Schedule::Every(interval)) => Some(interval),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(every("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(every("15m"), Some(Duration::from_secs(15 * 60)));
+        assert_eq!(every(" 2h\n"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(every("1d"), Some(Duration::from_secs(24 * 60 * 60)));
+    }
+
+    #[test]
+    fn rejects_zero() {
+        for value in ["0s", "0m", "0h", "0d"] {
+            let error = Schedule::parse(value).unwrap_err();
+            assert!(error.contains("longer than 0"), "{value}: {error}");
+        }
+    }
+
+    #[test]
+    fn rejects_overflow() {
+        // fits in u64 but not once multiplied into seconds
+        let error = Schedule::parse(&format!("{}d", u64::MAX / 60)).unwrap_err();
+        assert!(error.contains("too long"), "{error}");
+        // fits in u64 seconds but not in a chrono duration
+        let error = Schedule::parse(&format!("{}s", u64::MAX)).unwrap_err();
+        assert!(error.contains("too long"), "{error}");
+        // doesn't fit in u64 at all, so it isn't a valid cron expression either
+        assert!(Schedule::parse("99999999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn falls_back_to_cron() {
+        let schedule = Schedule::parse("0 30 9 * * Mon-Fri").unwrap();
+        assert!(matches!(schedule, Schedule::Cron(_)));
+        // a Saturday, the next run is Monday morning
+        let now = DateTime::parse_from_rfc3339("2023-03-18T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            schedule.next_after(now).map(|next| next.to_rfc3339()),
+            Some("2023-03-20T09:30:00+00:00".to_string())
+        );
+        assert!(Schedule::parse("15x").is_err());
+        assert!(Schedule::parse("").is_err());
+    }
+
+    #[test]
+    fn rejects_zero_wake_interval() {
+        let path =
+            std::env::temp_dir().join(format!("mail-test-{}-daemon.toml", std::process::id()));
+        fs::write(&path, "[daemon]\nwake_interval = 0\n").unwrap();
+        let result = config::get_daemon(&Some(path.clone()));
+        fs::remove_file(&path).ok();
+        assert!(result.unwrap_err().contains("wake_interval"));
+    }
+}
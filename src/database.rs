@@ -1,22 +1,31 @@
+use crate::app::{View, ViewFilter};
 use crate::config::DatabaseConfig;
 use crate::log::{debug_log, log};
-use crate::models::{Message, MessageLite, RawMessage};
+use crate::models::{
+    BackfillState, Draft, Message, MessageLite, OutboxEntry, PendingChange, RawMessage, SyncError,
+    SyncState,
+};
 use crate::schema::*;
 use crate::DebugMessageArgs;
 use crate::{
     config::{get_database, AccountConfig},
-    messages::email,
+    messages::{email, parse_emails},
+    threading::{self, ThreadMessage},
+    write_back::Change,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 use diesel::dsl::not;
-use diesel::query_dsl::methods::BoxedDsl;
+use diesel::sql_types::{BigInt, Nullable, Text};
 use diesel::sqlite::{Sqlite, SqliteConnection};
+use diesel::upsert::excluded;
 use diesel::{debug_query, prelude::*};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use himalaya_lib::Email;
-use mailparse::parse_mail;
+use itertools::Itertools;
+use mailparse::{parse_mail, DispositionType, MailHeaderMap, ParsedMail};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
@@ -24,6 +33,15 @@ fn run_migration(conn: &mut SqliteConnection) {
     conn.run_pending_migrations(MIGRATIONS).unwrap();
 }
 
+// accounts sync in parallel but sqlite takes one writer at a time, every write in
+// this process goes through here first
+static WRITER: Mutex<()> = Mutex::new(());
+
+fn write_lock() -> MutexGuard<'static, ()> {
+    // a panicked writer's transaction was rolled back, the lock is still good
+    WRITER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 pub fn establish_connection(config: Option<(&str, &Option<String>)>) -> SqliteConnection {
     let configs = config.map(|c| (c.0.to_string(), c.1)).unwrap_or_else(|| {
         let db_config = get_database(&None);
@@ -33,6 +51,10 @@ pub fn establish_connection(config: Option<(&str, &Option<String>)>) -> SqliteCo
     let database_url = configs.0;
     let mut conn = SqliteConnection::establish(&database_url)
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
+    // other processes (app, daemon, cli) still write, wait for them instead of failing
+    diesel::sql_query("PRAGMA busy_timeout = 5000")
+        .execute(&mut conn)
+        .expect("busy timeout");
     run_migration(&mut conn);
     conn
 }
@@ -59,44 +81,190 @@ pub fn debug_message(database_config: &DatabaseConfig, args: DebugMessageArgs) {
     dbg!(&parsed.get_headers(), &parsed.get_body(),);
 }
 
+#[derive(QueryableByName, Debug)]
+struct SearchHit {
+    #[diesel(sql_type = Nullable<Text>)]
+    parent_thread_key: Option<String>,
+    #[diesel(sql_type = Text)]
+    message_id: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    snippet: Option<String>,
+}
+
+// snippet() markers, swapped for <mark> once the text has been escaped
+const MARK_START: char = '\u{2}';
+const MARK_END: char = '\u{3}';
+
+fn fts_phrase(term: &str) -> Option<String> {
+    let term = term.replace('"', "");
+    let term = term.trim();
+    if term.is_empty() {
+        None
+    } else if term.contains(char::is_whitespace) {
+        Some(format!("\"{term}\""))
+    } else {
+        // single words match as prefixes so results show up while typing
+        Some(format!("\"{term}\"*"))
+    }
+}
+
+// everything text based goes through the fts index, quoted so user input can't
+// break the MATCH syntax.
+fn fts_query(filter: &MessageFilter) -> Option<String> {
+    let columns = [
+        (None, &filter.text),
+        (Some("message_from"), &filter.from),
+        (Some("{message_to message_cc}"), &filter.to),
+        (Some("subject"), &filter.subject),
+    ];
+    let terms = columns
+        .iter()
+        .flat_map(|(column, terms)| {
+            terms.iter().filter_map(move |term| {
+                fts_phrase(term).map(|phrase| match column {
+                    Some(column) => format!("{column} : {phrase}"),
+                    None => phrase,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" AND "))
+    }
+}
+
+// thread keys in rank order (date order without search terms) plus a highlighted
+// snippet for each matching message
+fn thread_keys(
+    conn: &mut SqliteConnection,
+    filter: &MessageFilter,
+    limit: usize,
+) -> Result<(Vec<String>, HashMap<String, String>), String> {
+    let search = fts_query(filter);
+    let now = db_timestamp(Utc::now());
+    let mut sql = if search.is_some() {
+        "SELECT m.parent_thread_key, m.message_id, \
+         snippet(messages_fts, -1, char(2), char(3), '…', 12) AS snippet \
+         FROM messages_fts JOIN messages m ON m.id = messages_fts.rowid \
+         WHERE m.parent_thread_key IS NOT NULL AND messages_fts MATCH ?"
+            .to_string()
+    } else {
+        "SELECT m.parent_thread_key, m.message_id, NULL AS snippet \
+         FROM messages m WHERE m.parent_thread_key IS NOT NULL"
+            .to_string()
+    };
+    let mut binds: Vec<String> = search.iter().cloned().collect();
+    let mut dates: Vec<i64> = vec![];
+
+    if let Some(account) = &filter.account {
+        sql.push_str(" AND m.account = ?");
+        binds.push(account.clone());
+    }
+    if let Some(folder) = &filter.folder {
+        sql.push_str(
+            " AND EXISTS (SELECT 1 FROM message_folders f \
+             WHERE f.account = m.account AND f.message_id = m.message_id AND f.folder = ?)",
+        );
+        binds.push(folder.clone());
+    }
+    for label in filter.labels.iter() {
+        sql.push_str(
+            " AND EXISTS (SELECT 1 FROM labels l \
+             WHERE l.account = m.account AND l.message_id = m.message_id \
+             AND lower(l.label) = lower(?))",
+        );
+        binds.push(label.clone());
+    }
+    match filter.pinned {
+        Some(true) => sql.push_str(" AND m.pinned_at IS NOT NULL"),
+        Some(false) => sql.push_str(" AND m.pinned_at IS NULL"),
+        None => {}
+    }
+    match filter.archived {
+        Some(true) => sql.push_str(" AND m.done_at IS NOT NULL"),
+        Some(false) => sql.push_str(" AND m.done_at IS NULL"),
+        None => {}
+    }
+    match filter.snoozed {
+        Some(true) => {
+            sql.push_str(" AND m.reminder_at > ?");
+            binds.push(now.clone());
+        }
+        Some(false) => {
+            sql.push_str(" AND (m.reminder_at IS NULL OR m.reminder_at <= ?)");
+            binds.push(now.clone());
+        }
+        None => {}
+    }
+    match filter.has_attachment {
+        Some(true) => sql.push_str(" AND m.has_attachment"),
+        Some(false) => sql.push_str(" AND NOT m.has_attachment"),
+        None => {}
+    }
+    // dates are bound after all the text so the placeholders stay in order
+    if let Some(after) = filter.after {
+        sql.push_str(" AND m.sent_date >= ?");
+        dates.push(after);
+    }
+    if let Some(before) = filter.before {
+        sql.push_str(" AND m.sent_date < ?");
+        dates.push(before);
+    }
+    if search.is_some() {
+        // column weights: subject, content, from, to, cc
+        sql.push_str(" ORDER BY bm25(messages_fts, 10.0, 1.0, 5.0, 2.0, 2.0)");
+    } else {
+        sql.push_str(" ORDER BY m.sent_date DESC");
+    }
+    sql.push_str(" LIMIT 1000");
+    debug_log(&sql);
+
+    let mut query = diesel::sql_query(sql).into_boxed::<Sqlite>();
+    for bind in binds.into_iter() {
+        query = query.bind::<Text, _>(bind);
+    }
+    for date in dates.into_iter() {
+        query = query.bind::<BigInt, _>(date);
+    }
+    let hits = query.load::<SearchHit>(conn).map_err(|e| e.to_string())?;
+
+    let mut thread_keys: Vec<String> = vec![];
+    let mut snippets = HashMap::new();
+    for hit in hits.into_iter() {
+        let key = hit.parent_thread_key.unwrap_or_default();
+        if !thread_keys.contains(&key) {
+            if thread_keys.len() >= limit {
+                continue;
+            }
+            thread_keys.push(key);
+        }
+        if let Some(snippet) = hit.snippet {
+            let snippet = ammonia::clean_text(&snippet)
+                .replace(MARK_START, "<mark>")
+                .replace(MARK_END, "</mark>");
+            snippets.entry(hit.message_id).or_insert(snippet);
+        }
+    }
+    Ok((thread_keys, snippets))
+}
+
+// (threads, message_id -> highlighted snippet)
+pub type ThreadList = (Vec<Vec<Message>>, HashMap<String, String>);
+
+// searches come back best match last, everything else oldest thread first.
 pub fn list_threads(
     database_config: &DatabaseConfig,
     filter: MessageFilter,
-) -> Result<Vec<Vec<Message>>, String> {
+) -> Result<ThreadList, String> {
     let mut conn = establish_connection(Some((
         database_config.path.clone().as_str(),
         &database_config.password.clone(),
     )));
 
-    let mut query = messages::table.into_boxed();
-    if let Some(q) = filter.query {
-        let q = format!("%{q}%");
-        query = query
-            .filter(messages::content.like(q.to_owned()))
-            .or_filter(messages::subject.like(q.to_owned()))
-            .or_filter(messages::message_to.like(q.to_owned()))
-            .or_filter(messages::message_cc.like(q.to_owned()))
-            .or_filter(messages::message_from.like(q.to_owned()));
-    } else {
-        query = query.filter(messages::parent_thread_key.is_not_null())
-    }
-    let query = query
-        .select(messages::parent_thread_key)
-        .distinct()
-        .order(messages::sent_date.desc())
-        .limit(100);
-
-    let debug = debug_query::<Sqlite, _>(&query);
-    debug_log(debug);
-    let thread_keys = query
-        .load::<Option<String>>(&mut conn)
-        .expect("Error loading threads");
-
-    let thread_keys = thread_keys
-        .into_iter()
-        .map(|s| s.unwrap_or_default())
-        .collect::<Vec<_>>();
-    dbg!(&thread_keys);
+    let ranked = fts_query(&filter).is_some();
+    let (thread_keys, snippets) = thread_keys(&mut conn, &filter, 100)?;
 
     let query = messages::table.into_boxed();
     let query = query
@@ -118,8 +286,12 @@ pub fn list_threads(
             messages::message_bcc,
             messages::parent_thread_key,
             messages::sent_date,
+            messages::has_attachment,
+            messages::snoozed_until,
+            messages::seen_at,
+            messages::labels,
         ))
-        .filter(messages::parent_thread_key.eq_any(thread_keys))
+        .filter(messages::parent_thread_key.eq_any(&thread_keys))
         .order(messages::sent_date.desc());
     let debug = debug_query::<Sqlite, _>(&query);
     debug_log(debug);
@@ -135,10 +307,19 @@ pub fn list_threads(
             .and_modify(|vec| vec.push(message.clone()))
             .or_insert(vec![message]);
     });
-    // into values is not stable but inner vec is.
-    let mut results = messages_group.into_values().collect::<Vec<_>>();
-    results.sort_by_cached_key(|v| date_int(&v.first().unwrap().sent_at.clone().unwrap()));
-    Ok(results)
+    let results = if ranked {
+        thread_keys
+            .iter()
+            .rev()
+            .filter_map(|key| messages_group.remove(key))
+            .collect::<Vec<_>>()
+    } else {
+        // into values is not stable but inner vec is.
+        let mut results = messages_group.into_values().collect::<Vec<_>>();
+        results.sort_by_cached_key(|v| date_int(&v.first().unwrap().sent_at.clone().unwrap()));
+        results
+    };
+    Ok((results, snippets))
 }
 
 // (text, html)
@@ -163,198 +344,1848 @@ pub fn get_message_id_content(
     })
 }
 
-pub fn save_records(
+pub fn get_message(
     database_config: &DatabaseConfig,
-    raw: RawMessage,
-    record: Message,
-) -> Result<(), String> {
+    account: &str,
+    message_id: &str,
+) -> Result<Message, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    messages::table
+        .filter(messages::account.eq(account))
+        .filter(messages::message_id.eq(message_id))
+        .first::<Message>(&mut conn)
+        .map_err(|e| format!("{message_id}: {e}"))
+}
+
+// the message as it came from the server
+pub fn get_raw_message(
+    database_config: &DatabaseConfig,
+    account: &str,
+    message_id: &str,
+) -> Result<Vec<u8>, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    raw_messages::table
+        .filter(raw_messages::account.eq(account))
+        .filter(raw_messages::message_id.eq(message_id))
+        .select(raw_messages::message)
+        .first::<Option<Vec<u8>>>(&mut conn)
+        .map_err(|e| format!("{message_id}: {e}"))?
+        .ok_or_else(|| format!("{message_id} has no raw message"))
+}
+
+// inserts a new draft or updates the one with the same id, returns the id
+pub fn save_draft(database_config: &DatabaseConfig, draft: Draft) -> Result<i32, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        match draft.id {
+            Some(id) => {
+                diesel::update(drafts::table.filter(drafts::id.eq(id)))
+                    .set((
+                        drafts::message_to.eq(&draft.message_to),
+                        drafts::message_cc.eq(&draft.message_cc),
+                        drafts::message_bcc.eq(&draft.message_bcc),
+                        drafts::subject.eq(&draft.subject),
+                        drafts::body.eq(&draft.body),
+                        drafts::in_reply_to.eq(&draft.in_reply_to),
+                        drafts::message_references.eq(&draft.message_references),
+                        drafts::forward_of.eq(&draft.forward_of),
+                        drafts::parent_thread_key.eq(&draft.parent_thread_key),
+                        drafts::updated_at.eq(&draft.updated_at),
+                    ))
+                    .execute(conn)?;
+            }
+            None => {
+                diesel::insert_into(drafts::table)
+                    .values(&draft)
+                    .execute(conn)?;
+            }
+        }
+        // message_id is unique and set before the first save
+        drafts::table
+            .filter(drafts::message_id.eq(&draft.message_id))
+            .select(drafts::id)
+            .first::<Option<i32>>(conn)
+    })
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "the draft has no id".to_string())
+}
+
+pub fn get_draft(database_config: &DatabaseConfig, id: i32) -> Result<Draft, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    drafts::table
+        .filter(drafts::id.eq(id))
+        .first::<Draft>(&mut conn)
+        .map_err(|e| format!("draft {id}: {e}"))
+}
+
+// newest first. `parent_thread_key` keeps it to the drafts answering that thread.
+// drafts waiting in the outbox are listed there instead.
+pub fn list_drafts(database_config: &DatabaseConfig, account: &str) -> Result<Vec<Draft>, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    drafts::table
+        .filter(drafts::account.eq(account))
+        .filter(not(
+            drafts::message_id.eq_any(outbox::table.select(outbox::message_id))
+        ))
+        .order(drafts::updated_at.desc())
+        .load::<Draft>(&mut conn)
+        .map_err(|e| e.to_string())
+}
+
+pub fn delete_draft(database_config: &DatabaseConfig, id: i32) -> Result<(), String> {
+    let _writer = write_lock();
     let mut conn = establish_connection(Some((
         database_config.path.clone().as_str(),
         &database_config.password.clone(),
     )));
-    diesel::insert_into(messages::table)
-        .values(&record)
+    diesel::delete(drafts::table.filter(drafts::id.eq(id)))
         .execute(&mut conn)
         .map_err(|e| e.to_string())?;
-    diesel::insert_into(raw_messages::table)
-        .values(&raw)
+    Ok(())
+}
+
+pub fn draft_uploaded(database_config: &DatabaseConfig, id: i32, at: &str) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::update(drafts::table.filter(drafts::id.eq(id)))
+        .set(drafts::uploaded_at.eq(at))
         .execute(&mut conn)
         .map_err(|e| e.to_string())?;
-
     Ok(())
 }
 
-pub fn message_to_db(
-    message: &Email,
-    account: &AccountConfig,
-    database_config: &DatabaseConfig,
-) -> Option<(RawMessage, Message)> {
-    if let Ok(message) = message.parsed() {
-        let headers = message.get_headers();
-        let headers = headers
-            .into_iter()
-            .map(|h| (h.get_key(), h.get_value()))
-            .collect::<HashMap<_, _>>();
-        let parent_id = headers
-            .get("In-Reply-To")
-            .or_else(|| headers.get("References"))
-            .cloned();
-
-        let parts = message
-            .subparts
-            .iter()
-            .map(|p| {
-                (
-                    p.headers
-                        .iter()
-                        .filter(|h| h.get_key() == "Content-Type")
-                        .nth(0)
-                        .map(|s| s.get_value())
-                        .unwrap_or_default(),
-                    email::parsed_mail_body(p).ok(),
-                )
-            })
-            .filter(|f| f.1.is_some())
-            .collect::<HashMap<_, _>>();
-        let text_key = parts.keys().find(|k| k.starts_with("text/plain"));
-        let html_key = parts.keys().find(|k| k.starts_with("text/html"));
-        let mut text_format = text_key.and_then(|k| parts.get(k).cloned()).flatten();
-        let mut html_format = html_key.and_then(|k| parts.get(k).cloned()).flatten();
-        if html_format.is_none() && text_format.is_none() {
-            let header = headers.get("Content-Type");
-            let header = header.map(|s| s.clone().to_owned()).unwrap_or_default();
-            if header.starts_with("text/html") {
-                html_format = message.get_body().ok();
-            } else if header.starts_with("text/plain") {
-                text_format = message.get_body().ok();
-            }
+pub fn queue_outbox(database_config: &DatabaseConfig, entry: OutboxEntry) -> Result<i32, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::insert_into(outbox::table)
+            .values(&entry)
+            .execute(conn)?;
+        outbox::table
+            .filter(outbox::message_id.eq(&entry.message_id))
+            .select(outbox::id)
+            .first::<Option<i32>>(conn)
+    })
+    .map_err(|e| match e {
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        ) => {
+            format!("{} is already in the outbox", entry.message_id)
         }
+        e => e.to_string(),
+    })?
+    .ok_or_else(|| "the outbox entry has no id".to_string())
+}
 
-        let content = text_format
-            .as_ref()
-            .or(html_format.as_ref())
-            .map(|s| s.to_owned());
+pub fn get_outbox(database_config: &DatabaseConfig, id: i32) -> Result<OutboxEntry, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    outbox::table
+        .filter(outbox::id.eq(id))
+        .first::<OutboxEntry>(&mut conn)
+        .map_err(|e| format!("outbox {id}: {e}"))
+}
 
-        let message_id = headers
-            .get("Message-ID")
-            .cloned()
-            .unwrap_or_else(|| "no id found!".to_owned());
+// soonest first, failed ones included
+pub fn list_outbox(
+    database_config: &DatabaseConfig,
+    account: &str,
+) -> Result<Vec<OutboxEntry>, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    outbox::table
+        .filter(outbox::account.eq(account))
+        .order(outbox::send_at.asc())
+        .load::<OutboxEntry>(&mut conn)
+        .map_err(|e| e.to_string())
+}
 
-        let subject = headers.get("Subject").map(|a| a.clone());
-        // take parent id and look it up in the db. if there use that thread id.
-        let parent_thread_key = parent_thread_key(&headers, database_config);
-        let record = Message {
-            account: account.name.clone(),
-            subject,
-            sent_at: headers.get("Date").cloned(),
-            message_from: headers.get("From").cloned(),
-            message_to: headers.get("To").cloned(),
-            message_cc: headers.get("Cc").cloned(),
-            message_bcc: headers.get("Bcc").cloned(),
-            folders: Some("INBOX".into()),
-            message_id: message_id.clone(),
-            content,
-            text_format,
-            html_format,
-            parent_id,
-            parent_thread_key,
-            sent_date: date_int(&headers.get("Date").cloned().unwrap_or_default()),
-            ..Default::default()
-        };
+// messages of the account that gave up on sending
+pub fn count_failed_outbox(database_config: &DatabaseConfig, account: &str) -> Result<i64, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    outbox::table
+        .filter(outbox::account.eq(account))
+        .filter(outbox::failed_at.is_not_null())
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(|e| e.to_string())
+}
 
-        let raw = RawMessage {
-            message_id: Some(message_id),
-            message: Some(message.raw_bytes.to_vec()),
-            ..Default::default()
-        };
-        Some((raw, record))
-    } else {
-        None
-    }
+// due and not being delivered. claims older than `stale_before` belong to a
+// process that went away mid delivery.
+pub fn due_outbox(
+    database_config: &DatabaseConfig,
+    now: &str,
+    stale_before: &str,
+) -> Result<Vec<OutboxEntry>, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    outbox::table
+        .filter(outbox::failed_at.is_null())
+        .filter(outbox::send_at.le(now))
+        .filter(
+            outbox::claimed_at
+                .is_null()
+                .or(outbox::claimed_at.lt(stale_before)),
+        )
+        .order(outbox::send_at.asc())
+        .load::<OutboxEntry>(&mut conn)
+        .map_err(|e| e.to_string())
 }
 
-fn date_int(date: &str) -> Option<i64> {
-    let sent: Option<DateTime<Utc>> = DateTime::parse_from_rfc2822(date).ok().map(|d| d.into());
-    sent.map(|s| s.timestamp())
+// false when someone else got to it first, the app and the daemon both deliver
+pub fn claim_outbox(
+    database_config: &DatabaseConfig,
+    id: i32,
+    now: &str,
+    stale_before: &str,
+) -> Result<bool, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::update(
+        outbox::table
+            .filter(outbox::id.eq(id))
+            .filter(outbox::failed_at.is_null())
+            .filter(
+                outbox::claimed_at
+                    .is_null()
+                    .or(outbox::claimed_at.lt(stale_before)),
+            ),
+    )
+    .set(outbox::claimed_at.eq(now))
+    .execute(&mut conn)
+    .map(|updated| updated == 1)
+    .map_err(|e| e.to_string())
 }
-#[derive(Default)]
-pub struct MessageFilter {
-    query: Option<String>,
-    pinned: bool,
-    folder: bool,
-    snoozed: bool,
-    archived: bool,
-}
-impl From<&crate::app::ViewFilter> for MessageFilter {
-    fn from(value: &crate::app::ViewFilter) -> Self {
-        MessageFilter {
-            query: value.query.clone(),
-            ..Default::default()
-        }
-    }
+
+pub fn outbox_sent(database_config: &DatabaseConfig, id: i32) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::delete(outbox::table.filter(outbox::id.eq(id)))
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(())
 }
 
-fn parent_thread_key(
-    headers: &HashMap<String, String>,
+// releases the claim. with `retry_at` it goes again then, without it it's failed for good.
+pub fn outbox_failed(
     database_config: &DatabaseConfig,
-) -> Option<String> {
-    let parent_id = headers
-        .get("In-Reply-To")
-        .or_else(|| headers.get("References"))
-        .cloned();
-    if let Some(parent_id) = parent_id {
-        let mut conn = establish_connection(Some((
-            database_config.path.clone().as_str(),
-            &database_config.password.clone(),
-        )));
-
-        match messages::dsl::messages
-            .filter(messages::message_id.eq(&parent_id))
-            .first::<Message>(&mut conn)
-        {
-            Ok(message) => {
-                if message.parent_thread_key.is_some() {
-                    return Some(message.parent_thread_key.unwrap().clone());
-                } else {
-                    log(format!(
-                        "message id {} matching parent id {} does not have a parent_thread_key",
-                        message.message_id, parent_id
-                    ));
-                }
-            }
-            Err(err) => log(format!(
-                "Error matching parent id {}: {:#?}",
-                parent_id, err
-            )),
-        }
+    id: i32,
+    error: &str,
+    retry_at: Option<&str>,
+    now: &str,
+) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    let entry = outbox::table.filter(outbox::id.eq(id));
+    let failed = (
+        outbox::attempts.eq(outbox::attempts + 1),
+        outbox::last_error.eq(error),
+        outbox::claimed_at.eq(None::<String>),
+    );
+    match retry_at {
+        Some(retry_at) => diesel::update(entry)
+            .set((failed, outbox::send_at.eq(retry_at)))
+            .execute(&mut conn),
+        None => diesel::update(entry)
+            .set((failed, outbox::failed_at.eq(now)))
+            .execute(&mut conn),
     }
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
 
-    headers
-        .get("Subject")
-        .map(|a| a.clone())
-        .map(|s| {
-            let s = s.to_lowercase();
-            s.replace("re:", "").trim().to_owned()
-        })
-        .map(|s| {
-            let mut hasher = Sha256::new();
-            hasher.update(s);
-            let result: String = format!("{:X}", hasher.finalize());
-            result
-        })
+// a failed message goes again at `send_at` with a fresh set of attempts
+pub fn retry_outbox(
+    database_config: &DatabaseConfig,
+    id: i32,
+    send_at: &str,
+) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::update(outbox::table.filter(outbox::id.eq(id)))
+        .set((
+            outbox::send_at.eq(send_at),
+            outbox::attempts.eq(0),
+            outbox::failed_at.eq(None::<String>),
+        ))
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// takes it out unless a delivery is already under way, returns whether it did
+pub fn cancel_outbox(database_config: &DatabaseConfig, id: i32) -> Result<bool, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::delete(
+        outbox::table
+            .filter(outbox::id.eq(id))
+            .filter(outbox::claimed_at.is_null()),
+    )
+    .execute(&mut conn)
+    .map(|deleted| deleted == 1)
+    .map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveOutcome {
+    Inserted,
+    Updated,
+}
+
+// (account, message_id) is unique. re-saving a message refreshes what came from the
+// server and leaves local state like pinned_at/done_at/reminder_at alone.
+// `record.folders` is the folder it was fetched from, the message is added to it
+// alongside any folders it is already in.
+pub fn save_records(
+    database_config: &DatabaseConfig,
+    raw: RawMessage,
+    record: Message,
+    uid: Option<u32>,
+) -> Result<SaveOutcome, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let existing = messages::table
+            .filter(messages::account.eq(&record.account))
+            .filter(messages::message_id.eq(&record.message_id))
+            .select(messages::id)
+            .first::<Option<i32>>(conn)
+            .optional()?;
+        diesel::insert_into(messages::table)
+            .values(&record)
+            .on_conflict((messages::account, messages::message_id))
+            .do_update()
+            .set((
+                messages::parent_id.eq(excluded(messages::parent_id)),
+                messages::subject.eq(excluded(messages::subject)),
+                messages::sent_at.eq(excluded(messages::sent_at)),
+                messages::message_from.eq(excluded(messages::message_from)),
+                messages::content.eq(excluded(messages::content)),
+                messages::text_format.eq(excluded(messages::text_format)),
+                messages::html_format.eq(excluded(messages::html_format)),
+                messages::message_to.eq(excluded(messages::message_to)),
+                messages::message_cc.eq(excluded(messages::message_cc)),
+                messages::message_bcc.eq(excluded(messages::message_bcc)),
+                messages::parent_thread_key.eq(excluded(messages::parent_thread_key)),
+                messages::sent_date.eq(excluded(messages::sent_date)),
+                messages::has_attachment.eq(excluded(messages::has_attachment)),
+                messages::message_references.eq(excluded(messages::message_references)),
+                // keeps the first read, sync takes unread from the server through refresh_seen
+                messages::seen_at.eq(coalesce(messages::seen_at, excluded(messages::seen_at))),
+                // only imap sync on gmail knows these, other paths keep what is there
+                messages::gm_msgid.eq(coalesce_bigint(
+                    excluded(messages::gm_msgid),
+                    messages::gm_msgid,
+                )),
+                messages::gm_thrid.eq(coalesce_bigint(
+                    excluded(messages::gm_thrid),
+                    messages::gm_thrid,
+                )),
+            ))
+            .execute(conn)?;
+        save_references(conn, &record)?;
+        // replies that arrived before this message started their own thread, fold them in
+        if let Some(key) = &record.parent_thread_key {
+            let replies = thread_references::table
+                .filter(thread_references::account.eq(&record.account))
+                .filter(thread_references::reference.eq(&record.message_id))
+                .select(thread_references::message_id);
+            let orphaned = messages::table
+                .filter(messages::account.eq(&record.account))
+                .filter(messages::message_id.eq_any(replies))
+                .filter(messages::parent_thread_key.ne(key))
+                .select(messages::parent_thread_key)
+                .distinct()
+                .load::<Option<String>>(conn)?
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            if !orphaned.is_empty() {
+                // gmail's thread id stays authoritative
+                diesel::update(
+                    messages::table
+                        .filter(messages::account.eq(&record.account))
+                        .filter(messages::parent_thread_key.eq_any(orphaned))
+                        .filter(messages::gm_thrid.is_null()),
+                )
+                .set(messages::parent_thread_key.eq(key))
+                .execute(conn)?;
+            }
+        }
+        if let Some(folder) = &record.folders {
+            diesel::insert_into(message_folders::table)
+                .values((
+                    message_folders::account.eq(&record.account),
+                    message_folders::message_id.eq(&record.message_id),
+                    message_folders::folder.eq(folder),
+                    message_folders::uid.eq(uid.map(i64::from)),
+                ))
+                .on_conflict((
+                    message_folders::account,
+                    message_folders::message_id,
+                    message_folders::folder,
+                ))
+                .do_update()
+                .set(message_folders::uid.eq(excluded(message_folders::uid)))
+                .execute(conn)?;
+            refresh_folders(conn, &record.account, &record.message_id)?;
+        }
+        diesel::insert_into(raw_messages::table)
+            .values(&raw)
+            .on_conflict((raw_messages::account, raw_messages::message_id))
+            .do_nothing()
+            .execute(conn)?;
+        Ok(if existing.is_some() {
+            SaveOutcome::Updated
+        } else {
+            SaveOutcome::Inserted
+        })
+    })
+    .map_err(|e| e.to_string())
+}
+
+// thread_references mirrors message_references one id per row
+fn save_references(conn: &mut SqliteConnection, record: &Message) -> QueryResult<()> {
+    diesel::delete(
+        thread_references::table
+            .filter(thread_references::account.eq(&record.account))
+            .filter(thread_references::message_id.eq(&record.message_id)),
+    )
+    .execute(conn)?;
+    let rows = record
+        .message_references
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .unique()
+        .map(|reference| {
+            (
+                thread_references::account.eq(&record.account),
+                thread_references::message_id.eq(&record.message_id),
+                thread_references::reference.eq(reference),
+            )
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(thread_references::table)
+        .values(&rows)
+        .execute(conn)?;
+    Ok(())
+}
+
+// messages.folders mirrors message_folders as a comma separated list for display
+fn refresh_folders(
+    conn: &mut SqliteConnection,
+    account: &str,
+    message_id: &str,
+) -> QueryResult<usize> {
+    diesel::sql_query(
+        "UPDATE messages SET folders = (\
+            SELECT group_concat(f.folder, ',') FROM message_folders f \
+            WHERE f.account = messages.account AND f.message_id = messages.message_id) \
+         WHERE account = ? AND message_id = ?",
+    )
+    .bind::<Text, _>(account)
+    .bind::<Text, _>(message_id)
+    .execute(conn)
+}
+
+// replaces the gmail labels of a message with what the server reported
+pub fn save_labels(
+    database_config: &DatabaseConfig,
+    account: &str,
+    message_id: &str,
+    names: &[String],
+) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        replace_labels(conn, account, message_id, names)
+    })
+    .map_err(|e| e.to_string())
+}
+
+// brings the labels of messages already stored from `folder` up to date. `labels`
+// is what the server reports per uid, messages whose labels didn't change are left
+// alone. returns how many were updated.
+pub fn refresh_labels(
+    database_config: &DatabaseConfig,
+    account: &str,
+    folder: &str,
+    labels: &HashMap<u32, Vec<String>>,
+) -> Result<usize, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let stored = message_folders::table
+            .filter(message_folders::account.eq(account))
+            .filter(message_folders::folder.eq(folder))
+            .filter(message_folders::uid.is_not_null())
+            .select((message_folders::message_id, message_folders::uid))
+            .load::<(String, Option<i64>)>(conn)?;
+        let mut local: HashMap<String, HashSet<String>> = HashMap::new();
+        for (message_id, label) in labels::table
+            .filter(labels::account.eq(account))
+            .select((labels::message_id, labels::label))
+            .load::<(String, String)>(conn)?
+        {
+            local.entry(message_id).or_default().insert(label);
+        }
+        let mut count = 0;
+        for (message_id, uid) in stored.into_iter() {
+            let Some(server) = uid.and_then(|uid| labels.get(&(uid as u32))) else {
+                continue;
+            };
+            let unchanged = local.get(&message_id).map_or(server.is_empty(), |local| {
+                local.len() == server.len() && server.iter().all(|l| local.contains(l))
+            });
+            if !unchanged {
+                replace_labels(conn, account, &message_id, server)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    })
+    .map_err(|e| e.to_string())
+}
+
+fn replace_labels(
+    conn: &mut SqliteConnection,
+    account: &str,
+    message_id: &str,
+    names: &[String],
+) -> QueryResult<()> {
+    diesel::delete(
+        labels::table
+            .filter(labels::account.eq(account))
+            .filter(labels::message_id.eq(message_id)),
+    )
+    .execute(conn)?;
+    for label in names.iter() {
+        diesel::insert_or_ignore_into(labels::table)
+            .values((
+                labels::account.eq(account),
+                labels::message_id.eq(message_id),
+                labels::label.eq(label),
+            ))
+            .execute(conn)?;
+    }
+    // messages.labels mirrors the labels table for display, like folders
+    diesel::sql_query(
+        "UPDATE messages SET labels = (\
+            SELECT group_concat(l.label, ',') FROM labels l \
+            WHERE l.account = messages.account AND l.message_id = messages.message_id) \
+         WHERE account = ? AND message_id = ?",
+    )
+    .bind::<Text, _>(account)
+    .bind::<Text, _>(message_id)
+    .execute(conn)?;
+    Ok(())
+}
+
+// every folder anything has been stored in
+pub fn list_folders(database_config: &DatabaseConfig) -> Result<Vec<String>, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    message_folders::table
+        .select(message_folders::folder)
+        .distinct()
+        .order(message_folders::folder.asc())
+        .load::<String>(&mut conn)
+        .map_err(|e| e.to_string())
+}
+
+// drops the folder from messages whose uid is no longer in it. rows without a uid
+// came from somewhere that doesn't know uids and are left alone.
+pub fn prune_folder(
+    database_config: &DatabaseConfig,
+    account: &str,
+    folder: &str,
+    present: &HashSet<u32>,
+) -> Result<usize, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let gone = message_folders::table
+            .filter(message_folders::account.eq(account))
+            .filter(message_folders::folder.eq(folder))
+            .filter(message_folders::uid.is_not_null())
+            .select((message_folders::message_id, message_folders::uid))
+            .load::<(String, Option<i64>)>(conn)?
+            .into_iter()
+            .filter(|(_, uid)| !uid.map_or(false, |uid| present.contains(&(uid as u32))))
+            .map(|(message_id, _)| message_id)
+            .collect::<Vec<_>>();
+        for message_id in gone.iter() {
+            diesel::delete(
+                message_folders::table
+                    .filter(message_folders::account.eq(account))
+                    .filter(message_folders::folder.eq(folder))
+                    .filter(message_folders::message_id.eq(message_id)),
+            )
+            .execute(conn)?;
+            refresh_folders(conn, account, message_id)?;
+        }
+        Ok(gone.len())
+    })
+    .map_err(|e| e.to_string())
+}
+
+// takes \Seen from the server for every message in `folder`. a message with a local
+// read or unread still waiting in pending_changes keeps what it has.
+pub fn refresh_seen(
+    database_config: &DatabaseConfig,
+    account: &str,
+    folder: &str,
+    seen: &HashSet<u32>,
+) -> Result<usize, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let pending = pending_changes::table
+            .filter(pending_changes::account.eq(account))
+            .filter(
+                pending_changes::change
+                    .eq_any([Change::Seen(true).as_str(), Change::Seen(false).as_str()]),
+            )
+            .select(pending_changes::message_id)
+            .load::<String>(conn)?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let server = message_folders::table
+            .filter(message_folders::account.eq(account))
+            .filter(message_folders::folder.eq(folder))
+            .filter(message_folders::uid.is_not_null())
+            .select((message_folders::message_id, message_folders::uid))
+            .load::<(String, Option<i64>)>(conn)?
+            .into_iter()
+            .filter(|(message_id, _)| !pending.contains(message_id))
+            .map(|(message_id, uid)| {
+                let seen = uid.is_some_and(|uid| seen.contains(&(uid as u32)));
+                (message_id, seen)
+            })
+            .collect::<HashMap<_, _>>();
+        let local = messages::table
+            .filter(messages::account.eq(account))
+            .select((messages::message_id, messages::seen_at.is_not_null()))
+            .load::<(String, bool)>(conn)?;
+        let (read, unread): (Vec<_>, Vec<_>) = local
+            .into_iter()
+            .filter_map(|(message_id, local)| {
+                let server = *server.get(&message_id)?;
+                (server != local).then_some((message_id, server))
+            })
+            .partition(|(_, server)| *server);
+        let now = db_timestamp(Utc::now());
+        let mut count = 0;
+        for chunk in read.chunks(500) {
+            let ids = chunk.iter().map(|(message_id, _)| message_id);
+            count += diesel::update(
+                messages::table
+                    .filter(messages::account.eq(account))
+                    .filter(messages::message_id.eq_any(ids)),
+            )
+            .set(messages::seen_at.eq(&now))
+            .execute(conn)?;
+        }
+        for chunk in unread.chunks(500) {
+            let ids = chunk.iter().map(|(message_id, _)| message_id);
+            count += diesel::update(
+                messages::table
+                    .filter(messages::account.eq(account))
+                    .filter(messages::message_id.eq_any(ids)),
+            )
+            .set(messages::seen_at.eq(None::<String>))
+            .execute(conn)?;
+        }
+        Ok(count)
+    })
+    .map_err(|e| e.to_string())
+}
+
+// after a move on the server, the old uid is no longer valid
+pub fn move_folder(
+    database_config: &DatabaseConfig,
+    account: &str,
+    message_id: &str,
+    from: &str,
+    to: &str,
+) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::sql_query(
+            "UPDATE OR REPLACE message_folders SET folder = ?, uid = NULL \
+             WHERE account = ? AND message_id = ? AND folder = ?",
+        )
+        .bind::<Text, _>(to)
+        .bind::<Text, _>(account)
+        .bind::<Text, _>(message_id)
+        .bind::<Text, _>(from)
+        .execute(conn)?;
+        refresh_folders(conn, account, message_id)?;
+        Ok(())
+    })
+    .map_err(|e| e.to_string())
+}
+
+// what a pin/done/snooze action applies to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    // parent_thread_key
+    Thread(String),
+    // the same message_id can be stored under more than one account
+    Message { account: String, message_id: String },
+}
+
+// (id, account, message_id) of every message the target covers
+fn target_messages(
+    conn: &mut SqliteConnection,
+    target: &Target,
+) -> QueryResult<Vec<(Option<i32>, String, String)>> {
+    let query = messages::table
+        .select((messages::id, messages::account, messages::message_id))
+        .into_boxed();
+    let query = match target {
+        Target::Thread(key) => query.filter(messages::parent_thread_key.eq(key.clone())),
+        Target::Message {
+            account,
+            message_id,
+        } => query
+            .filter(messages::account.eq(account.clone()))
+            .filter(messages::message_id.eq(message_id.clone())),
+    };
+    query.load(conn)
+}
+
+// remembered so write_back can repeat it on the server once it is reachable
+fn queue_changes(
+    conn: &mut SqliteConnection,
+    messages: &[(Option<i32>, String, String)],
+    change: Change,
+) -> QueryResult<usize> {
+    let created_at = db_timestamp(Utc::now());
+    let changes = messages
+        .iter()
+        .map(|(_, account, message_id)| PendingChange {
+            id: None,
+            account: account.clone(),
+            message_id: message_id.clone(),
+            change: change.as_str().to_owned(),
+            created_at: created_at.clone(),
+            attempts: 0,
+            last_error: None,
+            claimed_at: None,
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(pending_changes::table)
+        .values(&changes)
+        .execute(conn)
+}
+
+pub fn set_pinned(
+    database_config: &DatabaseConfig,
+    target: &Target,
+    pinned: bool,
+) -> Result<usize, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let targets = target_messages(conn, target)?;
+        let ids = targets.iter().map(|t| t.0).collect::<Vec<_>>();
+        let pinned_at = pinned.then(|| db_timestamp(Utc::now()));
+        let count = diesel::update(messages::table.filter(messages::id.eq_any(ids)))
+            .set(messages::pinned_at.eq(pinned_at))
+            .execute(conn)?;
+        queue_changes(conn, &targets, Change::Pin(pinned))?;
+        Ok(count)
+    })
+    .map_err(|e| e.to_string())
+}
+
+// done hides it from the inbox, restoring brings it back
+pub fn set_done(
+    database_config: &DatabaseConfig,
+    target: &Target,
+    done: bool,
+) -> Result<usize, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let targets = target_messages(conn, target)?;
+        let ids = targets.iter().map(|t| t.0).collect::<Vec<_>>();
+        let done_at = done.then(|| db_timestamp(Utc::now()));
+        let count = diesel::update(messages::table.filter(messages::id.eq_any(ids)))
+            .set((
+                messages::done_at.eq(done_at),
+                messages::snoozed_until.eq(None::<String>),
+            ))
+            .execute(conn)?;
+        queue_changes(conn, &targets, Change::Done(done))?;
+        Ok(count)
+    })
+    .map_err(|e| e.to_string())
+}
+
+// hidden from the inbox until `until`, None cancels the snooze
+pub fn snooze(
+    database_config: &DatabaseConfig,
+    target: &Target,
+    until: Option<DateTime<Utc>>,
+) -> Result<usize, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let targets = target_messages(conn, target)?;
+        let ids = targets.iter().map(|t| t.0).collect::<Vec<_>>();
+        let count = diesel::update(messages::table.filter(messages::id.eq_any(ids)))
+            .set((
+                messages::reminder_at.eq(until.map(db_timestamp)),
+                messages::snoozed_until.eq(None::<String>),
+            ))
+            .execute(conn)?;
+        queue_changes(conn, &targets, Change::Snooze(until.is_some()))?;
+        Ok(count)
+    })
+    .map_err(|e| e.to_string())
+}
+
+pub fn set_seen(
+    database_config: &DatabaseConfig,
+    target: &Target,
+    seen: bool,
+) -> Result<usize, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let targets = target_messages(conn, target)?;
+        let unseen = messages::table
+            .filter(messages::id.eq_any(targets.iter().map(|t| t.0).collect::<Vec<_>>()))
+            .filter(messages::seen_at.is_null())
+            .select(messages::id)
+            .load::<Option<i32>>(conn)?;
+        // only what actually changes needs to go to the server
+        let targets = targets
+            .into_iter()
+            .filter(|(id, _, _)| unseen.contains(id) == seen)
+            .collect::<Vec<_>>();
+        let ids = targets.iter().map(|t| t.0).collect::<Vec<_>>();
+        let seen_at = seen.then(|| db_timestamp(Utc::now()));
+        let count = diesel::update(messages::table.filter(messages::id.eq_any(ids)))
+            .set(messages::seen_at.eq(seen_at))
+            .execute(conn)?;
+        queue_changes(conn, &targets, Change::Seen(seen))?;
+        Ok(count)
+    })
+    .map_err(|e| e.to_string())
+}
+
+// due snoozes go back to the inbox, remembering when they were due for the badge
+pub fn wake_snoozed(database_config: &DatabaseConfig, now: DateTime<Utc>) -> Result<usize, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let due = messages::table
+            .filter(messages::reminder_at.le(db_timestamp(now)))
+            .select((messages::id, messages::account, messages::message_id))
+            .load::<(Option<i32>, String, String)>(conn)?;
+        let ids = due.iter().map(|t| t.0).collect::<Vec<_>>();
+        let count = diesel::update(messages::table.filter(messages::id.eq_any(ids)))
+            .set((
+                messages::snoozed_until.eq(messages::reminder_at),
+                messages::reminder_at.eq(None::<String>),
+                messages::done_at.eq(None::<String>),
+            ))
+            .execute(conn)?;
+        queue_changes(conn, &due, Change::Snooze(false))?;
+        Ok(count)
+    })
+    .map_err(|e| e.to_string())
+}
+
+// accounts with changes waiting to be written back
+pub fn pending_change_accounts(database_config: &DatabaseConfig) -> Result<Vec<String>, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    pending_changes::table
+        .select(pending_changes::account)
+        .distinct()
+        .load::<String>(&mut conn)
+        .map_err(|e| e.to_string())
+}
+
+// takes every waiting change of `account` in order, or none when another replay holds
+// some of them. changes of one message have to reach the server in the order they were
+// made, so an account is replayed by one caller at a time.
+pub fn claim_changes(
+    database_config: &DatabaseConfig,
+    account: &str,
+    now: &str,
+    stale_before: &str,
+) -> Result<Vec<PendingChange>, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let held = pending_changes::table
+            .filter(pending_changes::account.eq(account))
+            .filter(pending_changes::claimed_at.ge(stale_before))
+            .count()
+            .get_result::<i64>(conn)?;
+        if held > 0 {
+            return Ok(vec![]);
+        }
+        diesel::update(pending_changes::table.filter(pending_changes::account.eq(account)))
+            .set(pending_changes::claimed_at.eq(now))
+            .execute(conn)?;
+        pending_changes::table
+            .filter(pending_changes::account.eq(account))
+            .order(pending_changes::id.asc())
+            .load::<PendingChange>(conn)
+    })
+    .map_err(|e| e.to_string())
+}
+
+// hands changes back for the next replay
+pub fn release_changes(database_config: &DatabaseConfig, ids: &[i32]) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::update(pending_changes::table.filter(pending_changes::id.eq_any(ids)))
+        .set(pending_changes::claimed_at.eq(None::<String>))
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn finish_change(database_config: &DatabaseConfig, id: i32) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::delete(pending_changes::table.filter(pending_changes::id.eq(id)))
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn fail_change(database_config: &DatabaseConfig, id: i32, error: &str) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::update(pending_changes::table.filter(pending_changes::id.eq(id)))
+        .set((
+            pending_changes::attempts.eq(pending_changes::attempts + 1),
+            pending_changes::last_error.eq(error),
+            pending_changes::claimed_at.eq(None::<String>),
+        ))
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// folders a message was stored from
+pub fn message_folders(
+    database_config: &DatabaseConfig,
+    account: &str,
+    message_id: &str,
+) -> Vec<String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    message_folders::table
+        .filter(message_folders::account.eq(account))
+        .filter(message_folders::message_id.eq(message_id))
+        .select(message_folders::folder)
+        .load::<String>(&mut conn)
+        .unwrap_or_default()
+}
+
+pub fn get_sync_state(
+    database_config: &DatabaseConfig,
+    account: &str,
+    folder: &str,
+) -> Option<SyncState> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    sync_state::table
+        .filter(sync_state::account.eq(account))
+        .filter(sync_state::folder.eq(folder))
+        .first::<SyncState>(&mut conn)
+        .ok()
+}
+
+pub fn save_sync_state(database_config: &DatabaseConfig, state: SyncState) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    // unique(account, folder) turns this into an update of the existing mark
+    diesel::replace_into(sync_state::table)
+        .values(&SyncState { id: None, ..state })
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// how far a backfill of one date window got, the window is kept as "YYYY-MM-DD"
+// with "" for an open end
+pub fn get_backfill_state(
+    database_config: &DatabaseConfig,
+    account: &str,
+    folder: &str,
+    since: &str,
+    before: &str,
+) -> Option<BackfillState> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    backfill_state::table
+        .filter(backfill_state::account.eq(account))
+        .filter(backfill_state::folder.eq(folder))
+        .filter(backfill_state::since.eq(since))
+        .filter(backfill_state::before.eq(before))
+        .first::<BackfillState>(&mut conn)
+        .ok()
+}
+
+pub fn save_backfill_state(
+    database_config: &DatabaseConfig,
+    state: BackfillState,
+) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::replace_into(backfill_state::table)
+        .values(&BackfillState { id: None, ..state })
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// uids of a folder that are already stored, only meaningful for the UIDVALIDITY
+// they were stored under
+pub fn folder_uids(
+    database_config: &DatabaseConfig,
+    account: &str,
+    folder: &str,
+) -> Result<HashSet<u32>, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    let uids = message_folders::table
+        .filter(message_folders::account.eq(account))
+        .filter(message_folders::folder.eq(folder))
+        .filter(message_folders::uid.is_not_null())
+        .select(message_folders::uid)
+        .load::<Option<i64>>(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(uids.into_iter().flatten().map(|uid| uid as u32).collect())
+}
+
+// a message that didn't make it into the database. a uid that failed before is
+// updated rather than recorded twice.
+pub fn record_sync_error(
+    database_config: &DatabaseConfig,
+    account: &str,
+    folder: &str,
+    uid: Option<u32>,
+    error: &str,
+    raw: Option<Vec<u8>>,
+) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    let now = db_timestamp(Utc::now());
+    let uid = uid.map(i64::from);
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let updated = match uid {
+            Some(uid) => diesel::update(
+                sync_errors::table
+                    .filter(sync_errors::account.eq(account))
+                    .filter(sync_errors::folder.eq(folder))
+                    .filter(sync_errors::uid.eq(uid)),
+            )
+            .set((
+                sync_errors::attempts.eq(sync_errors::attempts + 1),
+                sync_errors::error.eq(error),
+                sync_errors::last_attempt_at.eq(&now),
+            ))
+            .execute(conn)?,
+            None => 0,
+        };
+        if updated == 0 {
+            diesel::insert_into(sync_errors::table)
+                .values(&SyncError {
+                    id: None,
+                    account: account.to_owned(),
+                    folder: folder.to_owned(),
+                    uid,
+                    error: error.to_owned(),
+                    raw,
+                    attempts: 1,
+                    created_at: now.clone(),
+                    last_attempt_at: now.clone(),
+                })
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+    .map_err(|e| e.to_string())
+}
+
+pub fn sync_errors(database_config: &DatabaseConfig) -> Result<Vec<SyncError>, String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    sync_errors::table
+        .order((
+            sync_errors::account,
+            sync_errors::folder,
+            sync_errors::id.asc(),
+        ))
+        .load::<SyncError>(&mut conn)
+        .map_err(|e| e.to_string())
+}
+
+pub fn clear_sync_error(database_config: &DatabaseConfig, id: i32) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::delete(sync_errors::table.filter(sync_errors::id.eq(id)))
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn fail_sync_error(
+    database_config: &DatabaseConfig,
+    id: i32,
+    error: &str,
+) -> Result<(), String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    diesel::update(sync_errors::table.filter(sync_errors::id.eq(id)))
+        .set((
+            sync_errors::attempts.eq(sync_errors::attempts + 1),
+            sync_errors::error.eq(error),
+            sync_errors::last_attempt_at.eq(db_timestamp(Utc::now())),
+        ))
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn message_to_db(
+    message: &Email,
+    account: &AccountConfig,
+    folder: &str,
+    database_config: &DatabaseConfig,
+) -> Result<Option<(RawMessage, Message)>, String> {
+    if let Ok(message) = message.parsed() {
+        let headers = message.get_headers();
+        let headers = headers
+            .into_iter()
+            .map(|h| (h.get_key(), h.get_value()))
+            .collect::<HashMap<_, _>>();
+        let references =
+            threading::references(headers.get("References"), headers.get("In-Reply-To"));
+        let parent_id = references.last().cloned();
+
+        // a part that doesn't decode fails the message, it is kept for a retry
+        let parts = message
+            .subparts
+            .iter()
+            .map(|p| {
+                let content_type = p
+                    .headers
+                    .iter()
+                    .find(|h| h.get_key() == "Content-Type")
+                    .map(|s| s.get_value())
+                    .unwrap_or_default();
+                let body = email::parsed_mail_body(p)
+                    .map_err(|e| format!("decoding the {content_type} part: {e}"))?;
+                Ok((content_type, body))
+            })
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        let text_key = parts.keys().find(|k| k.starts_with("text/plain"));
+        let html_key = parts.keys().find(|k| k.starts_with("text/html"));
+        let mut text_format = text_key.and_then(|k| parts.get(k).cloned());
+        let mut html_format = html_key.and_then(|k| parts.get(k).cloned());
+        if html_format.is_none() && text_format.is_none() {
+            let header = headers.get("Content-Type");
+            let header = header.map(|s| s.clone().to_owned()).unwrap_or_default();
+            if header.starts_with("text/html") {
+                html_format = message.get_body().ok();
+            } else if header.starts_with("text/plain") {
+                text_format = message.get_body().ok();
+            }
+        }
+
+        let content = text_format
+            .as_ref()
+            .or(html_format.as_ref())
+            .map(|s| s.to_owned());
+
+        // without a Message-ID the content is the only stable key we have
+        let message_id = headers
+            .get("Message-ID")
+            .cloned()
+            .unwrap_or_else(|| content_message_id(message.raw_bytes));
+
+        let subject = headers.get("Subject").cloned();
+        let sent_date = date_int(&headers.get("Date").cloned().unwrap_or_default());
+        let thread_message = ThreadMessage {
+            message_id: message_id.clone(),
+            references: references.clone(),
+            subject: subject.clone(),
+            sent_date,
+            participants: participants(&[
+                headers.get("From"),
+                headers.get("To"),
+                headers.get("Cc"),
+            ]),
+        };
+        let parent_thread_key = parent_thread_key(&account.name, &thread_message, database_config);
+        let record = Message {
+            account: account.name.clone(),
+            subject,
+            sent_at: headers.get("Date").cloned(),
+            message_from: headers.get("From").cloned(),
+            message_to: headers.get("To").cloned(),
+            message_cc: headers.get("Cc").cloned(),
+            message_bcc: headers.get("Bcc").cloned(),
+            folders: Some(folder.to_owned()),
+            message_id: message_id.clone(),
+            content,
+            text_format,
+            html_format,
+            parent_id,
+            parent_thread_key,
+            sent_date,
+            has_attachment: has_attachment(message),
+            message_references: Some(references.join(" ")),
+            ..Default::default()
+        };
+
+        let raw = RawMessage {
+            message_id: Some(message_id),
+            message: Some(message.raw_bytes.to_vec()),
+            account: Some(account.name.clone()),
+            ..Default::default()
+        };
+        Ok(Some((raw, record)))
+    } else {
+        Ok(None)
+    }
+}
+
+// lowercased addresses out of address headers
+fn participants(fields: &[Option<&String>]) -> Vec<String> {
+    fields
+        .iter()
+        .flatten()
+        .flat_map(|field| parse_emails(field))
+        .map(|(address, _)| address.to_lowercase())
+        .unique()
+        .collect()
+}
+
+fn has_attachment(message: &ParsedMail) -> bool {
+    message.get_content_disposition().disposition == DispositionType::Attachment
+        || message.subparts.iter().any(has_attachment)
+}
+
+fn content_message_id(raw: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(raw);
+    format!("<{:x}@bes.local>", hasher.finalize())
+}
+
+fn date_int(date: &str) -> Option<i64> {
+    let sent: Option<DateTime<Utc>> = DateTime::parse_from_rfc2822(date).ok().map(|d| d.into());
+    sent.map(|s| s.timestamp())
+}
+// built from the search bar by query::parse. text based fields are matched
+// through the fts index, the rest are plain column filters.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct MessageFilter {
+    pub text: Vec<String>,
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub subject: Vec<String>,
+    pub folder: Option<String>,
+    // gmail labels, all of them have to match
+    pub labels: Vec<String>,
+    pub account: Option<String>,
+    // unix timestamps compared against sent_date
+    pub before: Option<i64>,
+    pub after: Option<i64>,
+    pub pinned: Option<bool>,
+    pub snoozed: Option<bool>,
+    pub archived: Option<bool>,
+    pub has_attachment: Option<bool>,
+}
+impl TryFrom<&ViewFilter> for MessageFilter {
+    type Error = String;
+
+    fn try_from(value: &ViewFilter) -> Result<Self, Self::Error> {
+        let mut filter = value
+            .query
+            .as_deref()
+            .map(crate::query::parse)
+            .transpose()?
+            .unwrap_or_default();
+        // operators typed in the search bar win over the current view
+        match &value.view {
+            // the inbox leaves out what is done or snoozed, searches look everywhere
+            View::Inbox => {
+                if value.query.is_none() {
+                    // sent and all mail are synced too, only what is in the inbox belongs here
+                    filter.folder.get_or_insert_with(|| "INBOX".into());
+                    filter.archived.get_or_insert(false);
+                    filter.snoozed.get_or_insert(false);
+                }
+            }
+            View::Pinned => {
+                filter.pinned.get_or_insert(true);
+            }
+            View::Snoozed => {
+                filter.snoozed.get_or_insert(true);
+            }
+            View::Done => {
+                filter.archived.get_or_insert(true);
+            }
+            View::Folder(folder) => {
+                filter.folder.get_or_insert_with(|| folder.clone());
+            }
+            // drafts aren't messages, the app lists them on their own
+            View::Drafts | View::Outbox => {}
+        }
+        if filter.account.is_none() {
+            filter.account = value.account.clone();
+        }
+        Ok(filter)
+    }
+}
+
+// stored timestamp format. fixed width utc so text comparisons in sql order correctly.
+pub fn db_timestamp(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+sql_function!(fn lower(x: Nullable<Text>) -> Nullable<Text>);
+sql_function!(fn coalesce(x: Nullable<Text>, y: Nullable<Text>) -> Nullable<Text>);
+sql_function!(#[sql_name = "coalesce"] fn coalesce_bigint(x: Nullable<BigInt>, y: Nullable<BigInt>) -> Nullable<BigInt>);
+
+// same answer threading::thread would give, using what is already stored:
+// the closest ancestor we have decides, otherwise the root of the references.
+// replies without any references can only be matched on subject.
+fn parent_thread_key(
+    account: &str,
+    message: &ThreadMessage,
+    database_config: &DatabaseConfig,
+) -> Option<String> {
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+    let message_id = &message.message_id;
+    let references = &message.references;
+
+    // gmail's thread id, once synced, wins over anything worked out here
+    if let Ok(Some(key)) = messages::table
+        .filter(messages::account.eq(account))
+        .filter(messages::message_id.eq(message_id))
+        .filter(messages::gm_thrid.is_not_null())
+        .select(messages::parent_thread_key)
+        .first::<Option<String>>(&mut conn)
+    {
+        return Some(key);
+    }
+
+    for parent_id in references.iter().rev() {
+        match messages::table
+            .filter(messages::account.eq(account))
+            .filter(messages::message_id.eq(parent_id))
+            .select(messages::parent_thread_key)
+            .first::<Option<String>>(&mut conn)
+        {
+            Ok(Some(key)) => return Some(key),
+            Ok(None) => log(format!(
+                "message id {} matching parent id {} does not have a parent_thread_key",
+                message_id, parent_id
+            )),
+            Err(diesel::result::Error::NotFound) => {}
+            Err(err) => log(format!(
+                "Error matching parent id {}: {:#?}",
+                parent_id, err
+            )),
+        }
+    }
+    if let Some(root) = references.first() {
+        return Some(threading::thread_key(root));
+    }
+
+    let subject = message.subject.as_deref().map(threading::normalize_subject);
+    if let (Some((subject, true)), Some(sent_date)) = (subject, message.sent_date) {
+        let candidates = messages::table
+            .filter(messages::account.eq(account))
+            .filter(lower(messages::subject).eq(&subject))
+            .filter(messages::parent_thread_key.is_not_null())
+            .filter(messages::sent_date.between(sent_date - threading::SUBJECT_WINDOW, sent_date))
+            .select((
+                messages::parent_thread_key,
+                messages::sent_date,
+                messages::message_from,
+                messages::message_to,
+                messages::message_cc,
+            ))
+            .order(messages::sent_date.desc())
+            .load::<(
+                Option<String>,
+                Option<i64>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+            )>(&mut conn)
+            .unwrap_or_default();
+        let original = candidates
+            .into_iter()
+            .find(|(_, sent_date, from, to, cc)| {
+                let original = ThreadMessage {
+                    sent_date: *sent_date,
+                    participants: participants(&[from.as_ref(), to.as_ref(), cc.as_ref()]),
+                    ..Default::default()
+                };
+                threading::same_conversation(&original, message)
+            })
+            .and_then(|(key, ..)| key);
+        if original.is_some() {
+            return original;
+        }
+    }
+    Some(threading::thread_key(message_id))
+}
+
+// rebuilds parent_thread_key for the whole database. rows stored before
+// message_references existed get it filled in from the raw message first.
+pub fn rethread(database_config: &DatabaseConfig) -> Result<usize, String> {
+    let _writer = write_lock();
+    let mut conn = establish_connection(Some((
+        database_config.path.clone().as_str(),
+        &database_config.password.clone(),
+    )));
+
+    let missing = messages::table
+        .filter(messages::message_references.is_null())
+        .select((messages::account, messages::message_id))
+        .load::<(String, String)>(&mut conn)
+        .map_err(|e| e.to_string())?;
+    log(format!("reading references for {} messages", missing.len()));
+    for (account, message_id) in missing.into_iter() {
+        let raw = raw_messages::table
+            .filter(raw_messages::account.eq(&account))
+            .filter(raw_messages::message_id.eq(&message_id))
+            .select(raw_messages::message)
+            .first::<Option<Vec<u8>>>(&mut conn)
+            .ok()
+            .flatten();
+        let references = raw
+            .as_ref()
+            .and_then(|raw| parse_mail(raw).ok())
+            .map(|parsed| {
+                threading::references(
+                    parsed.headers.get_first_value("References").as_ref(),
+                    parsed.headers.get_first_value("In-Reply-To").as_ref(),
+                )
+            })
+            .unwrap_or_default();
+        diesel::update(
+            messages::table
+                .filter(messages::account.eq(&account))
+                .filter(messages::message_id.eq(&message_id)),
+        )
+        .set((
+            messages::message_references.eq(references.join(" ")),
+            messages::parent_id.eq(references.last().cloned()),
+        ))
+        .execute(&mut conn)
+        .map_err(|e| e.to_string())?;
+    }
+
+    let rows = messages::table
+        .select((
+            messages::account,
+            messages::message_id,
+            messages::message_references,
+            messages::subject,
+            messages::sent_date,
+            messages::message_from,
+            messages::message_to,
+            messages::message_cc,
+        ))
+        .load::<(
+            String,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<i64>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        )>(&mut conn)
+        .map_err(|e| e.to_string())?;
+    let mut accounts: HashMap<String, Vec<ThreadMessage>> = HashMap::new();
+    for (account, message_id, references, subject, sent_date, from, to, cc) in rows.into_iter() {
+        accounts.entry(account).or_default().push(ThreadMessage {
+            message_id,
+            references: references
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(|s| s.to_owned())
+                .collect(),
+            subject,
+            sent_date,
+            participants: participants(&[from.as_ref(), to.as_ref(), cc.as_ref()]),
+        });
+    }
+
+    let mut threaded = 0;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::delete(thread_references::table).execute(conn)?;
+        for (account, input) in accounts.iter() {
+            for message in input.iter() {
+                let rows = message
+                    .references
+                    .iter()
+                    .unique()
+                    .map(|reference| {
+                        (
+                            thread_references::account.eq(account),
+                            thread_references::message_id.eq(&message.message_id),
+                            thread_references::reference.eq(reference),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                diesel::insert_or_ignore_into(thread_references::table)
+                    .values(&rows)
+                    .execute(conn)?;
+            }
+            let keys = threading::thread(input);
+            for (message_id, key) in keys.iter() {
+                // gmail's thread id stays authoritative
+                diesel::update(
+                    messages::table
+                        .filter(messages::account.eq(account))
+                        .filter(messages::message_id.eq(message_id))
+                        .filter(messages::gm_thrid.is_null()),
+                )
+                .set(messages::parent_thread_key.eq(key))
+                .execute(conn)?;
+            }
+            threaded += keys.len();
+        }
+        Ok(())
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(threaded)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use diesel::migration::MigrationSource;
+
+    use super::*;
+
+    // an in-memory database shared by every connection to `name`, it lives as long as
+    // the connection returned with it
+    pub(crate) fn scratch_database(name: &str) -> (DatabaseConfig, SqliteConnection) {
+        let database_config = DatabaseConfig {
+            path: format!("file:{name}?mode=memory&cache=shared"),
+            password: None,
+            password_used: false,
+        };
+        let conn = connect(&database_config);
+        (database_config, conn)
+    }
+
+    fn save(database_config: &DatabaseConfig, account: &str, message_id: &str) -> SaveOutcome {
+        let raw = RawMessage {
+            id: None,
+            message_id: Some(message_id.to_owned()),
+            message: Some(format!("Message-ID: {message_id}\r\n\r\nbody").into_bytes()),
+            account: Some(account.to_owned()),
+        };
+        let record = Message {
+            account: account.to_owned(),
+            message_id: message_id.to_owned(),
+            folders: Some("INBOX".to_owned()),
+            parent_thread_key: Some(threading::thread_key(message_id)),
+            ..Default::default()
+        };
+        save_records(database_config, raw, record, Some(1)).unwrap()
+    }
+
+    fn connect(database_config: &DatabaseConfig) -> SqliteConnection {
+        establish_connection(Some((
+            database_config.path.as_str(),
+            &database_config.password,
+        )))
+    }
+
+    #[test]
+    fn message_targets_stay_in_their_account() {
+        let (database_config, mut conn) = scratch_database("target");
+        save(&database_config, "work", "<a@example.com>");
+        save(&database_config, "home", "<a@example.com>");
+        let target = Target::Message {
+            account: "work".to_owned(),
+            message_id: "<a@example.com>".to_owned(),
+        };
+        assert_eq!(set_pinned(&database_config, &target, true), Ok(1));
+
+        let pinned = messages::table
+            .filter(messages::pinned_at.is_not_null())
+            .select(messages::account)
+            .load::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(pinned, vec!["work".to_owned()]);
+        let queued = pending_changes::table
+            .select(pending_changes::account)
+            .load::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(queued, vec!["work".to_owned()]);
+    }
+
+    #[test]
+    fn saving_again_updates_in_place() {
+        let (database_config, mut conn) = scratch_database("resave");
+        assert_eq!(
+            save(&database_config, "work", "<a@example.com>"),
+            SaveOutcome::Inserted
+        );
+        let target = Target::Message {
+            account: "work".to_owned(),
+            message_id: "<a@example.com>".to_owned(),
+        };
+        set_pinned(&database_config, &target, true).unwrap();
+        assert_eq!(
+            save(&database_config, "work", "<a@example.com>"),
+            SaveOutcome::Updated
+        );
+
+        let stored = messages::table.load::<Message>(&mut conn).unwrap();
+        assert_eq!(stored.len(), 1);
+        // local state survives the update
+        assert!(stored[0].pinned_at.is_some());
+        let raw = raw_messages::table
+            .select(raw_messages::id)
+            .load::<Option<i32>>(&mut conn)
+            .unwrap();
+        assert_eq!(raw.len(), 1);
+        let folders = message_folders::table
+            .select(message_folders::folder)
+            .load::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(folders, vec!["INBOX".to_owned()]);
+    }
+
+    #[test]
+    fn dedupe_keeps_one_row_per_account() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        let migrations = MigrationSource::<Sqlite>::migrations(&MIGRATIONS).unwrap();
+        let dedupe = migrations
+            .iter()
+            .position(|m| m.name().to_string().contains("unique_messages"))
+            .expect("the dedupe migration");
+        for _ in 0..dedupe {
+            conn.run_next_migration(MIGRATIONS).unwrap();
+        }
+
+        // what older versions left behind: a copy per sync, state set on a later copy,
+        // the same message in another account and placeholder ids
+        diesel::sql_query(
+            "INSERT INTO messages (id, message_id, account, pinned_at, done_at) VALUES
+                (1, '<a@example.com>', 'work', NULL, NULL),
+                (2, '<a@example.com>', 'work', '2023-03-01', NULL),
+                (3, '<a@example.com>', 'work', NULL, '2023-03-02'),
+                (4, '<a@example.com>', 'home', NULL, NULL),
+                (5, 'no id found!', 'work', NULL, NULL),
+                (6, 'no id found!', 'work', NULL, NULL)",
+        )
+        .execute(&mut conn)
+        .unwrap();
+        diesel::sql_query(
+            "INSERT INTO raw_messages (id, message_id, message) VALUES
+                (1, '<a@example.com>', 'a'),
+                (2, '<a@example.com>', 'a'),
+                (3, '<a@example.com>', 'a'),
+                (4, '<a@example.com>', 'a'),
+                (5, 'no id found!', 'b'),
+                (6, 'no id found!', 'c')",
+        )
+        .execute(&mut conn)
+        .unwrap();
+        run_migration(&mut conn);
+
+        let stored = messages::table
+            .order(messages::id)
+            .load::<Message>(&mut conn)
+            .unwrap();
+        let rows = stored
+            .iter()
+            .map(|m| (m.id, m.account.as_str(), m.message_id.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                (Some(1), "work", "<a@example.com>"),
+                (Some(4), "home", "<a@example.com>"),
+                (Some(5), "work", "<legacy-5@bes.local>"),
+                (Some(6), "work", "<legacy-6@bes.local>"),
+            ]
+        );
+        // the oldest copy took over what was set on the others
+        assert_eq!(stored[0].pinned_at.as_deref(), Some("2023-03-01"));
+        assert_eq!(stored[0].done_at.as_deref(), Some("2023-03-02"));
+
+        let raw = raw_messages::table
+            .order(raw_messages::id)
+            .select((
+                raw_messages::id,
+                raw_messages::account,
+                raw_messages::message_id,
+            ))
+            .load::<(Option<i32>, Option<String>, Option<String>)>(&mut conn)
+            .unwrap();
+        let raw = raw
+            .iter()
+            .map(|(id, account, message_id)| (*id, account.as_deref(), message_id.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            raw,
+            vec![
+                (Some(1), Some("work"), Some("<a@example.com>")),
+                (Some(4), Some("home"), Some("<a@example.com>")),
+                (Some(5), Some("work"), Some("<legacy-5@bes.local>")),
+                (Some(6), Some("work"), Some("<legacy-6@bes.local>")),
+            ]
+        );
+
+        // and it can't happen again
+        let duplicate = diesel::sql_query(
+            "INSERT INTO messages (message_id, account) VALUES ('<a@example.com>', 'work')",
+        )
+        .execute(&mut conn);
+        assert!(duplicate.is_err());
+    }
 }
-/*
-use to fix up threads.
-WITH RECURSIVE CTE AS (
-  SELECT m.id, m.message_id, m.parent_id,CAST(m.id AS varchar) AS path
-  FROM messages m
-  WHERE parent_id is null
-  UNION ALL
-  SELECT m1.id, m1.message_id, m1.parent_id,CAST(m1.id AS varchar) || ',' || path
-  FROM messages m1
-  JOIN CTE ON m1.parent_id = CTE.message_id
-)
-SELECT *
-FROM CTE
-where instr(path, ',') > 0;
-*/
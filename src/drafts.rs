@@ -0,0 +1,158 @@
+use chrono::Utc;
+
+use crate::{
+    config::{AccountConfig, DatabaseConfig},
+    database, imap_session,
+    log::log,
+    models::Draft,
+    send::{self, OutgoingMessage},
+    threading,
+};
+
+// how often the compose window saves what is being written
+pub const AUTOSAVE_SECS: u64 = 5;
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+// nothing typed yet, not worth a draft
+pub fn is_blank(outgoing: &OutgoingMessage) -> bool {
+    [
+        &outgoing.to,
+        &outgoing.cc,
+        &outgoing.bcc,
+        &outgoing.subject,
+        &outgoing.body,
+    ]
+    .iter()
+    .all(|field| field.trim().is_empty())
+        && outgoing.forward_of.is_none()
+}
+
+// stores the composition locally, returns the draft id and the Message-ID it keeps
+pub fn save(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    outgoing: &OutgoingMessage,
+) -> Result<(i32, String), String> {
+    let message_id = outgoing
+        .message_id
+        .clone()
+        .unwrap_or_else(|| send::new_message_id(account));
+    // replies show up at the bottom of the thread they answer
+    let parent_thread_key = outgoing.in_reply_to.as_ref().and_then(|parent| {
+        database::get_message(database_config, &account.name, parent)
+            .ok()
+            .and_then(|message| message.parent_thread_key)
+    });
+    let now = database::db_timestamp(Utc::now());
+    let id = database::save_draft(
+        database_config,
+        Draft {
+            id: outgoing.draft_id,
+            account: account.name.clone(),
+            message_id: message_id.clone(),
+            message_to: non_empty(&outgoing.to),
+            message_cc: non_empty(&outgoing.cc),
+            message_bcc: non_empty(&outgoing.bcc),
+            subject: non_empty(&outgoing.subject),
+            body: non_empty(&outgoing.body),
+            in_reply_to: outgoing.in_reply_to.clone(),
+            message_references: non_empty(&outgoing.references.join(" ")),
+            forward_of: outgoing.forward_of.clone(),
+            parent_thread_key,
+            created_at: now.clone(),
+            updated_at: now,
+            uploaded_at: None,
+        },
+    )?;
+    Ok((id, message_id))
+}
+
+// back into the compose window
+pub fn open(database_config: &DatabaseConfig, id: i32) -> Result<OutgoingMessage, String> {
+    let draft = database::get_draft(database_config, id)?;
+    let attachments = match &draft.forward_of {
+        Some(original) => vec![send::forward_attachment(
+            database_config,
+            &draft.account,
+            original,
+        )?],
+        None => vec![],
+    };
+    Ok(OutgoingMessage {
+        to: draft.message_to.unwrap_or_default(),
+        cc: draft.message_cc.unwrap_or_default(),
+        bcc: draft.message_bcc.unwrap_or_default(),
+        subject: draft.subject.unwrap_or_default(),
+        body: draft.body.unwrap_or_default(),
+        in_reply_to: draft.in_reply_to,
+        references: threading::message_ids(&draft.message_references.unwrap_or_default()),
+        attachments,
+        message_id: Some(draft.message_id),
+        draft_id: draft.id,
+        forward_of: draft.forward_of,
+    })
+}
+
+// copies the draft into the server's drafts folder when the account wants that
+pub fn upload(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    id: i32,
+) -> Result<(), String> {
+    if !account.drafts.upload {
+        return Ok(());
+    }
+    let outgoing = open(database_config, id)?;
+    let message = send::build(account, &outgoing, Utc::now())?;
+    let message_id = outgoing.message_id.unwrap_or_default();
+    let mut session = imap_session::connect(account)?;
+    let result = imap_session::replace_draft(
+        &mut session,
+        &account.drafts.folder,
+        &message_id,
+        &message.formatted(),
+    );
+    session.logout().ok();
+    result?;
+    database::draft_uploaded(database_config, id, &database::db_timestamp(Utc::now()))
+}
+
+// gone locally, and from the server when it was uploaded
+pub fn discard(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    id: i32,
+) -> Result<(), String> {
+    let draft = forget(database_config, id)?;
+    remove_uploaded(account, &draft);
+    Ok(())
+}
+
+// gone locally, returns it for `remove_uploaded`
+pub fn forget(database_config: &DatabaseConfig, id: i32) -> Result<Draft, String> {
+    let draft = database::get_draft(database_config, id)?;
+    database::delete_draft(database_config, id)?;
+    Ok(draft)
+}
+
+// a stale copy on the server isn't worth keeping the local one for, failures are only logged
+pub fn remove_uploaded(account: &AccountConfig, draft: &Draft) {
+    if draft.uploaded_at.is_none() {
+        return;
+    }
+    let removed = imap_session::connect(account).and_then(|mut session| {
+        imap_session::select(&mut session, &account.drafts.folder)?;
+        let removed = imap_session::delete_message(&mut session, &draft.message_id);
+        session.logout().ok();
+        removed
+    });
+    if let Err(e) = removed {
+        log(format!(
+            "removing draft {} from the server: {}",
+            draft.message_id, e
+        ));
+    }
+}
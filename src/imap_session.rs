@@ -0,0 +1,592 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+use imap::types::Flag;
+use imap_proto::NameAttribute;
+use native_tls::TlsStream;
+use utf7_imap::decode_utf7_imap;
+
+use crate::{config::AccountConfig, log::log, messages::MessageFilter, oauth};
+
+// himalaya hides uids and UIDVALIDITY so incremental sync talks to the server directly.
+pub type Session = imap::Session<TlsStream<TcpStream>>;
+
+const FETCH_CHUNK: usize = 50;
+// without CONDSTORE only the labels of this many of the newest messages are refreshed
+const LABEL_WINDOW: usize = 500;
+// transient connection failures are retried after 1, 2, 4 and 8 seconds
+pub const CONNECT_ATTEMPTS: u32 = 5;
+pub const RETRY_START: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+pub struct FolderStatus {
+    pub uid_validity: u32,
+    pub uid_next: Option<u32>,
+    pub exists: u32,
+    // only sent by servers with CONDSTORE
+    pub highest_modseq: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchedMessage {
+    pub uid: u32,
+    pub flags: Vec<String>,
+    pub raw: Vec<u8>,
+}
+
+// X-GM-EXT-1 attributes of one message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GmailAttributes {
+    pub msgid: Option<u64>,
+    pub thrid: Option<u64>,
+    pub labels: Vec<String>,
+}
+
+pub fn connect(account: &AccountConfig) -> Result<Session, String> {
+    let mut delay = RETRY_START;
+    let mut attempt = 1;
+    loop {
+        match try_connect(account) {
+            Err((e, true)) if attempt < CONNECT_ATTEMPTS => {
+                log(format!("{e}, trying again in {}s", delay.as_secs()));
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result.map_err(|(e, _)| e),
+        }
+    }
+}
+
+// the flag says whether trying again could help
+fn try_connect(account: &AccountConfig) -> Result<Session, (String, bool)> {
+    let (host, port) = account.imap_host();
+    let client = imap::ClientBuilder::new(host.as_str(), port)
+        .native_tls()
+        .map_err(|e| {
+            (
+                format!("connecting to {host}:{port}: {e}"),
+                is_transient(&e),
+            )
+        })?;
+    let session = if account.oauth2.is_some() {
+        let authenticator = oauth::XOAuth2 {
+            user: account.imap_login(),
+            access_token: oauth::access_token(account).map_err(|e| (e, false))?,
+        };
+        client.authenticate("XOAUTH2", &authenticator)
+    } else {
+        let password = account.imap_password().map_err(|e| (e, false))?;
+        client.login(account.imap_login(), password)
+    };
+    session.map_err(|(e, _)| {
+        (
+            format!("login for {}: {}", account.name, e),
+            is_transient(&e),
+        )
+    })
+}
+
+// network trouble, as opposed to the server saying no
+fn is_transient(e: &imap::Error) -> bool {
+    matches!(e, imap::Error::Io(_) | imap::Error::ConnectionLost)
+}
+
+// false once the server or the network dropped the connection
+pub fn is_alive(session: &mut Session) -> bool {
+    session.noop().is_ok()
+}
+
+// every folder that can be selected
+pub fn list_folders(session: &mut Session) -> Result<Vec<String>, String> {
+    let names = session
+        .list(None, Some("*"))
+        .map_err(|e| format!("list: {e}"))?;
+    Ok(names
+        .iter()
+        .filter(|name| !name.attributes().contains(&NameAttribute::NoSelect))
+        .map(|name| name.name().to_owned())
+        .collect())
+}
+
+// the folders the server marks with one of `uses` (RFC 6154), like \Sent or \All.
+// their names differ between servers and languages.
+pub fn special_use_folders(
+    session: &mut Session,
+    uses: &[NameAttribute],
+) -> Result<Vec<String>, String> {
+    let names = session
+        .list(None, Some("*"))
+        .map_err(|e| format!("list: {e}"))?;
+    Ok(names
+        .iter()
+        .filter(|name| name.attributes().iter().any(|a| uses.contains(a)))
+        .map(|name| name.name().to_owned())
+        .collect())
+}
+
+// stored uids are only good for as long as UIDVALIDITY stays the same, a folder
+// that doesn't report one can't be synced by uid at all
+pub fn select(session: &mut Session, folder: &str) -> Result<FolderStatus, String> {
+    let mailbox = session
+        .select(folder)
+        .map_err(|e| format!("select {folder}: {e}"))?;
+    let uid_validity = mailbox
+        .uid_validity
+        .ok_or_else(|| format!("select {folder}: the server sent no UIDVALIDITY"))?;
+    Ok(FolderStatus {
+        uid_validity,
+        uid_next: mailbox.uid_next,
+        exists: mailbox.exists,
+        highest_modseq: mailbox.highest_mod_seq,
+    })
+}
+
+// uids above `after_uid` in ascending order. when `limit` is set only the newest are kept.
+pub fn uids_after(
+    session: &mut Session,
+    after_uid: u32,
+    limit: Option<u32>,
+) -> Result<Vec<u32>, String> {
+    let found = session
+        .uid_search(format!("UID {}:*", after_uid.saturating_add(1)))
+        .map_err(|e| format!("uid search: {e}"))?;
+    // "n:*" always matches the highest uid, even when it is below n
+    let mut uids = found
+        .into_iter()
+        .filter(|uid| *uid > after_uid)
+        .collect::<Vec<_>>();
+    uids.sort_unstable();
+    if let Some(limit) = limit {
+        let skip = uids.len().saturating_sub(limit as usize);
+        uids.drain(..skip);
+    }
+    Ok(uids)
+}
+
+// every uid in the selected folder
+pub fn all_uids(session: &mut Session) -> Result<HashSet<u32>, String> {
+    session
+        .uid_search("ALL")
+        .map_err(|e| format!("uid search: {e}"))
+}
+
+// uids in the selected folder that are flagged \Seen
+pub fn seen_uids(session: &mut Session) -> Result<HashSet<u32>, String> {
+    session
+        .uid_search("SEEN")
+        .map_err(|e| format!("uid search: {e}"))
+}
+
+// uids above `after_uid` whose internal date falls inside the window of `filter`,
+// oldest first
+pub fn search_dates(
+    session: &mut Session,
+    filter: &MessageFilter,
+    after_uid: u32,
+) -> Result<Vec<u32>, String> {
+    let mut query = vec![format!("UID {}:*", after_uid.saturating_add(1))];
+    if let Some(since) = filter.since {
+        query.push(format!("SINCE {}", since.format("%-d-%b-%Y")));
+    }
+    if let Some(before) = filter.before {
+        query.push(format!("BEFORE {}", before.format("%-d-%b-%Y")));
+    }
+    let found = session
+        .uid_search(query.join(" "))
+        .map_err(|e| format!("uid search: {e}"))?;
+    let mut uids = found
+        .into_iter()
+        .filter(|uid| *uid > after_uid)
+        .collect::<Vec<_>>();
+    uids.sort_unstable();
+    Ok(uids)
+}
+
+pub fn fetch_uids(session: &mut Session, uids: &[u32]) -> Result<Vec<FetchedMessage>, String> {
+    let mut messages = vec![];
+    for chunk in uids.chunks(FETCH_CHUNK) {
+        let set = chunk
+            .iter()
+            .map(|uid| uid.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let fetches = session
+            .uid_fetch(set, "(UID FLAGS BODY.PEEK[])")
+            .map_err(|e| format!("uid fetch: {e}"))?;
+        for fetch in fetches.iter() {
+            if let (Some(uid), Some(body)) = (fetch.uid, fetch.body()) {
+                messages.push(FetchedMessage {
+                    uid,
+                    flags: fetch.flags().iter().map(|f| f.to_string()).collect(),
+                    raw: body.to_vec(),
+                });
+            }
+        }
+    }
+    messages.sort_by_key(|m| m.uid);
+    Ok(messages)
+}
+
+// flags and expunges the message with this Message-ID in the selected folder
+pub fn delete_message(session: &mut Session, message_id: &str) -> Result<bool, String> {
+    let Some(uid) = find_uid(session, message_id)? else {
+        return Ok(false);
+    };
+    session
+        .uid_store(uid.to_string(), "+FLAGS (\\Deleted)")
+        .map_err(|e| format!("delete {message_id}: {e}"))?;
+    session
+        .uid_expunge(uid.to_string())
+        .map_err(|e| format!("expunge {message_id}: {e}"))?;
+    Ok(true)
+}
+
+// puts `raw` in `folder` as a draft, replacing an earlier version with the same Message-ID
+pub fn replace_draft(
+    session: &mut Session,
+    folder: &str,
+    message_id: &str,
+    raw: &[u8],
+) -> Result<(), String> {
+    select(session, folder)?;
+    delete_message(session, message_id)?;
+    session
+        .append(folder, raw)
+        .flag(Flag::Draft)
+        .flag(Flag::Seen)
+        .finish()
+        .map(|_| ())
+        .map_err(|e| format!("append to {folder}: {e}"))
+}
+
+// uid of a message in the selected folder
+pub fn find_uid(session: &mut Session, message_id: &str) -> Result<Option<u32>, String> {
+    let query = format!("HEADER Message-ID \"{}\"", message_id.replace('"', ""));
+    let uids = session
+        .uid_search(query)
+        .map_err(|e| format!("uid search {message_id}: {e}"))?;
+    Ok(uids.into_iter().max())
+}
+
+pub fn has_capability(session: &mut Session, capability: &str) -> bool {
+    session
+        .capabilities()
+        .map(|capabilities| capabilities.has_str(capability))
+        .unwrap_or(false)
+}
+
+pub fn has_gmail_extensions(session: &mut Session) -> bool {
+    has_capability(session, "X-GM-EXT-1")
+}
+
+// IDLE on the selected folder until the server reports a change or `timeout` passes,
+// true on a change. without keepalive a timeout ends the wait instead of idling again.
+pub fn wait_for_change(session: &mut Session, timeout: Duration) -> Result<bool, String> {
+    let outcome = session
+        .idle()
+        .timeout(timeout)
+        .keepalive(false)
+        .wait_while(imap::extensions::idle::stop_on_any)
+        .map_err(|e| format!("idle: {e}"))?;
+    Ok(outcome == imap::extensions::idle::WaitOutcome::MailboxChanged)
+}
+
+// imap-proto doesn't know X-GM-THRID, so the gmail attributes come from a second
+// raw FETCH that is parsed here
+pub fn fetch_gmail(
+    session: &mut Session,
+    uids: &[u32],
+) -> Result<HashMap<u32, GmailAttributes>, String> {
+    let mut found = HashMap::new();
+    for chunk in uids.chunks(FETCH_CHUNK) {
+        let set = chunk
+            .iter()
+            .map(|uid| uid.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        found.extend(gmail_fetch(
+            session,
+            &set,
+            "UID X-GM-MSGID X-GM-THRID X-GM-LABELS",
+            None,
+        )?);
+    }
+    Ok(found)
+}
+
+// labels of stored messages, they change on the server after the message was
+// fetched. with `changed_since` only what changed after that modseq is asked for,
+// otherwise the newest LABEL_WINDOW of `uids`. only `labels` is filled in.
+pub fn fetch_gmail_labels(
+    session: &mut Session,
+    uids: &[u32],
+    changed_since: Option<u64>,
+) -> Result<HashMap<u32, GmailAttributes>, String> {
+    let Some(last_uid) = uids.iter().max() else {
+        return Ok(HashMap::new());
+    };
+    if let Some(modseq) = changed_since {
+        return gmail_fetch(
+            session,
+            &format!("1:{last_uid}"),
+            "UID X-GM-LABELS",
+            Some(modseq),
+        );
+    }
+    let mut uids = uids.to_vec();
+    uids.sort_unstable();
+    let recent = &uids[uids.len().saturating_sub(LABEL_WINDOW)..];
+    let mut found = HashMap::new();
+    for chunk in recent.chunks(FETCH_CHUNK) {
+        let set = chunk
+            .iter()
+            .map(|uid| uid.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        found.extend(gmail_fetch(session, &set, "UID X-GM-LABELS", None)?);
+    }
+    Ok(found)
+}
+
+fn gmail_fetch(
+    session: &mut Session,
+    set: &str,
+    items: &str,
+    changed_since: Option<u64>,
+) -> Result<HashMap<u32, GmailAttributes>, String> {
+    let modifier = changed_since
+        .map(|modseq| format!(" (CHANGEDSINCE {modseq})"))
+        .unwrap_or_default();
+    let response = session
+        .run_command_and_read_response(format!("UID FETCH {set} ({items}){modifier}"))
+        .map_err(|e| format!("gmail fetch: {e}"))?;
+    Ok(parse_gmail_fetch(&response))
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Atom(String),
+    List(Vec<Token>),
+    Nil,
+}
+
+fn parse_gmail_fetch(response: &[u8]) -> HashMap<u32, GmailAttributes> {
+    let marker = b"FETCH (";
+    let mut found = HashMap::new();
+    let mut rest = response;
+    while let Some(start) = rest.windows(marker.len()).position(|w| w == marker) {
+        // keep the "(" so the attributes read as one list
+        let (token, remaining) = read_token(&rest[start + marker.len() - 1..]);
+        rest = remaining;
+        let items = match token {
+            Some(Token::List(items)) => items,
+            _ => continue,
+        };
+        let mut uid = None;
+        let mut attributes = GmailAttributes::default();
+        for pair in items.chunks(2) {
+            match pair {
+                [Token::Atom(key), Token::Atom(value)] => match key.to_uppercase().as_str() {
+                    "UID" => uid = value.parse().ok(),
+                    "X-GM-MSGID" => attributes.msgid = value.parse().ok(),
+                    "X-GM-THRID" => attributes.thrid = value.parse().ok(),
+                    _ => {}
+                },
+                [Token::Atom(key), Token::List(labels)]
+                    if key.eq_ignore_ascii_case("X-GM-LABELS") =>
+                {
+                    attributes.labels = labels
+                        .iter()
+                        .filter_map(|label| match label {
+                            // system labels come as \Inbox, \Important, ...
+                            Token::Atom(label) if label.starts_with('\\') => {
+                                Some(label.trim_start_matches('\\').to_owned())
+                            }
+                            // user labels are named like mailboxes, in modified UTF-7
+                            Token::Atom(label) => Some(decode_utf7_imap(label.clone())),
+                            Token::List(_) | Token::Nil => None,
+                        })
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+        if let Some(uid) = uid {
+            found.insert(uid, attributes);
+        }
+    }
+    found
+}
+
+// one atom, quoted string, {n} literal or parenthesized list
+fn read_token(input: &[u8]) -> (Option<Token>, &[u8]) {
+    let start = input
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(input.len());
+    let input = &input[start..];
+    match input.first() {
+        None => (None, input),
+        Some(b'(') => {
+            let mut items = vec![];
+            let mut rest = &input[1..];
+            loop {
+                let start = rest
+                    .iter()
+                    .position(|b| !b.is_ascii_whitespace())
+                    .unwrap_or(rest.len());
+                rest = &rest[start..];
+                if rest.first() == Some(&b')') {
+                    return (Some(Token::List(items)), &rest[1..]);
+                }
+                match read_token(rest) {
+                    (Some(token), remaining) => {
+                        items.push(token);
+                        rest = remaining;
+                    }
+                    (None, remaining) => return (Some(Token::List(items)), remaining),
+                }
+            }
+        }
+        Some(b'"') => {
+            let mut value = vec![];
+            let mut escaped = false;
+            for (i, b) in input.iter().enumerate().skip(1) {
+                match (escaped, b) {
+                    (false, b'\\') => escaped = true,
+                    (false, b'"') => {
+                        let value = String::from_utf8_lossy(&value).into_owned();
+                        return (Some(Token::Atom(value)), &input[i + 1..]);
+                    }
+                    _ => {
+                        value.push(*b);
+                        escaped = false;
+                    }
+                }
+            }
+            (None, &input[input.len()..])
+        }
+        Some(b'{') => {
+            let end = input.iter().position(|b| *b == b'}').unwrap_or(input.len());
+            let size = std::str::from_utf8(&input[1..end])
+                .ok()
+                .and_then(|n| n.parse::<usize>().ok())
+                .unwrap_or_default();
+            let body = (end + 1..input.len())
+                .find(|i| input[*i] == b'\n')
+                .map(|i| i + 1)
+                .unwrap_or(input.len());
+            let stop = (body + size).min(input.len());
+            let value = String::from_utf8_lossy(&input[body..stop]).into_owned();
+            (Some(Token::Atom(value)), &input[stop..])
+        }
+        Some(_) => {
+            let end = input
+                .iter()
+                .position(|b| b.is_ascii_whitespace() || *b == b'(' || *b == b')')
+                .unwrap_or(input.len());
+            let value = String::from_utf8_lossy(&input[..end]).into_owned();
+            // a quoted "NIL" is a string, this one isn't
+            if value.eq_ignore_ascii_case("NIL") {
+                return (Some(Token::Nil), &input[end..]);
+            }
+            (Some(Token::Atom(value)), &input[end..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(response: &str) -> Vec<String> {
+        parse_gmail_fetch(response.as_bytes())
+            .remove(&7)
+            .expect("uid 7")
+            .labels
+    }
+
+    #[test]
+    fn reads_tokens() {
+        assert_eq!(
+            read_token(b"  atom rest").0,
+            Some(Token::Atom("atom".to_string()))
+        );
+        assert_eq!(read_token(b"NIL)").0, Some(Token::Nil));
+        assert_eq!(
+            read_token(b"\"NIL\"").0,
+            Some(Token::Atom("NIL".to_string()))
+        );
+        assert_eq!(
+            read_token(br#""say \"hi\" \\ back""#).0,
+            Some(Token::Atom(r#"say "hi" \ back"#.to_string()))
+        );
+        assert_eq!(
+            read_token(b"{5}\r\nhello world").0,
+            Some(Token::Atom("hello".to_string()))
+        );
+        assert_eq!(
+            read_token(b"(a (b) )").0,
+            Some(Token::List(vec![
+                Token::Atom("a".to_string()),
+                Token::List(vec![Token::Atom("b".to_string())]),
+            ]))
+        );
+        // unterminated input ends the token instead of running past it
+        assert_eq!(read_token(b"\"open").0, None);
+        assert_eq!(
+            read_token(b"(a").0,
+            Some(Token::List(vec![Token::Atom("a".to_string())]))
+        );
+    }
+
+    #[test]
+    fn parses_gmail_fetch() {
+        let response = "* 3 FETCH (X-GM-THRID 1278455344230334865 UID 7 \
+                        X-GM-MSGID 1278455344230334866 X-GM-LABELS (\\Inbox \\Important))\r\n\
+                        * 4 FETCH (UID 8 X-GM-LABELS ())\r\n\
+                        a1 OK Success\r\n";
+        let found = parse_gmail_fetch(response.as_bytes());
+        assert_eq!(
+            found[&7],
+            GmailAttributes {
+                msgid: Some(1278455344230334866),
+                thrid: Some(1278455344230334865),
+                labels: vec!["Inbox".to_string(), "Important".to_string()],
+            }
+        );
+        assert!(found[&8].labels.is_empty());
+    }
+
+    #[test]
+    fn parses_gmail_labels() {
+        assert_eq!(
+            labels(r#"* 1 FETCH (UID 7 X-GM-LABELS ("My Label" "say \"hi\"" \Sent))"#),
+            vec!["My Label", "say \"hi\"", "Sent"]
+        );
+        // user labels are modified UTF-7
+        assert_eq!(
+            labels(r#"* 1 FETCH (UID 7 X-GM-LABELS ("R&AOk-sum&AOk-"))"#),
+            vec!["Résumé"]
+        );
+        assert_eq!(
+            labels("* 1 FETCH (UID 7 X-GM-LABELS ({8}\r\nTwo Word \\Starred))"),
+            vec!["Two Word", "Starred"]
+        );
+        assert!(labels("* 1 FETCH (UID 7 X-GM-LABELS NIL)").is_empty());
+        assert_eq!(
+            labels(r#"* 1 FETCH (UID 7 X-GM-LABELS (NIL "NIL"))"#),
+            vec!["NIL"]
+        );
+        // MODSEQ comes along with CHANGEDSINCE
+        assert_eq!(
+            labels("* 1 FETCH (UID 7 MODSEQ (12345) X-GM-LABELS (\\Inbox))"),
+            vec!["Inbox"]
+        );
+    }
+}
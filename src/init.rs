@@ -12,7 +12,7 @@ pub async fn init(args: ConfigArgs) -> io::Result<()> {
 
     let account_file = args
         .account_file
-        .unwrap_or_else(|| config::default_account_path());
+        .unwrap_or_else(config::default_account_path);
 
     let database_file = args
         .database_file
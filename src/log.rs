@@ -1,8 +1,9 @@
 use std::fmt::Debug;
+// stderr, stdout is left to what a command reports
 pub fn debug_log<T: Debug>(message: T) {
-    println!("{message:#?}")
+    eprintln!("{message:#?}")
 }
 
 pub fn log<T: ToString>(message: T) {
-    println!("{}", message.to_string())
+    eprintln!("{}", message.to_string())
 }
@@ -1,28 +1,36 @@
 #![allow(non_snake_case)]
 use clap::Parser;
-use dioxus::prelude::*;
 use dioxus_desktop::{wry, Config, WindowBuilder};
-use std::{
-    cell::Cell,
-    path::{Path, PathBuf},
-};
-use tokio;
+use std::{cell::Cell, path::PathBuf};
 
 use crate::{
     app::{App, AppProps, ViewFilter},
     config::DatabaseConfig,
+    sync::{Progress, SyncReport},
 };
 
 mod app;
 mod components;
 mod config;
+mod credentials;
+mod daemon;
 mod database;
+mod drafts;
+mod imap_session;
 mod init;
 mod log;
 mod messages;
 mod models;
+mod oauth;
+mod outbox;
+mod push;
+mod query;
 mod schema;
+mod send;
+mod snooze;
 mod sync;
+mod threading;
+mod write_back;
 
 #[derive(Parser)] // requires `derive` feature
 #[command(name = "cargo")]
@@ -33,6 +41,77 @@ enum Args {
     Init(ConfigArgs),
     Run(RunArgs),
     DebugMessage(DebugMessageArgs),
+    Rethread(RethreadArgs),
+    Wake(WakeArgs),
+    Daemon(DaemonArgs),
+    Login(LoginArgs),
+    SetCredential(SetCredentialArgs),
+    /// prints the imap password of an account, what himalaya runs as passwd_cmd
+    #[command(hide = true)]
+    Password(PasswordArgs),
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct SetCredentialArgs {
+    /// the name to use as passwd_file in an account
+    name: String,
+}
+
+#[derive(clap::Args)]
+pub struct PasswordArgs {
+    account_file: PathBuf,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct LoginArgs {
+    /// account name, may be left out when only one account uses oauth2
+    #[arg(long)]
+    account: Option<String>,
+    /// enter a code on another device instead of redirecting a local browser
+    #[arg(long)]
+    device: bool,
+    #[arg(short)]
+    config_file: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct DaemonArgs {
+    #[arg(short)]
+    config_file: Option<PathBuf>,
+    #[arg(short)]
+    database_file: Option<PathBuf>,
+    #[arg(short)]
+    password: Option<String>,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct WakeArgs {
+    /// keep running and check every `interval` seconds
+    #[arg(long)]
+    watch: bool,
+    #[arg(long, default_value_t = 60)]
+    interval: u64,
+    #[arg(short)]
+    config_file: Option<PathBuf>,
+    #[arg(short)]
+    database_file: Option<PathBuf>,
+    #[arg(short)]
+    password: Option<String>,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct RethreadArgs {
+    #[arg(short)]
+    config_file: Option<PathBuf>,
+    #[arg(short)]
+    database_file: Option<PathBuf>,
+    #[arg(short)]
+    password: Option<String>,
 }
 
 #[derive(clap::Args, Clone)]
@@ -79,6 +158,9 @@ pub struct ConfigArgs {
 pub struct LoadArgs {
     #[arg(long)]
     path: PathBuf,
+    /// print the sync report as json
+    #[arg(long)]
+    json: bool,
     #[arg(short)]
     config_file: Option<PathBuf>,
     #[arg(short)]
@@ -92,9 +174,23 @@ pub struct LoadArgs {
 pub struct SyncArgs {
     #[arg(long)]
     count: Option<u32>,
-    #[arg(short)]
+    /// ignore the stored sync state and pull the newest `count` envelopes
+    #[arg(long)]
+    latest: bool,
+    /// try the messages that failed to store in earlier runs again
+    #[arg(long)]
+    retry_failed: bool,
+    /// print the sync report as json
+    #[arg(long)]
+    json: bool,
+    /// accounts to sync at the same time
+    #[arg(long, default_value_t = config::default_jobs())]
+    jobs: usize,
+    /// backfill mail from this day on, YYYY-MM-DD
+    #[arg(long)]
     start_time: Option<String>,
-    #[arg(short)]
+    /// backfill mail up to and including this day, YYYY-MM-DD
+    #[arg(long)]
     end_time: Option<String>,
     #[arg(short)]
     config_file: Option<PathBuf>,
@@ -139,9 +235,18 @@ async fn main() {
             } else {
                 config::get_database(&args.config_file)
             };
-            sync::load_files(&database_config, &args.path, None)
-                .await
-                .expect("sync");
+            let accounts =
+                config::get_accounts(args.config_file.unwrap_or_else(config::default_config_path));
+            let report = sync::load_files(
+                &database_config,
+                &args.path,
+                &accounts,
+                None,
+                &Progress::default(),
+            )
+            .await
+            .expect("sync");
+            print_report(&report, args.json);
         }
         Args::Sync(args) => {
             let database_config = if let Some(database_file) = args.database_file {
@@ -156,14 +261,153 @@ async fn main() {
             } else {
                 config::get_database(&args.config_file)
             };
-            sync::sync_count(&database_config, args.count.unwrap_or(10), None)
+            let accounts =
+                config::get_accounts(args.config_file.unwrap_or_else(config::default_config_path));
+            let progress = Progress::default();
+            let report = if args.retry_failed {
+                sync::retry_failed(&database_config, &accounts, &progress).await
+            } else if args.start_time.is_some() || args.end_time.is_some() {
+                let since = args
+                    .start_time
+                    .map(|date| sync::parse_date(&date).expect("start time"));
+                let until = args
+                    .end_time
+                    .map(|date| sync::parse_date(&date).expect("end time"));
+                sync::backfill(
+                    &database_config,
+                    since,
+                    until,
+                    &accounts,
+                    None,
+                    args.jobs,
+                    &progress,
+                )
                 .await
-                .expect("sync");
+            } else if args.latest {
+                sync::sync_count(
+                    &database_config,
+                    args.count.unwrap_or(10),
+                    &accounts,
+                    None,
+                    args.jobs,
+                    &progress,
+                )
+                .await
+            } else {
+                sync::sync_incremental(
+                    &database_config,
+                    args.count.unwrap_or(10),
+                    &accounts,
+                    None,
+                    args.jobs,
+                    &progress,
+                )
+                .await
+            }
+            .expect("sync");
+            print_report(&report, args.json);
+        }
+        Args::Rethread(args) => {
+            let database_config = if let Some(database_file) = args.database_file {
+                DatabaseConfig {
+                    path: database_file
+                        .to_str()
+                        .map(|s| s.to_string())
+                        .expect("database path"),
+                    password_used: args.password.is_some(),
+                    password: args.password,
+                }
+            } else {
+                config::get_database(&args.config_file)
+            };
+            let count = database::rethread(&database_config).expect("rethread");
+            println!("rethreaded {count} messages");
+        }
+        Args::Wake(args) => {
+            let database_config = if let Some(database_file) = args.database_file {
+                DatabaseConfig {
+                    path: database_file
+                        .to_str()
+                        .map(|s| s.to_string())
+                        .expect("database path"),
+                    password_used: args.password.is_some(),
+                    password: args.password,
+                }
+            } else {
+                config::get_database(&args.config_file)
+            };
+            if args.watch {
+                snooze::run(
+                    database_config,
+                    std::time::Duration::from_secs(args.interval),
+                )
+                .await;
+            } else {
+                let woken = snooze::wake_due(&database_config).expect("wake");
+                println!("woke {woken} snoozed messages");
+            }
+        }
+        Args::Daemon(args) => {
+            let database_config = if let Some(database_file) = args.database_file {
+                DatabaseConfig {
+                    path: database_file
+                        .to_str()
+                        .map(|s| s.to_string())
+                        .expect("database path"),
+                    password_used: args.password.is_some(),
+                    password: args.password,
+                }
+            } else {
+                config::get_database(&args.config_file)
+            };
+            daemon::run(database_config, args.config_file)
+                .await
+                .expect("daemon");
+        }
+        Args::Login(args) => {
+            let accounts =
+                config::get_accounts(args.config_file.unwrap_or_else(config::default_config_path))
+                    .into_iter()
+                    .filter(|account| account.oauth2.is_some())
+                    .filter(|account| {
+                        args.account
+                            .as_ref()
+                            .is_none_or(|name| *name == account.name)
+                    })
+                    .collect::<Vec<_>>();
+            let account = match accounts.as_slice() {
+                [account] => account,
+                [] => panic!("no matching account with an [oauth2] config"),
+                _ => panic!("more than one account uses oauth2, pick one with --account"),
+            };
+            let path = oauth::login(account, args.device).expect("login");
+            println!(
+                "signed in {}, tokens are in {}",
+                account.name,
+                path.display()
+            );
+        }
+        Args::SetCredential(args) => {
+            let secret =
+                rpassword::prompt_password(format!("secret for {}: ", args.name)).expect("secret");
+            let path = config::default_credentials_path();
+            credentials::set(&path, &args.name, &secret).expect("credentials");
+            println!("saved {} in {}", args.name, path.display());
+        }
+        Args::Password(args) => {
+            let account = config::get_account_config(&args.account_file.to_string_lossy())
+                .expect("account config");
+            match account.imap_password() {
+                Ok(password) => println!("{password}"),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
         }
         Args::Init(args) => init::init(args).await.expect("missing init"),
         Args::Run(args) => {
             let view = ViewFilter::default();
-            let config = app::Config::default();
             let database_config = if let Some(database_file) = args.database_file {
                 DatabaseConfig {
                     path: database_file
@@ -176,12 +420,9 @@ async fn main() {
             } else {
                 config::get_database(&args.config_file)
             };
-            let account_config = config::get_accounts(
-                args.config_file
-                    .unwrap_or_else(|| config::default_config_path()),
-            )
-            .pop()
-            .expect("account config");
+            let accounts =
+                config::get_accounts(args.config_file.unwrap_or_else(config::default_config_path));
+            let account_config = accounts.last().cloned().expect("account config");
 
             use wry::application::window::Icon;
             let bin: &[u8] = std::include_bytes!("icon.bin");
@@ -211,6 +452,7 @@ async fn main() {
                     view_filter: Cell::new(Some(view)),
                     account_config: Cell::new(Some(account_config)),
                     database_config: Cell::new(Some(database_config)),
+                    accounts: Cell::new(accounts),
                 },
                 Config::default()
                     .with_window(window)
@@ -219,8 +461,17 @@ async fn main() {
             );
         }
     }
+}
 
-    println!("Hello, world!");
+fn print_report(report: &SyncReport, json: bool) {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(report).expect("report json")
+        );
+    } else {
+        println!("{report}");
+    }
 }
 /* // generate icon pulled from core lib
 // "src/icon.bin"
@@ -4,63 +4,85 @@ use std::{
     path::PathBuf,
 };
 
-use himalaya_lib::{BackendBuilder, Email, Emails};
-use mailparse::{addrparse, body::Body, parse_mail, MailAddr, MailParseError, ParsedMail};
+use chrono::NaiveDate;
+use himalaya_lib::{BackendBuilder, Emails};
+use imap_proto::NameAttribute;
+use mailparse::{addrparse, body::Body, MailAddr, MailParseError, ParsedMail};
 
-use crate::config::AccountConfig;
+use crate::{config::AccountConfig, imap_session};
 #[derive(Debug, Default)]
 pub struct MessageFilter {
-    pub since: Option<u32>,
-    pub before: Option<u32>,
+    // IMAP SEARCH semantics, `since` is inclusive, `before` is not
+    pub since: Option<NaiveDate>,
+    pub before: Option<NaiveDate>,
     pub limit: Option<u32>,
     pub page: Option<u32>,
     pub folder: String,
 }
-pub fn get_messages(account: &AccountConfig, filter: MessageFilter) -> Emails {
+pub fn get_messages(account: &AccountConfig, filter: MessageFilter) -> Result<Emails, String> {
+    if account.oauth2.is_some() {
+        return Err(format!(
+            "{} signs in with oauth2, which only the incremental sync supports",
+            account.name
+        ));
+    }
     let (account_config, backend_config) = account.backend_config();
     let backend = BackendBuilder::new()
         .build(&account_config, &backend_config)
-        .unwrap();
+        .map_err(|e| e.to_string())?;
 
     let x = backend
         .list_envelopes(
-            &filter.folder.as_str(),
+            filter.folder.as_str(),
             filter.limit.unwrap_or(10) as usize,
             filter.page.unwrap_or_default() as usize,
         )
-        .unwrap();
+        .map_err(|e| format!("listing {}: {}", filter.folder, e))?;
     let ids: Vec<_> = x.iter().map(|e| e.id.as_ref()).collect();
 
-    let emails = backend
+    backend
         .get_emails(filter.folder.as_str(), ids)
-        .expect("email");
-    emails
+        .map_err(|e| format!("fetching from {}: {}", filter.folder, e))
+}
+
+pub fn list_folders(account: &AccountConfig) -> Result<Vec<String>, String> {
+    let mut session = imap_session::connect(account)?;
+    let folders = imap_session::list_folders(&mut session);
+    session.logout().ok();
+    folders
 }
 
-pub fn load_messages(path: &PathBuf) -> Emails {
-    let paths = fs::read_dir(path).unwrap();
-    let buffs = paths
+pub fn special_use_folders(
+    account: &AccountConfig,
+    uses: &[NameAttribute],
+) -> Result<Vec<String>, String> {
+    let mut session = imap_session::connect(account)?;
+    let folders = imap_session::special_use_folders(&mut session, uses);
+    session.logout().ok();
+    folders
+}
+
+pub fn load_messages(path: &PathBuf) -> Result<Vec<Vec<u8>>, String> {
+    let paths = fs::read_dir(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    paths
         .into_iter()
         .map(|path| {
-            let mut f = File::open(path.expect("path").path()).expect("file");
+            let path = path.map_err(|e| e.to_string())?.path();
+            let mut f = File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
             let mut buffer = Vec::new();
-            f.read_to_end(&mut buffer).expect("file string");
-            buffer
+            f.read_to_end(&mut buffer)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok(buffer)
         })
-        .collect::<Vec<_>>();
-    Emails::from(buffs)
+        .collect::<Result<Vec<_>, String>>()
 }
 pub mod email {
     use super::*;
     // main lib tui
     pub fn parsed_mail_body(email: &ParsedMail) -> Result<String, MailParseError> {
         match email.get_body_encoded() {
-            Body::Base64(body) | Body::QuotedPrintable(body) => {
-                Ok(body.get_decoded_as_string().unwrap().to_string())
-            }
-            Body::SevenBit(body) | Body::EightBit(body) => {
-                Ok(body.get_as_string().unwrap().to_string())
-            }
+            Body::Base64(body) | Body::QuotedPrintable(body) => body.get_decoded_as_string(),
+            Body::SevenBit(body) | Body::EightBit(body) => body.get_as_string(),
             Body::Binary(body) => Ok(format!("{:?}", body.get_raw())),
         }
     }
@@ -82,3 +104,22 @@ pub fn parse_emails(emails: &str) -> Vec<(String, Option<String>)> {
         })
         .collect::<Vec<_>>()
 }
+
+#[cfg(test)]
+mod tests {
+    use mailparse::parse_mail;
+
+    use super::*;
+
+    #[test]
+    fn malformed_body_is_an_error() {
+        let raw = b"Content-Type: text/plain\r\nContent-Transfer-Encoding: base64\r\n\r\n!!!not base64!!!\r\n";
+        let parsed = parse_mail(raw).unwrap();
+        assert!(email::parsed_mail_body(&parsed).is_err());
+
+        let raw =
+            b"Content-Type: text/plain\r\nContent-Transfer-Encoding: base64\r\n\r\naGVsbG8=\r\n";
+        let parsed = parse_mail(raw).unwrap();
+        assert_eq!(email::parsed_mail_body(&parsed).unwrap(), "hello");
+    }
+}
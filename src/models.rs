@@ -26,6 +26,10 @@ pub struct MessageLite {
     pub message_bcc: Option<String>,
     pub parent_thread_key: Option<String>,
     pub sent_date: Option<i64>,
+    pub has_attachment: bool,
+    pub snoozed_until: Option<String>,
+    pub seen_at: Option<String>,
+    pub labels: Option<String>,
 }
 impl From<MessageLite> for Message {
     fn from(value: MessageLite) -> Self {
@@ -49,6 +53,13 @@ impl From<MessageLite> for Message {
             message_bcc: value.message_bcc,
             parent_thread_key: value.parent_thread_key,
             sent_date: value.sent_date,
+            has_attachment: value.has_attachment,
+            message_references: None,
+            snoozed_until: value.snoozed_until,
+            seen_at: value.seen_at,
+            gm_msgid: None,
+            gm_thrid: None,
+            labels: value.labels,
         }
     }
 }
@@ -73,6 +84,13 @@ pub struct Message {
     pub message_bcc: Option<String>,
     pub parent_thread_key: Option<String>,
     pub sent_date: Option<i64>,
+    pub has_attachment: bool,
+    pub message_references: Option<String>,
+    pub snoozed_until: Option<String>,
+    pub seen_at: Option<String>,
+    pub gm_msgid: Option<i64>,
+    pub gm_thrid: Option<i64>,
+    pub labels: Option<String>,
 }
 #[derive(Default, Insertable, PartialEq)]
 #[diesel(table_name = messages)]
@@ -101,6 +119,7 @@ pub struct RawMessage {
     pub id: Option<i32>,
     pub message_id: Option<String>,
     pub message: Option<Vec<u8>>,
+    pub account: Option<String>,
 }
 
 #[derive(Insertable, PartialEq)]
@@ -109,3 +128,95 @@ pub struct NewRawMessage {
     pub message_id: String,
     pub message: Vec<u8>,
 }
+
+#[derive(Queryable, Debug, Default, PartialEq, Insertable, Clone)]
+#[diesel(table_name = sync_state)]
+pub struct SyncState {
+    pub id: Option<i32>,
+    pub account: String,
+    pub folder: String,
+    pub uid_validity: i64,
+    pub highest_uid: i64,
+    pub last_synced_at: Option<String>,
+    pub highest_modseq: Option<i64>,
+}
+
+#[derive(Queryable, Debug, Default, PartialEq, Insertable, Clone)]
+#[diesel(table_name = drafts)]
+pub struct Draft {
+    pub id: Option<i32>,
+    pub account: String,
+    pub message_id: String,
+    pub message_to: Option<String>,
+    pub message_cc: Option<String>,
+    pub message_bcc: Option<String>,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub message_references: Option<String>,
+    pub forward_of: Option<String>,
+    pub parent_thread_key: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub uploaded_at: Option<String>,
+}
+
+#[derive(Queryable, Debug, Default, PartialEq, Insertable, Clone)]
+#[diesel(table_name = backfill_state)]
+pub struct BackfillState {
+    pub id: Option<i32>,
+    pub account: String,
+    pub folder: String,
+    pub since: String,
+    pub before: String,
+    pub uid_validity: i64,
+    pub last_uid: i64,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Queryable, Debug, Default, PartialEq, Insertable, Clone)]
+#[diesel(table_name = pending_changes)]
+pub struct PendingChange {
+    pub id: Option<i32>,
+    pub account: String,
+    pub message_id: String,
+    pub change: String,
+    pub created_at: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub claimed_at: Option<String>,
+}
+
+#[derive(Queryable, Debug, Default, PartialEq, Insertable, Clone)]
+#[diesel(table_name = sync_errors)]
+pub struct SyncError {
+    pub id: Option<i32>,
+    pub account: String,
+    pub folder: String,
+    pub uid: Option<i64>,
+    pub error: String,
+    pub raw: Option<Vec<u8>>,
+    pub attempts: i32,
+    pub created_at: String,
+    pub last_attempt_at: String,
+}
+
+#[derive(Queryable, Debug, Default, PartialEq, Insertable, Clone)]
+#[diesel(table_name = outbox)]
+pub struct OutboxEntry {
+    pub id: Option<i32>,
+    pub account: String,
+    pub message_id: String,
+    pub draft_id: Option<i32>,
+    pub subject: Option<String>,
+    pub message_to: Option<String>,
+    pub message_bcc: Option<String>,
+    pub recipients: String,
+    pub raw: Vec<u8>,
+    pub send_at: String,
+    pub created_at: String,
+    pub claimed_at: Option<String>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub failed_at: Option<String>,
+}
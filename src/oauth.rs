@@ -0,0 +1,632 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{
+    config::{self, AccountConfig, OAuth2Config},
+    log::log,
+};
+
+// access tokens are refreshed this long before they run out
+const EXPIRY_MARGIN: i64 = 60;
+
+// two syncs finding the same expired token refresh it once
+static REFRESH: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoints {
+    pub auth_url: String,
+    pub token_url: String,
+    pub device_url: Option<String>,
+    pub scopes: Vec<String>,
+}
+
+impl Endpoints {
+    // the provider fills in what the config leaves out
+    pub fn for_config(oauth2: &OAuth2Config) -> Result<Endpoints, String> {
+        let provider = match oauth2.provider.as_deref() {
+            Some("gmail") => Some(Endpoints {
+                auth_url: "https://accounts.google.com/o/oauth2/v2/auth".into(),
+                token_url: "https://oauth2.googleapis.com/token".into(),
+                // google doesn't hand out the mail scope to device codes
+                device_url: None,
+                scopes: vec!["https://mail.google.com/".into()],
+            }),
+            Some("outlook") => Some(Endpoints {
+                auth_url: "https://login.microsoftonline.com/common/oauth2/v2.0/authorize".into(),
+                token_url: "https://login.microsoftonline.com/common/oauth2/v2.0/token".into(),
+                device_url: Some(
+                    "https://login.microsoftonline.com/common/oauth2/v2.0/devicecode".into(),
+                ),
+                scopes: vec![
+                    "https://outlook.office.com/IMAP.AccessAsUser.All".into(),
+                    "https://outlook.office.com/SMTP.Send".into(),
+                    "offline_access".into(),
+                ],
+            }),
+            Some(other) => return Err(format!("unknown oauth2 provider \"{other}\"")),
+            None => None,
+        };
+        let missing = |name: &str| format!("oauth2 needs a provider or {name}");
+        Ok(Endpoints {
+            auth_url: oauth2
+                .auth_url
+                .clone()
+                .or_else(|| provider.as_ref().map(|p| p.auth_url.clone()))
+                .ok_or_else(|| missing("auth_url"))?,
+            token_url: oauth2
+                .token_url
+                .clone()
+                .or_else(|| provider.as_ref().map(|p| p.token_url.clone()))
+                .ok_or_else(|| missing("token_url"))?,
+            device_url: oauth2
+                .device_url
+                .clone()
+                .or_else(|| provider.as_ref().and_then(|p| p.device_url.clone())),
+            scopes: if oauth2.scopes.is_empty() {
+                provider.map(|p| p.scopes).unwrap_or_default()
+            } else {
+                oauth2.scopes.clone()
+            },
+        })
+    }
+}
+
+// what ends up in the token file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    // unix seconds
+    pub expires_at: Option<i64>,
+}
+
+impl Tokens {
+    fn expired(&self) -> bool {
+        self.expires_at
+            .map(|at| at - EXPIRY_MARGIN <= Utc::now().timestamp())
+            .unwrap_or(false)
+    }
+}
+
+// token endpoint answers, errors included
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    expires_in: Option<i64>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+impl TokenResponse {
+    fn into_tokens(self, refresh_token: Option<String>) -> Result<Tokens, String> {
+        match (self.access_token, self.error) {
+            (Some(access_token), None) => Ok(Tokens {
+                access_token,
+                // refreshing usually keeps the old refresh token
+                refresh_token: self.refresh_token.or(refresh_token),
+                expires_at: self.expires_in.map(|s| Utc::now().timestamp() + s),
+            }),
+            (_, error) => Err(format!(
+                "token endpoint: {}{}",
+                error.unwrap_or_else(|| "no access token".into()),
+                self.error_description
+                    .map(|d| format!(", {d}"))
+                    .unwrap_or_default()
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceCode {
+    device_code: String,
+    user_code: String,
+    // google calls it verification_url
+    #[serde(alias = "verification_url")]
+    verification_uri: String,
+    #[serde(default = "default_device_interval")]
+    interval: u64,
+    expires_in: u64,
+}
+
+fn default_device_interval() -> u64 {
+    5
+}
+
+fn token_file(account: &AccountConfig, oauth2: &OAuth2Config) -> PathBuf {
+    oauth2.token_file.clone().unwrap_or_else(|| {
+        config::default_token_dir().join(format!("{}.json", account.name.replace('/', "_")))
+    })
+}
+
+fn load_tokens(path: &PathBuf) -> Result<Tokens, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// readable by the owner only, it holds the refresh token
+fn save_tokens(path: &PathBuf, tokens: &Tokens) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let json = serde_json::to_string_pretty(tokens).map_err(|e| e.to_string())?;
+    file.write_all(json.as_bytes())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// 4xx answers carry the oauth error in the body, so they are read like any other
+fn post_form<T: DeserializeOwned>(url: &str, form: &[(&str, &str)]) -> Result<T, String> {
+    let response = match ureq::post(url).send_form(form) {
+        Ok(response) => response,
+        Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => return Err(format!("{url}: {e}")),
+    };
+    response.into_json::<T>().map_err(|e| format!("{url}: {e}"))
+}
+
+fn oauth2_config(account: &AccountConfig) -> Result<&OAuth2Config, String> {
+    account
+        .oauth2
+        .as_ref()
+        .ok_or_else(|| format!("{} has no [oauth2] config", account.name))
+}
+
+// a valid access token for the account, refreshed and saved when it ran out
+pub fn access_token(account: &AccountConfig) -> Result<String, String> {
+    let oauth2 = oauth2_config(account)?;
+    let path = token_file(account, oauth2);
+    let _refreshing = REFRESH.lock().unwrap_or_else(|e| e.into_inner());
+    let tokens = load_tokens(&path)
+        .map_err(|e| format!("{e}, sign in with `login --account \"{}\"`", account.name))?;
+    if !tokens.expired() {
+        return Ok(tokens.access_token);
+    }
+    let refresh_token = tokens.refresh_token.ok_or_else(|| {
+        format!(
+            "the token for {} expired, sign in again with `login`",
+            account.name
+        )
+    })?;
+    let endpoints = Endpoints::for_config(oauth2)?;
+    let mut form = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token.as_str()),
+        ("client_id", oauth2.client_id.as_str()),
+    ];
+    if let Some(secret) = &oauth2.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+    let tokens = post_form::<TokenResponse>(&endpoints.token_url, &form)?
+        .into_tokens(Some(refresh_token.clone()))?;
+    save_tokens(&path, &tokens)?;
+    Ok(tokens.access_token)
+}
+
+// the SASL XOAUTH2 initial response, servers expect it base64 encoded which
+// the imap and smtp clients take care of
+pub fn xoauth2(user: &str, access_token: &str) -> String {
+    format!("user={user}\x01auth=Bearer {access_token}\x01\x01")
+}
+
+pub struct XOAuth2 {
+    pub user: String,
+    pub access_token: String,
+}
+
+impl imap::Authenticator for XOAuth2 {
+    type Response = String;
+    // a second challenge is the server's error, answering empty ends the exchange
+    fn process(&self, challenge: &[u8]) -> Self::Response {
+        if challenge.is_empty() {
+            xoauth2(&self.user, &self.access_token)
+        } else {
+            String::new()
+        }
+    }
+}
+
+// runs the sign in and stores the tokens. the device flow prints a code to enter
+// on another device, the default opens a local port for the browser to redirect to.
+pub fn login(account: &AccountConfig, device: bool) -> Result<PathBuf, String> {
+    let oauth2 = oauth2_config(account)?;
+    let endpoints = Endpoints::for_config(oauth2)?;
+    let tokens = if device {
+        device_flow(oauth2, &endpoints)?
+    } else {
+        loopback_flow(oauth2, &endpoints)?
+    };
+    if tokens.refresh_token.is_none() {
+        log("no refresh token was issued, the next sign in is due when this one expires");
+    }
+    let path = token_file(account, oauth2);
+    save_tokens(&path, &tokens)?;
+    Ok(path)
+}
+
+fn device_flow(oauth2: &OAuth2Config, endpoints: &Endpoints) -> Result<Tokens, String> {
+    let device_url = endpoints
+        .device_url
+        .as_ref()
+        .ok_or("this provider has no device flow, sign in without --device")?;
+    let scope = endpoints.scopes.join(" ");
+    let code = post_form::<DeviceCode>(
+        device_url,
+        &[("client_id", oauth2.client_id.as_str()), ("scope", &scope)],
+    )?;
+    log(format!(
+        "open {} and enter {}",
+        code.verification_uri, code.user_code
+    ));
+
+    let mut interval = Duration::from_secs(code.interval);
+    let deadline = Utc::now().timestamp() + code.expires_in as i64;
+    let mut form = vec![
+        ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+        ("device_code", code.device_code.as_str()),
+        ("client_id", oauth2.client_id.as_str()),
+    ];
+    if let Some(secret) = &oauth2.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+    while Utc::now().timestamp() < deadline {
+        thread::sleep(interval);
+        let response = post_form::<TokenResponse>(&endpoints.token_url, &form)?;
+        match response.error.as_deref() {
+            Some("authorization_pending") => continue,
+            Some("slow_down") => interval += Duration::from_secs(5),
+            _ => return response.into_tokens(None),
+        }
+    }
+    Err("the device code expired before sign in finished".into())
+}
+
+fn random_string(len: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn loopback_flow(oauth2: &OAuth2Config, endpoints: &Endpoints) -> Result<Tokens, String> {
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    let redirect_uri = format!("http://127.0.0.1:{port}");
+    // PKCE, so a client without a secret can't have its code stolen on the way back
+    let verifier = random_string(64);
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    let state = random_string(24);
+    let auth_url = Url::parse_with_params(
+        &endpoints.auth_url,
+        &[
+            ("client_id", oauth2.client_id.as_str()),
+            ("redirect_uri", &redirect_uri),
+            ("response_type", "code"),
+            ("scope", &endpoints.scopes.join(" ")),
+            ("state", &state),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+            // google only hands out refresh tokens with these
+            ("access_type", "offline"),
+            ("prompt", "consent"),
+        ],
+    )
+    .map_err(|e| format!("auth_url: {e}"))?;
+    log(format!("open this in a browser to sign in:\n{auth_url}"));
+
+    let code = wait_for_redirect(&listener, &state)?;
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code.as_str()),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("client_id", oauth2.client_id.as_str()),
+        ("code_verifier", verifier.as_str()),
+    ];
+    if let Some(secret) = &oauth2.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+    post_form::<TokenResponse>(&endpoints.token_url, &form)?.into_tokens(None)
+}
+
+// answers browser requests until one carries the code or an error
+fn wait_for_redirect(listener: &TcpListener, state: &str) -> Result<String, String> {
+    for stream in listener.incoming() {
+        let mut stream = stream.map_err(|e| e.to_string())?;
+        let mut request_line = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut request_line)
+            .map_err(|e| e.to_string())?;
+        let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let url = Url::parse(&format!("http://127.0.0.1{path}")).map_err(|e| e.to_string())?;
+        let query = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        let result = match (query("code"), query("error")) {
+            (_, Some(error)) => Some(Err(format!("sign in failed: {error}"))),
+            (Some(_), _) if query("state").as_deref() != Some(state) => {
+                Some(Err("sign in answered with the wrong state".to_string()))
+            }
+            (Some(code), _) => Some(Ok(code)),
+            // favicon and friends
+            (None, None) => None,
+        };
+        let body = match &result {
+            Some(Ok(_)) => "Signed in, you can close this window.",
+            Some(Err(_)) => "Sign in failed, see the terminal.",
+            None => "",
+        };
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .ok();
+        if let Some(result) = result {
+            return result;
+        }
+    }
+    Err("stopped listening for the sign in".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn oauth2(provider: Option<&str>) -> OAuth2Config {
+        OAuth2Config {
+            provider: provider.map(str::to_string),
+            client_id: "client".into(),
+            client_secret: None,
+            auth_url: None,
+            token_url: None,
+            device_url: None,
+            scopes: vec![],
+            token_file: None,
+        }
+    }
+
+    fn response(json: &str) -> TokenResponse {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn into_tokens_keeps_the_old_refresh_token() {
+        let tokens = response(r#"{"access_token": "new", "expires_in": 3600}"#)
+            .into_tokens(Some("old".into()))
+            .unwrap();
+        assert_eq!(tokens.access_token, "new");
+        assert_eq!(tokens.refresh_token.as_deref(), Some("old"));
+        let expires_at = tokens.expires_at.unwrap();
+        assert!((expires_at - Utc::now().timestamp() - 3600).abs() <= 5);
+
+        let tokens = response(r#"{"access_token": "new", "refresh_token": "rotated"}"#)
+            .into_tokens(Some("old".into()))
+            .unwrap();
+        assert_eq!(tokens.refresh_token.as_deref(), Some("rotated"));
+        assert_eq!(tokens.expires_at, None);
+    }
+
+    #[test]
+    fn into_tokens_reports_error_bodies() {
+        let error = response(
+            r#"{"error": "invalid_grant", "error_description": "Token has been revoked."}"#,
+        )
+        .into_tokens(Some("old".into()))
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "token endpoint: invalid_grant, Token has been revoked."
+        );
+
+        // an error wins over an access token in the same answer
+        let error = response(r#"{"access_token": "new", "error": "slow_down"}"#)
+            .into_tokens(None)
+            .unwrap_err();
+        assert_eq!(error, "token endpoint: slow_down");
+
+        let error = response("{}").into_tokens(None).unwrap_err();
+        assert_eq!(error, "token endpoint: no access token");
+    }
+
+    #[test]
+    fn expired_counts_the_margin() {
+        let now = Utc::now().timestamp();
+        let tokens = |expires_at| Tokens {
+            access_token: "a".into(),
+            refresh_token: None,
+            expires_at,
+        };
+        assert!(!tokens(None).expired());
+        assert!(!tokens(Some(now + 3600)).expired());
+        assert!(tokens(Some(now + EXPIRY_MARGIN - 1)).expired());
+        assert!(tokens(Some(now - 10)).expired());
+    }
+
+    #[test]
+    fn endpoints_come_from_the_provider() {
+        let gmail = Endpoints::for_config(&oauth2(Some("gmail"))).unwrap();
+        assert_eq!(gmail.token_url, "https://oauth2.googleapis.com/token");
+        assert_eq!(gmail.device_url, None);
+        assert_eq!(gmail.scopes, vec!["https://mail.google.com/".to_string()]);
+
+        let outlook = Endpoints::for_config(&oauth2(Some("outlook"))).unwrap();
+        assert!(outlook.device_url.is_some());
+        assert!(outlook.scopes.contains(&"offline_access".to_string()));
+    }
+
+    #[test]
+    fn endpoints_prefer_the_config() {
+        let config = OAuth2Config {
+            token_url: Some("https://example.com/token".into()),
+            scopes: vec!["mail".into()],
+            ..oauth2(Some("gmail"))
+        };
+        let endpoints = Endpoints::for_config(&config).unwrap();
+        assert_eq!(endpoints.token_url, "https://example.com/token");
+        assert_eq!(
+            endpoints.auth_url,
+            "https://accounts.google.com/o/oauth2/v2/auth"
+        );
+        assert_eq!(endpoints.scopes, vec!["mail".to_string()]);
+    }
+
+    #[test]
+    fn endpoints_need_a_provider_or_urls() {
+        assert_eq!(
+            Endpoints::for_config(&oauth2(None)).unwrap_err(),
+            "oauth2 needs a provider or auth_url"
+        );
+        assert_eq!(
+            Endpoints::for_config(&oauth2(Some("yahoo"))).unwrap_err(),
+            "unknown oauth2 provider \"yahoo\""
+        );
+        let config = OAuth2Config {
+            auth_url: Some("https://example.com/auth".into()),
+            token_url: Some("https://example.com/token".into()),
+            ..oauth2(None)
+        };
+        let endpoints = Endpoints::for_config(&config).unwrap();
+        assert_eq!(endpoints.device_url, None);
+        assert!(endpoints.scopes.is_empty());
+    }
+
+    // answers one request, hands back the body that was posted
+    fn token_endpoint(
+        status: &'static str,
+        body: &'static str,
+    ) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut posted = vec![0; length];
+            reader.read_exact(&mut posted).unwrap();
+            write!(
+                &stream,
+                "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            String::from_utf8(posted).unwrap()
+        });
+        (url, handle)
+    }
+
+    fn account(name: &str, token_url: &str, tokens: &Tokens) -> AccountConfig {
+        let token_file =
+            std::env::temp_dir().join(format!("bes-oauth-{}-{}.json", name, std::process::id()));
+        save_tokens(&token_file, tokens).unwrap();
+        toml::from_str(&format!(
+            r#"
+            name = "{name}"
+            default = true
+            [account]
+            email = "me@example.com"
+            [imap]
+            login = "me@example.com"
+            [oauth2]
+            client_id = "client"
+            auth_url = "http://127.0.0.1/auth"
+            token_url = "{token_url}"
+            token_file = "{}"
+            "#,
+            token_file.display()
+        ))
+        .unwrap()
+    }
+
+    fn expired_tokens() -> Tokens {
+        Tokens {
+            access_token: "stale".into(),
+            refresh_token: Some("old".into()),
+            expires_at: Some(Utc::now().timestamp() - 10),
+        }
+    }
+
+    #[test]
+    fn access_token_refreshes_and_saves() {
+        let (url, endpoint) =
+            token_endpoint("200 OK", r#"{"access_token": "fresh", "expires_in": 3600}"#);
+        let account = account("refresh", &url, &expired_tokens());
+        assert_eq!(access_token(&account).unwrap(), "fresh");
+
+        let posted = endpoint.join().unwrap();
+        assert!(posted.contains("grant_type=refresh_token"));
+        assert!(posted.contains("refresh_token=old"));
+        assert!(posted.contains("client_id=client"));
+
+        let path = token_file(&account, account.oauth2.as_ref().unwrap());
+        let saved = load_tokens(&path).unwrap();
+        assert_eq!(saved.access_token, "fresh");
+        assert_eq!(saved.refresh_token.as_deref(), Some("old"));
+        assert!(!saved.expired());
+        // a valid token is used as it is, nothing listens anymore
+        assert_eq!(access_token(&account).unwrap(), "fresh");
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn access_token_reports_a_rejected_refresh() {
+        let (url, endpoint) = token_endpoint(
+            "400 Bad Request",
+            r#"{"error": "invalid_grant", "error_description": "Token has been revoked."}"#,
+        );
+        let account = account("rejected", &url, &expired_tokens());
+        assert_eq!(
+            access_token(&account).unwrap_err(),
+            "token endpoint: invalid_grant, Token has been revoked."
+        );
+        endpoint.join().unwrap();
+
+        // the old tokens stay for a later sign in to replace
+        let path = token_file(&account, account.oauth2.as_ref().unwrap());
+        assert_eq!(load_tokens(&path).unwrap().access_token, "stale");
+        fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,209 @@
+use chrono::{DateTime, Duration, Utc};
+use imap_proto::NameAttribute;
+use itertools::Itertools;
+
+use crate::{
+    config::{AccountConfig, DatabaseConfig},
+    database::{self, db_timestamp},
+    drafts,
+    log::log,
+    messages,
+    models::OutboxEntry,
+    send::{self, Failure, OutgoingMessage},
+};
+
+// a transient failure is tried this many times before the message counts as failed
+const MAX_ATTEMPTS: i32 = 5;
+// the first retry is after a minute, doubling from there
+const RETRY_SECS: i64 = 60;
+// a delivery claimed this long ago never finished, its process went away
+const STALE_CLAIM_MINS: i64 = 10;
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.trim().is_empty()).then(|| value.to_owned())
+}
+
+// builds the message and queues it for `send_at`, returns the outbox id. the draft
+// stays until delivery so cancelling brings the composition back.
+pub fn queue(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    outgoing: &OutgoingMessage,
+    send_at: DateTime<Utc>,
+) -> Result<i32, String> {
+    account.sender()?;
+    if [&outgoing.to, &outgoing.cc, &outgoing.bcc]
+        .iter()
+        .all(|addresses| addresses.trim().is_empty())
+    {
+        return Err("no recipients".into());
+    }
+    let (draft_id, message_id) = drafts::save(database_config, account, outgoing)?;
+    let outgoing = OutgoingMessage {
+        draft_id: Some(draft_id),
+        message_id: Some(message_id.clone()),
+        ..outgoing.clone()
+    };
+    let message = send::build(account, &outgoing, send_at)?;
+    // bcc is only in the envelope from here on
+    let recipients = message
+        .envelope()
+        .to()
+        .iter()
+        .map(|address| address.to_string())
+        .join(",");
+    database::queue_outbox(
+        database_config,
+        OutboxEntry {
+            id: None,
+            account: account.name.clone(),
+            message_id,
+            draft_id: Some(draft_id),
+            subject: non_empty(&outgoing.subject),
+            message_to: non_empty(&outgoing.to),
+            message_bcc: non_empty(&outgoing.bcc),
+            recipients,
+            raw: message.formatted(),
+            send_at: db_timestamp(send_at),
+            created_at: db_timestamp(Utc::now()),
+            ..Default::default()
+        },
+    )
+}
+
+// sends everything that's due, returns how many went out. both the app and the
+// daemon call this, a message is only sent by whoever claims it.
+pub fn deliver_due(
+    database_config: &DatabaseConfig,
+    accounts: &[AccountConfig],
+) -> Result<usize, String> {
+    let now = Utc::now();
+    let stale_before = db_timestamp(now - Duration::minutes(STALE_CLAIM_MINS));
+    let due = database::due_outbox(database_config, &db_timestamp(now), &stale_before)?;
+
+    let mut sent = 0;
+    for entry in due.into_iter() {
+        let id = entry.id.unwrap_or_default();
+        let claimed_at = db_timestamp(Utc::now());
+        if !database::claim_outbox(database_config, id, &claimed_at, &stale_before)? {
+            continue;
+        }
+        let delivered = match accounts.iter().find(|a| a.name == entry.account) {
+            Some(account) => send::deliver(account, &entry).map(|_| account),
+            None => Err(Failure::Permanent(format!(
+                "no account named {}",
+                entry.account
+            ))),
+        };
+        match delivered {
+            Ok(account) => {
+                delivered_entry(database_config, account, entry)?;
+                sent += 1;
+            }
+            Err(failure) => failed_entry(database_config, &entry, failure)?,
+        }
+    }
+    Ok(sent)
+}
+
+fn delivered_entry(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    entry: OutboxEntry,
+) -> Result<(), String> {
+    // it went out, nothing after this may send it again
+    database::outbox_sent(database_config, entry.id.unwrap_or_default())?;
+    let stored = send::store_sent(
+        database_config,
+        account,
+        &sent_folder(account),
+        entry.raw,
+        entry.message_bcc.as_deref(),
+    );
+    if let Err(e) = stored {
+        log(format!("keeping a copy of {}: {}", entry.message_id, e));
+    }
+    if let Some(draft_id) = entry.draft_id {
+        if let Err(e) = drafts::discard(database_config, account, draft_id) {
+            log(format!("removing the draft of {}: {}", entry.message_id, e));
+        }
+    }
+    Ok(())
+}
+
+// the server's \Sent folder unless the config names one
+fn sent_folder(account: &AccountConfig) -> String {
+    if let Some(folder) = account.sent_folder() {
+        return folder;
+    }
+    match messages::special_use_folders(account, &[NameAttribute::Sent]) {
+        Ok(folders) if !folders.is_empty() => folders[0].clone(),
+        Ok(_) => "Sent".into(),
+        Err(e) => {
+            log(format!(
+                "finding the sent folder of {}: {}",
+                account.name, e
+            ));
+            "Sent".into()
+        }
+    }
+}
+
+fn failed_entry(
+    database_config: &DatabaseConfig,
+    entry: &OutboxEntry,
+    failure: Failure,
+) -> Result<(), String> {
+    let id = entry.id.unwrap_or_default();
+    let now = Utc::now();
+    match failure {
+        Failure::Transient(reason) if entry.attempts + 1 < MAX_ATTEMPTS => {
+            let retry_at = now + Duration::seconds(RETRY_SECS << entry.attempts);
+            log(format!(
+                "sending {} failed, trying again at {}: {}",
+                entry.message_id, retry_at, reason
+            ));
+            database::outbox_failed(
+                database_config,
+                id,
+                &reason,
+                Some(&db_timestamp(retry_at)),
+                &db_timestamp(now),
+            )
+        }
+        failure => {
+            log(format!(
+                "sending {} failed: {}",
+                entry.message_id,
+                failure.reason()
+            ));
+            database::outbox_failed(
+                database_config,
+                id,
+                failure.reason(),
+                None,
+                &db_timestamp(now),
+            )
+        }
+    }
+}
+
+// takes a message back out of the outbox, returns its draft to reopen
+pub fn cancel(
+    database_config: &DatabaseConfig,
+    id: i32,
+) -> Result<Option<OutgoingMessage>, String> {
+    let entry = database::get_outbox(database_config, id)?;
+    if !database::cancel_outbox(database_config, id)? {
+        return Err("it's already being sent".into());
+    }
+    entry
+        .draft_id
+        .map(|draft_id| drafts::open(database_config, draft_id))
+        .transpose()
+}
+
+// a failed message goes again right away
+pub fn retry(database_config: &DatabaseConfig, id: i32) -> Result<(), String> {
+    database::retry_outbox(database_config, id, &db_timestamp(Utc::now()))
+}
@@ -0,0 +1,173 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    config::{AccountConfig, DatabaseConfig},
+    database,
+    imap_session::{self, FolderStatus},
+    log::log,
+    sync::{self, Progress},
+};
+
+// servers end IDLE after 30 minutes, renew it before that
+const IDLE_TIMEOUT: Duration = Duration::from_secs(25 * 60);
+// IDLE is renewed this often to notice a stop
+const STOP_CHECK: Duration = Duration::from_secs(30);
+// after a dropped connection
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// the watcher threads stop when this is dropped, once they finish what they are doing
+pub struct Watchers {
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for Watchers {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// keeps one account in sync until `stop` is set. blocks, so run it on its own
+// thread. the account name is sent on `changed` whenever new mail was stored.
+pub fn watch(
+    database_config: DatabaseConfig,
+    account: AccountConfig,
+    changed: UnboundedSender<String>,
+    progress: Progress,
+    stop: Arc<AtomicBool>,
+) {
+    // nobody left to tell
+    let stopping = || stop.load(Ordering::Relaxed) || changed.is_closed();
+    while !stopping() {
+        // also catches up on whatever arrived while nobody was listening
+        let report = sync::sync_account(&database_config, &account, sync::INITIAL_COUNT, &progress);
+        if let Some(e) = report.error {
+            log(format!("sync {}: {}", account.name, e));
+            pause(RETRY_DELAY, &stopping);
+            continue;
+        }
+        if report.inserted() > 0 && changed.send(account.name.clone()).is_err() {
+            return;
+        }
+
+        if account.sync.idle {
+            match wait_for_mail(&database_config, &account, &stopping) {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => {
+                    log(format!("idle {}: {}", account.name, e));
+                    pause(RETRY_DELAY, &stopping);
+                    continue;
+                }
+            }
+        }
+        pause(Duration::from_secs(account.sync.poll_interval), &stopping);
+    }
+}
+
+// sleeps in short steps so a stop doesn't wait for the whole of `duration`
+fn pause(duration: Duration, stopping: &impl Fn() -> bool) {
+    let until = Instant::now() + duration;
+    while !stopping() {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return;
+        }
+        thread::sleep(left.min(Duration::from_secs(1)));
+    }
+}
+
+// the inbox when it is synced, otherwise the first folder that is
+fn idle_folder(folders: &[String]) -> Option<&String> {
+    folders
+        .iter()
+        .find(|folder| folder.eq_ignore_ascii_case("INBOX"))
+        .or_else(|| folders.first())
+}
+
+// true when the folder has mail the last sync didn't store
+fn has_new_mail(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    folder: &str,
+    status: &FolderStatus,
+) -> bool {
+    let Some(state) = database::get_sync_state(database_config, &account.name, folder) else {
+        return true;
+    };
+    if state.uid_validity != status.uid_validity as i64 {
+        return true;
+    }
+    match status.uid_next {
+        Some(uid_next) => uid_next as i64 > state.highest_uid + 1,
+        // no UIDNEXT from this server, count what is there instead
+        None => database::folder_uids(database_config, &account.name, folder)
+            .map(|uids| status.exists as usize > uids.len())
+            .unwrap_or(true),
+    }
+}
+
+// Ok(true) when it's time to sync again, Ok(false) when the server can't IDLE and
+// polling has to do. IDLE only watches one folder, with more synced it ends after
+// poll_interval so the others are looked at too.
+fn wait_for_mail(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    stopping: &impl Fn() -> bool,
+) -> Result<bool, String> {
+    let folders = sync::folders_to_sync(account);
+    let Some(folder) = idle_folder(&folders) else {
+        return Ok(false);
+    };
+    let mut session = imap_session::connect(account)?;
+    if !imap_session::has_capability(&mut session, "IDLE") {
+        session.logout().ok();
+        return Ok(false);
+    }
+    let status = imap_session::select(&mut session, folder)?;
+    // mail that came in after the sync looked doesn't wake IDLE
+    if has_new_mail(database_config, account, folder, &status) {
+        session.logout().ok();
+        return Ok(true);
+    }
+    let limit = if folders.len() > 1 {
+        IDLE_TIMEOUT.min(Duration::from_secs(account.sync.poll_interval))
+    } else {
+        IDLE_TIMEOUT
+    };
+    let until = Instant::now() + limit;
+    while !stopping() {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() || imap_session::wait_for_change(&mut session, left.min(STOP_CHECK))? {
+            break;
+        }
+    }
+    session.logout().ok();
+    Ok(true)
+}
+
+// one watcher thread per account, they run until the returned handle is dropped
+pub fn watch_all(
+    database_config: &DatabaseConfig,
+    accounts: Vec<AccountConfig>,
+    changed: UnboundedSender<String>,
+    progress: Progress,
+) -> Watchers {
+    let stop = Arc::new(AtomicBool::new(false));
+    for account in accounts.into_iter() {
+        let database_config = database_config.clone();
+        let changed = changed.clone();
+        let progress = progress.clone();
+        let stop = stop.clone();
+        thread::spawn(move || watch(database_config, account, changed, progress, stop));
+    }
+    Watchers { stop }
+}
@@ -0,0 +1,260 @@
+use chrono::{Local, NaiveDate, TimeZone};
+
+use crate::database::MessageFilter;
+
+// gmail style search. bare words and "quoted phrases" search everything,
+// operators narrow it down:
+//   from:alice to:bob subject:report in:INBOX label:receipts account:work
+//   before:2023-03-01 after:2023-01-01
+//   is:pinned is:done is:snoozed has:attachment (prefix with - to negate)
+// anything else with a colon in it, like a url or a time, is searched as text.
+pub fn parse(query: &str) -> Result<MessageFilter, String> {
+    let mut filter = MessageFilter::default();
+    for token in tokenize(query)?.into_iter() {
+        match token {
+            Token::Text(text) => filter.text.push(text),
+            Token::Operator {
+                negated,
+                key,
+                value,
+            } => apply(&mut filter, negated, &key, value)?,
+        }
+    }
+    Ok(filter)
+}
+
+const OPERATORS: [&str; 11] = [
+    "from", "to", "subject", "in", "folder", "label", "account", "before", "after", "is", "has",
+];
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Operator {
+        negated: bool,
+        key: String,
+        value: String,
+    },
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.peek().copied() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            tokens.push(Token::Text(quoted(&mut chars)?));
+        } else {
+            let mut word = String::new();
+            while let Some(c) = chars.peek().copied() {
+                if c.is_whitespace() || c == ':' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            if chars.peek() == Some(&':') {
+                chars.next();
+                let value = if chars.peek() == Some(&'"') {
+                    chars.next();
+                    quoted(&mut chars)?
+                } else {
+                    let mut value = String::new();
+                    while let Some(c) = chars.peek().copied() {
+                        if c.is_whitespace() {
+                            break;
+                        }
+                        value.push(c);
+                        chars.next();
+                    }
+                    value
+                };
+                let (negated, key) = match word.strip_prefix('-') {
+                    Some(key) => (true, key.to_lowercase()),
+                    None => (false, word.to_lowercase()),
+                };
+                if !OPERATORS.contains(&key.as_str()) {
+                    tokens.push(Token::Text(format!("{word}:{value}")));
+                    continue;
+                }
+                if value.is_empty() {
+                    return Err(format!("\"{key}:\" needs a value"));
+                }
+                tokens.push(Token::Operator {
+                    negated,
+                    key,
+                    value,
+                });
+            } else {
+                tokens.push(Token::Text(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn quoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    let mut text = String::new();
+    for c in chars.by_ref() {
+        if c == '"' {
+            return Ok(text);
+        }
+        text.push(c);
+    }
+    Err(format!("missing closing quote after \"{text}"))
+}
+
+fn apply(
+    filter: &mut MessageFilter,
+    negated: bool,
+    key: &str,
+    value: String,
+) -> Result<(), String> {
+    if negated && key != "is" && key != "has" {
+        return Err(format!("only is: and has: can be negated, not \"{key}:\""));
+    }
+    match key {
+        "from" => filter.from.push(value),
+        "to" => filter.to.push(value),
+        "subject" => filter.subject.push(value),
+        "in" | "folder" => filter.folder = Some(value),
+        "label" => filter.labels.push(value),
+        "account" => filter.account = Some(value),
+        "before" => filter.before = Some(parse_date(&value)?),
+        "after" => filter.after = Some(parse_date(&value)?),
+        "is" => match value.to_lowercase().as_str() {
+            "pinned" => filter.pinned = Some(!negated),
+            "done" | "archived" => filter.archived = Some(!negated),
+            "snoozed" => filter.snoozed = Some(!negated),
+            _ => return Err(format!("unknown state \"is:{value}\"")),
+        },
+        "has" => match value.to_lowercase().as_str() {
+            "attachment" | "attachments" => filter.has_attachment = Some(!negated),
+            _ => return Err(format!("unknown \"has:{value}\"")),
+        },
+        _ => return Err(format!("unknown operator \"{key}:\"")),
+    }
+    Ok(())
+}
+
+// start of the day in local time
+fn parse_date(value: &str) -> Result<i64, String> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y/%m/%d"))
+        .map_err(|_| format!("\"{value}\" is not a date, use YYYY-MM-DD"))?;
+    let midnight = date
+        .and_hms_opt(0, 0, 0)
+        .ok_or_else(|| format!("\"{value}\" is not a date"))?;
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|d| d.timestamp())
+        .ok_or_else(|| format!("\"{value}\" does not exist in local time"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(value: &str) -> i64 {
+        parse_date(value).unwrap()
+    }
+
+    #[test]
+    fn parses_queries() {
+        let text = |words: &[&str]| MessageFilter {
+            text: words.iter().map(|w| w.to_string()).collect(),
+            ..Default::default()
+        };
+        let cases = [
+            ("", MessageFilter::default()),
+            ("  hello   world ", text(&["hello", "world"])),
+            (
+                "\"quarterly report\" draft",
+                text(&["quarterly report", "draft"]),
+            ),
+            // not operators, searched as they are
+            ("https://example.com/a", text(&["https://example.com/a"])),
+            ("meet at 10:30", text(&["meet", "at", "10:30"])),
+            ("re: note:", text(&["re:", "note:"])),
+            ("-foo:bar", text(&["-foo:bar"])),
+            (
+                "from:alice To:bob subject:\"q1 numbers\" budget",
+                MessageFilter {
+                    text: vec!["budget".into()],
+                    from: vec!["alice".into()],
+                    to: vec!["bob".into()],
+                    subject: vec!["q1 numbers".into()],
+                    ..Default::default()
+                },
+            ),
+            (
+                "in:INBOX label:receipts label:2023 account:work",
+                MessageFilter {
+                    folder: Some("INBOX".into()),
+                    labels: vec!["receipts".into(), "2023".into()],
+                    account: Some("work".into()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "folder:[Gmail]/Sent after:2023-01-01 before:2023/03/01",
+                MessageFilter {
+                    folder: Some("[Gmail]/Sent".into()),
+                    after: Some(day("2023-01-01")),
+                    before: Some(day("2023-03-01")),
+                    ..Default::default()
+                },
+            ),
+            (
+                "is:pinned -is:done is:Snoozed has:attachment",
+                MessageFilter {
+                    pinned: Some(true),
+                    archived: Some(false),
+                    snoozed: Some(true),
+                    has_attachment: Some(true),
+                    ..Default::default()
+                },
+            ),
+            (
+                "is:archived -has:attachments",
+                MessageFilter {
+                    archived: Some(true),
+                    has_attachment: Some(false),
+                    ..Default::default()
+                },
+            ),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(parse(query), Ok(expected), "{query}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_operators() {
+        let cases = [
+            ("\"unclosed", "missing closing quote after \"unclosed"),
+            ("subject:\"open", "missing closing quote after \"open"),
+            ("from:", "\"from:\" needs a value"),
+            (
+                "-from:alice",
+                "only is: and has: can be negated, not \"from:\"",
+            ),
+            ("is:unread", "unknown state \"is:unread\""),
+            ("has:stars", "unknown \"has:stars\""),
+            (
+                "before:yesterday",
+                "\"yesterday\" is not a date, use YYYY-MM-DD",
+            ),
+            (
+                "after:2023-02-30",
+                "\"2023-02-30\" is not a date, use YYYY-MM-DD",
+            ),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(parse(query), Err(expected.to_owned()), "{query}");
+        }
+    }
+}
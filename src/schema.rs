@@ -21,6 +21,13 @@ diesel::table! {
         message_bcc -> Nullable<Text>,
         parent_thread_key -> Nullable<Text>,
         sent_date-> Nullable<BigInt>,
+        has_attachment -> Bool,
+        message_references -> Nullable<Text>,
+        snoozed_until -> Nullable<Text>,
+        seen_at -> Nullable<Text>,
+        gm_msgid -> Nullable<BigInt>,
+        gm_thrid -> Nullable<BigInt>,
+        labels -> Nullable<Text>,
     }
 }
 
@@ -29,7 +36,140 @@ diesel::table! {
         id -> Nullable<Integer>,
         message_id -> Nullable<Text>,
         message -> Nullable<Binary>,
+        account -> Nullable<Text>,
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(messages, raw_messages,);
+diesel::table! {
+    drafts (id) {
+        id -> Nullable<Integer>,
+        account -> Text,
+        message_id -> Text,
+        message_to -> Nullable<Text>,
+        message_cc -> Nullable<Text>,
+        message_bcc -> Nullable<Text>,
+        subject -> Nullable<Text>,
+        body -> Nullable<Text>,
+        in_reply_to -> Nullable<Text>,
+        message_references -> Nullable<Text>,
+        forward_of -> Nullable<Text>,
+        parent_thread_key -> Nullable<Text>,
+        created_at -> Text,
+        updated_at -> Text,
+        uploaded_at -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    backfill_state (id) {
+        id -> Nullable<Integer>,
+        account -> Text,
+        folder -> Text,
+        since -> Text,
+        before -> Text,
+        uid_validity -> BigInt,
+        last_uid -> BigInt,
+        updated_at -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    sync_state (id) {
+        id -> Nullable<Integer>,
+        account -> Text,
+        folder -> Text,
+        uid_validity -> BigInt,
+        highest_uid -> BigInt,
+        last_synced_at -> Nullable<Text>,
+        highest_modseq -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    pending_changes (id) {
+        id -> Nullable<Integer>,
+        account -> Text,
+        message_id -> Text,
+        change -> Text,
+        created_at -> Text,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        claimed_at -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    message_folders (id) {
+        id -> Nullable<Integer>,
+        account -> Text,
+        message_id -> Text,
+        folder -> Text,
+        uid -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    labels (id) {
+        id -> Nullable<Integer>,
+        account -> Text,
+        message_id -> Text,
+        label -> Text,
+    }
+}
+
+diesel::table! {
+    sync_errors (id) {
+        id -> Nullable<Integer>,
+        account -> Text,
+        folder -> Text,
+        uid -> Nullable<BigInt>,
+        error -> Text,
+        raw -> Nullable<Binary>,
+        attempts -> Integer,
+        created_at -> Text,
+        last_attempt_at -> Text,
+    }
+}
+
+diesel::table! {
+    outbox (id) {
+        id -> Nullable<Integer>,
+        account -> Text,
+        message_id -> Text,
+        draft_id -> Nullable<Integer>,
+        subject -> Nullable<Text>,
+        message_to -> Nullable<Text>,
+        message_bcc -> Nullable<Text>,
+        recipients -> Text,
+        raw -> Binary,
+        send_at -> Text,
+        created_at -> Text,
+        claimed_at -> Nullable<Text>,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        failed_at -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    thread_references (id) {
+        id -> Nullable<Integer>,
+        account -> Text,
+        message_id -> Text,
+        reference -> Text,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(
+    messages,
+    raw_messages,
+    sync_state,
+    pending_changes,
+    message_folders,
+    labels,
+    sync_errors,
+    backfill_state,
+    drafts,
+    outbox,
+    thread_references,
+);
@@ -0,0 +1,757 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use chrono::{DateTime, Utc};
+use himalaya_lib::Emails;
+use lettre::{
+    address::Envelope,
+    message::{
+        header::{ContentTransferEncoding, ContentType},
+        Attachment, Body, Mailbox, Mailboxes, MultiPart, SinglePart,
+    },
+    transport::smtp::authentication::{Credentials, Mechanism},
+    Address, Message, SmtpTransport, Transport,
+};
+use mailparse::{parse_mail, MailHeaderMap};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+use crate::{
+    config::{AccountConfig, DatabaseConfig, Sender, Smtp, TlsMode},
+    database::{self, message_to_db, save_records},
+    messages::parse_emails,
+    models::{Message as StoredMessage, OutboxEntry},
+    oauth, threading,
+};
+
+// what the compose view hands over
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutgoingMessage {
+    // comma separated addresses
+    pub to: String,
+    pub cc: String,
+    pub bcc: String,
+    pub subject: String,
+    pub body: String,
+    // the message this answers, threading needs both
+    pub in_reply_to: Option<String>,
+    pub references: Vec<String>,
+    pub attachments: Vec<OutgoingAttachment>,
+    // set on the first draft save so the draft, its server copy and the sent
+    // message all share one Message-ID
+    pub message_id: Option<String>,
+    pub draft_id: Option<i32>,
+    // the message attached by forward, drafts rebuild the attachment from it
+    pub forward_of: Option<String>,
+}
+
+// transient failures are tried again later, permanent ones wait for the user
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    Transient(String),
+    Permanent(String),
+}
+
+impl Failure {
+    pub fn reason(&self) -> &str {
+        match self {
+            Failure::Transient(reason) | Failure::Permanent(reason) => reason,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutgoingAttachment {
+    pub name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+fn mailboxes(field: &str, addresses: &str) -> Result<Vec<Mailbox>, String> {
+    if addresses.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    addresses
+        .parse::<Mailboxes>()
+        .map(|mailboxes| mailboxes.into_iter().collect())
+        .map_err(|e| format!("{field}: {e}"))
+}
+
+pub fn new_message_id(account: &AccountConfig) -> String {
+    let email = account.email();
+    let domain = email.rsplit('@').next().unwrap_or("localhost");
+    let random = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect::<String>();
+    format!("<{}.{}@{}>", Utc::now().timestamp_millis(), random, domain)
+}
+
+// plain text, lettre takes care of the encoding. `date` is when it's meant to go out.
+pub fn build(
+    account: &AccountConfig,
+    outgoing: &OutgoingMessage,
+    date: DateTime<Utc>,
+) -> Result<Message, String> {
+    let from = Mailbox::new(
+        account.display_name(),
+        account
+            .email()
+            .parse()
+            .map_err(|e| format!("account email: {e}"))?,
+    );
+    let to = mailboxes("To", &outgoing.to)?;
+    let cc = mailboxes("Cc", &outgoing.cc)?;
+    let bcc = mailboxes("Bcc", &outgoing.bcc)?;
+
+    let message_id = outgoing
+        .message_id
+        .clone()
+        .unwrap_or_else(|| new_message_id(account));
+    let mut builder = Message::builder()
+        .from(from)
+        .subject(outgoing.subject.clone())
+        .message_id(Some(message_id))
+        .date(date.into());
+    for mailbox in to.into_iter() {
+        builder = builder.to(mailbox);
+    }
+    for mailbox in cc.into_iter() {
+        builder = builder.cc(mailbox);
+    }
+    for mailbox in bcc.into_iter() {
+        builder = builder.bcc(mailbox);
+    }
+    if let Some(parent) = &outgoing.in_reply_to {
+        builder = builder.in_reply_to(parent.clone());
+    }
+    if !outgoing.references.is_empty() {
+        builder = builder.references(outgoing.references.join(" "));
+    }
+
+    let text = SinglePart::plain(outgoing.body.clone());
+    if outgoing.attachments.is_empty() {
+        return builder.singlepart(text).map_err(|e| e.to_string());
+    }
+    let mut parts = MultiPart::mixed().singlepart(text);
+    for attachment in outgoing.attachments.iter() {
+        let content_type = ContentType::parse(&attachment.content_type)
+            .map_err(|e| format!("{}: {}", attachment.name, e))?;
+        parts = parts.singlepart(
+            Attachment::new(attachment.name.clone())
+                .body(attachment_body(attachment), content_type),
+        );
+    }
+    builder.multipart(parts).map_err(|e| e.to_string())
+}
+
+// a forwarded message/rfc822 part may only be 7bit, 8bit or binary (RFC 2046 5.2.1),
+// everything else is left to lettre
+fn attachment_body(attachment: &OutgoingAttachment) -> Body {
+    let data = attachment.data.clone();
+    if !attachment
+        .content_type
+        .eq_ignore_ascii_case("message/rfc822")
+    {
+        return Body::new(data);
+    }
+    // the raw message already has CRLF line endings. 8bit while lines fit smtp's limit.
+    let eight_bit = !data.contains(&0) && data.split(|b| *b == b'\n').all(|line| line.len() <= 998);
+    let encoding = if eight_bit {
+        ContentTransferEncoding::EightBit
+    } else {
+        ContentTransferEncoding::Binary
+    };
+    Body::dangerous_pre_encoded(data, encoding)
+}
+
+// the addresses of `addresses` that are neither ours nor in `seen`, which they are added to
+fn others(account: &AccountConfig, addresses: &str, seen: &mut Vec<String>) -> Vec<String> {
+    let own = [account.email(), account.imap_login()].map(|address| address.to_lowercase());
+    parse_emails(addresses)
+        .into_iter()
+        .filter(|(address, _)| {
+            let address = address.to_lowercase();
+            if own.contains(&address) || seen.contains(&address) {
+                return false;
+            }
+            seen.push(address);
+            true
+        })
+        .map(|(address, name)| match name {
+            Some(name) => format!("\"{}\" <{}>", name.replace('"', ""), address),
+            None => address,
+        })
+        .collect()
+}
+
+fn prefixed(prefix: &str, subject: &str, already: &[&str]) -> String {
+    let lower = subject.trim_start().to_lowercase();
+    if already.iter().any(|p| lower.starts_with(p)) {
+        subject.to_owned()
+    } else {
+        format!("{prefix} {subject}")
+    }
+}
+
+fn original_text(message: &StoredMessage) -> String {
+    message
+        .text_format
+        .clone()
+        .or_else(|| message.content.clone())
+        .unwrap_or_default()
+}
+
+// where the sender asked for answers to go, only the raw message has it
+fn reply_to(database_config: &DatabaseConfig, account: &str, message_id: &str) -> Option<String> {
+    let raw = database::get_raw_message(database_config, account, message_id).ok()?;
+    parse_mail(&raw)
+        .ok()?
+        .headers
+        .get_first_value("Reply-To")
+        .filter(|reply_to| !reply_to.trim().is_empty())
+}
+
+// a reply to `message_id`, to everyone on it when `all` is set
+pub fn reply(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    message_id: &str,
+    all: bool,
+) -> Result<OutgoingMessage, String> {
+    let original = database::get_message(database_config, &account.name, message_id)?;
+    let from = original.message_from.clone().unwrap_or_default();
+    let to = original.message_to.clone().unwrap_or_default();
+    let mut seen = vec![];
+    // Reply-To takes the place of the sender, answering our own mail goes to
+    // whoever it was sent to
+    let answer_to = reply_to(database_config, &account.name, message_id).unwrap_or(from.clone());
+    let mut recipients = others(account, &answer_to, &mut seen);
+    let replying_to_self = recipients.is_empty();
+    if replying_to_self || all {
+        recipients.extend(others(account, &to, &mut seen));
+    }
+    let cc = if all {
+        others(
+            account,
+            &original.message_cc.clone().unwrap_or_default(),
+            &mut seen,
+        )
+    } else {
+        vec![]
+    };
+
+    let mut references =
+        threading::message_ids(&original.message_references.clone().unwrap_or_default());
+    if !references.contains(&original.message_id) {
+        references.push(original.message_id.clone());
+    }
+    let quoted = original_text(&original)
+        .lines()
+        .map(|line| {
+            if line.is_empty() {
+                ">".to_owned()
+            } else {
+                format!("> {line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(OutgoingMessage {
+        to: recipients.join(", "),
+        cc: cc.join(", "),
+        subject: prefixed(
+            "Re:",
+            &original.subject.clone().unwrap_or_default(),
+            &["re:"],
+        ),
+        body: format!(
+            "\n\nOn {}, {} wrote:\n{}\n",
+            original.sent_at.clone().unwrap_or_default(),
+            from,
+            quoted
+        ),
+        in_reply_to: Some(original.message_id.clone()),
+        references,
+        ..Default::default()
+    })
+}
+
+// the original goes along whole as a message/rfc822 attachment, its text is inlined too
+pub fn forward(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    message_id: &str,
+) -> Result<OutgoingMessage, String> {
+    let original = database::get_message(database_config, &account.name, message_id)?;
+    let subject = original.subject.clone().unwrap_or_default();
+    Ok(OutgoingMessage {
+        subject: prefixed("Fwd:", &subject, &["fwd:", "fw:"]),
+        body: format!(
+            "\n\n---------- Forwarded message ----------\nFrom: {}\nDate: {}\nSubject: {}\nTo: {}\n\n{}\n",
+            original.message_from.clone().unwrap_or_default(),
+            original.sent_at.clone().unwrap_or_default(),
+            subject,
+            original.message_to.clone().unwrap_or_default(),
+            original_text(&original)
+        ),
+        attachments: vec![forward_attachment(database_config, &account.name, message_id)?],
+        forward_of: Some(message_id.to_owned()),
+        ..Default::default()
+    })
+}
+
+pub fn forward_attachment(
+    database_config: &DatabaseConfig,
+    account: &str,
+    message_id: &str,
+) -> Result<OutgoingAttachment, String> {
+    let original = database::get_message(database_config, account, message_id)?;
+    let raw = database::get_raw_message(database_config, account, message_id)?;
+    let name = original
+        .subject
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ' ' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let name = if name.trim().is_empty() {
+        "message".to_owned()
+    } else {
+        name.trim().to_owned()
+    };
+    Ok(OutgoingAttachment {
+        name: format!("{name}.eml"),
+        content_type: "message/rfc822".into(),
+        data: raw,
+    })
+}
+
+fn transport(account: &AccountConfig, smtp: &Smtp) -> Result<SmtpTransport, String> {
+    let builder = match smtp.tls {
+        TlsMode::Tls => SmtpTransport::relay(&smtp.host),
+        TlsMode::Starttls => SmtpTransport::starttls_relay(&smtp.host),
+        TlsMode::None => Ok(SmtpTransport::builder_dangerous(&smtp.host)),
+    }
+    .map_err(|e| format!("smtp {}: {}", smtp.host, e))?
+    .port(smtp.port);
+    let login = account.smtp_login(smtp);
+    let builder = if account.oauth2.is_some() {
+        builder
+            .credentials(Credentials::new(login, oauth::access_token(account)?))
+            .authentication(vec![Mechanism::Xoauth2])
+    } else {
+        builder.credentials(Credentials::new(login, account.smtp_password(smtp)?))
+    };
+    Ok(builder.build())
+}
+
+// hands a queued message to the smtp server or the sendmail command
+pub fn deliver(account: &AccountConfig, entry: &OutboxEntry) -> Result<(), Failure> {
+    let recipients = entry
+        .recipients
+        .split(',')
+        .map(|address| address.trim())
+        .filter(|address| !address.is_empty())
+        .collect::<Vec<_>>();
+    match account.sender().map_err(Failure::Permanent)? {
+        Sender::Smtp(smtp) => deliver_smtp(account, smtp, &recipients, &entry.raw),
+        Sender::Command(args) => deliver_command(&args, &recipients, &entry.raw),
+    }
+}
+
+fn deliver_smtp(
+    account: &AccountConfig,
+    smtp: &Smtp,
+    recipients: &[&str],
+    raw: &[u8],
+) -> Result<(), Failure> {
+    let from = account
+        .email()
+        .parse::<Address>()
+        .map_err(|e| Failure::Permanent(format!("account email: {e}")))?;
+    let to = recipients
+        .iter()
+        .map(|address| address.parse::<Address>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Failure::Permanent(format!("recipients: {e}")))?;
+    let envelope = Envelope::new(Some(from), to).map_err(|e| Failure::Permanent(e.to_string()))?;
+    // no connection or no token yet, worth another go
+    let transport = transport(account, smtp).map_err(Failure::Transient)?;
+    transport.send_raw(&envelope, raw).map(|_| ()).map_err(|e| {
+        let reason = format!("smtp {}: {}", smtp.host, e);
+        if e.is_permanent() {
+            Failure::Permanent(reason)
+        } else {
+            Failure::Transient(reason)
+        }
+    })
+}
+
+// sendmail's "try again later" exit code, EX_TEMPFAIL
+const EXIT_TEMPFAIL: i32 = 75;
+
+// pipes the message to the command with the recipients as its last arguments
+fn deliver_command(args: &[String], recipients: &[&str], raw: &[u8]) -> Result<(), Failure> {
+    let (program, args) = args
+        .split_first()
+        .ok_or_else(|| Failure::Permanent("sendmail_cmd is empty".into()))?;
+    // "--" keeps a recipient starting with "-" from being read as an option
+    let mut child = Command::new(program)
+        .args(args)
+        .arg("--")
+        .args(recipients)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Failure::Permanent(format!("running {program}: {e}")))?;
+    // the pipe is closed when stdin is dropped, the command sees the end of the message
+    let written = child
+        .stdin
+        .take()
+        .map(|mut stdin| stdin.write_all(raw))
+        .unwrap_or(Ok(()));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| Failure::Transient(format!("waiting for {program}: {e}")))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        let reason = format!("{} {}: {}", program, output.status, stderr);
+        return match output.status.code() {
+            Some(EXIT_TEMPFAIL) => Err(Failure::Transient(reason)),
+            Some(_) => Err(Failure::Permanent(reason)),
+            // killed by a signal, nothing says the message was at fault
+            None => Err(Failure::Transient(reason)),
+        };
+    }
+    // exiting fine means nothing when the command didn't take the whole message
+    match written {
+        Ok(()) => Ok(()),
+        Err(e) => Err(Failure::Transient(format!(
+            "{program} exited before reading the message: {e}"
+        ))),
+    }
+}
+
+// keeps a copy of what went out in the sent folder, returns the Message-ID.
+// the copy a later sync finds on the server has the same id and is merged with it.
+pub fn store_sent(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    folder: &str,
+    raw: Vec<u8>,
+    bcc: Option<&str>,
+) -> Result<String, String> {
+    let emails = Emails::from(vec![raw]);
+    let email = emails
+        .to_vec()
+        .into_iter()
+        .next()
+        .ok_or("the sent message could not be read back")?;
+    let (raw, mut record) = message_to_db(email, account, folder, database_config)?
+        .ok_or("the sent message could not be read back")?;
+    // lettre leaves Bcc out of what goes over the wire, only this copy has it
+    if let Some(bcc) = bcc.filter(|bcc| !bcc.trim().is_empty()) {
+        record.message_bcc = Some(bcc.to_owned());
+    }
+    record.seen_at = Some(database::db_timestamp(Utc::now()));
+    let message_id = record.message_id.clone();
+    save_records(database_config, raw, record, None)?;
+    Ok(message_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        thread,
+    };
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn account(smtp: &str) -> AccountConfig {
+        toml::from_str(&format!(
+            r#"
+            name = "test"
+            default = true
+            [account]
+            email = "me@example.com"
+            display_name = "Me"
+            [imap]
+            login = "me@example.com"
+            passwd = "secret"
+            {smtp}
+            "#
+        ))
+        .unwrap()
+    }
+
+    fn formatted(message: &Message) -> String {
+        String::from_utf8(message.formatted()).unwrap()
+    }
+
+    #[test]
+    fn mailboxes_parse_lists() {
+        assert!(mailboxes("To", "  ").unwrap().is_empty());
+        let parsed = mailboxes("To", "Ann <ann@example.com>, bob@example.com").unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].name.as_deref(), Some("Ann"));
+        assert_eq!(parsed[0].email.to_string(), "ann@example.com");
+        assert_eq!(parsed[1].name, None);
+        assert!(mailboxes("Cc", "not an address")
+            .unwrap_err()
+            .starts_with("Cc: "));
+    }
+
+    #[test]
+    fn build_sets_the_headers() {
+        let outgoing = OutgoingMessage {
+            to: "Ann <ann@example.com>".into(),
+            cc: "bob@example.com".into(),
+            bcc: "hidden@example.com".into(),
+            subject: "Re: Plans".into(),
+            body: "Sounds good".into(),
+            in_reply_to: Some("<parent@example.com>".into()),
+            references: vec!["<root@example.com>".into(), "<parent@example.com>".into()],
+            message_id: Some("<id@example.com>".into()),
+            ..Default::default()
+        };
+        let date = Utc.with_ymd_and_hms(2023, 3, 20, 10, 0, 0).unwrap();
+        let message = build(&account(""), &outgoing, date).unwrap();
+        let raw = formatted(&message);
+        assert!(raw.contains("From: Me <me@example.com>\r\n"));
+        assert!(raw.contains("To: Ann <ann@example.com>\r\n"));
+        assert!(raw.contains("Cc: bob@example.com\r\n"));
+        assert!(raw.contains("Subject: Re: Plans\r\n"));
+        assert!(raw.contains("Message-ID: <id@example.com>\r\n"));
+        assert!(raw.contains("In-Reply-To: <parent@example.com>\r\n"));
+        assert!(raw.contains("References: <root@example.com> <parent@example.com>\r\n"));
+        assert!(raw.contains("Date: Mon, 20 Mar 2023 10:00:00 +0000\r\n"));
+        assert!(raw.contains("Sounds good"));
+        // bcc only goes in the envelope
+        assert!(!raw.contains("Bcc"));
+        assert!(!raw.contains("hidden@example.com"));
+        let recipients = message
+            .envelope()
+            .to()
+            .iter()
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recipients,
+            ["ann@example.com", "bob@example.com", "hidden@example.com"]
+        );
+    }
+
+    #[test]
+    fn build_attaches_files() {
+        let outgoing = OutgoingMessage {
+            to: "ann@example.com".into(),
+            subject: "Report".into(),
+            body: "Attached".into(),
+            attachments: vec![OutgoingAttachment {
+                name: "report.csv".into(),
+                content_type: "text/csv".into(),
+                data: b"a,b\n1,2\n".to_vec(),
+            }],
+            ..Default::default()
+        };
+        let raw = formatted(&build(&account(""), &outgoing, Utc::now()).unwrap());
+        assert!(raw.contains("Content-Type: multipart/mixed;"));
+        assert!(raw.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(raw.contains("Content-Type: text/csv"));
+        assert!(raw.contains("filename=\"report.csv\""));
+        // a fresh Message-ID from the account's domain
+        assert!(raw.contains("@example.com>\r\n"));
+
+        let bad = OutgoingMessage {
+            attachments: vec![OutgoingAttachment {
+                name: "broken".into(),
+                content_type: "not a type".into(),
+                data: vec![],
+            }],
+            ..outgoing
+        };
+        assert!(build(&account(""), &bad, Utc::now())
+            .unwrap_err()
+            .starts_with("broken: "));
+    }
+
+    #[test]
+    fn build_forwards_messages_unencoded() {
+        let original = b"From: ann@example.com\r\nSubject: Caf\xc3\xa9\r\n\r\nBonjour\r\n".to_vec();
+        let outgoing = OutgoingMessage {
+            to: "bob@example.com".into(),
+            subject: "Fwd: Caf\u{e9}".into(),
+            attachments: vec![OutgoingAttachment {
+                name: "Caf\u{e9}.eml".into(),
+                content_type: "message/rfc822".into(),
+                data: original,
+            }],
+            ..Default::default()
+        };
+        let raw = formatted(&build(&account(""), &outgoing, Utc::now()).unwrap());
+        let part = &raw[raw.find("Content-Type: message/rfc822").unwrap()..];
+        // message/rfc822 parts may not be base64 encoded
+        assert!(part.contains("Content-Transfer-Encoding: 8bit\r\n"));
+        assert!(part.contains("Subject: Caf\u{e9}\r\n\r\nBonjour\r\n"));
+        assert!(!part.contains("base64"));
+    }
+
+    fn command(script: &str) -> Vec<String> {
+        vec!["sh".into(), "-c".into(), script.into(), "sh".into()]
+    }
+
+    #[test]
+    fn deliver_command_passes_recipients_after_options() {
+        let out = std::env::temp_dir().join(format!("bes-sendmail-{}", std::process::id()));
+        let script = format!("printf '%s\\n' \"$@\" > {0}; cat >> {0}", out.display());
+        deliver_command(
+            &command(&script),
+            &["-oi@example.com", "ann@example.com"],
+            b"Hi\r\n",
+        )
+        .unwrap();
+        let written = std::fs::read_to_string(&out).unwrap();
+        std::fs::remove_file(&out).ok();
+        assert_eq!(written, "--\n-oi@example.com\nann@example.com\nHi\r\n");
+    }
+
+    #[test]
+    fn deliver_command_needs_the_whole_message() {
+        // exits fine without reading, the message is bigger than the pipe
+        let raw = vec![b'a'; 1 << 20];
+        let e = deliver_command(&command("exit 0"), &["ann@example.com"], &raw).unwrap_err();
+        assert!(matches!(e, Failure::Transient(_)), "{e:?}");
+        assert!(e.reason().starts_with("sh exited before reading"), "{e:?}");
+    }
+
+    #[test]
+    fn deliver_command_reads_exit_codes() {
+        let e = deliver_command(&command("cat >/dev/null; echo busy >&2; exit 75"), &[], b"")
+            .unwrap_err();
+        assert!(matches!(e, Failure::Transient(_)), "{e:?}");
+        assert!(e.reason().ends_with(": busy"), "{e:?}");
+        let e = deliver_command(&command("cat >/dev/null; exit 67"), &[], b"").unwrap_err();
+        assert!(matches!(e, Failure::Permanent(_)), "{e:?}");
+    }
+
+    // a plain smtp server that takes one message, hands back the envelope and data
+    fn smtp_sink() -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut writer = &stream;
+            let mut received = vec![];
+            writer.write_all(b"220 sink ready\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let command = line.trim_end().to_string();
+                let verb = command.split(' ').next().unwrap_or("").to_uppercase();
+                let answer = match verb.as_str() {
+                    "EHLO" => "250-sink\r\n250 AUTH PLAIN LOGIN\r\n".to_string(),
+                    "AUTH" => "235 ok\r\n".to_string(),
+                    "DATA" => {
+                        writer.write_all(b"354 go ahead\r\n").unwrap();
+                        let mut data = String::new();
+                        loop {
+                            let mut line = String::new();
+                            reader.read_line(&mut line).unwrap();
+                            if line == ".\r\n" {
+                                break;
+                            }
+                            data.push_str(&line);
+                        }
+                        received.push(data);
+                        "250 queued\r\n".to_string()
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 bye\r\n").unwrap();
+                        break;
+                    }
+                    _ => "250 ok\r\n".to_string(),
+                };
+                if verb != "DATA" {
+                    received.push(command);
+                }
+                writer.write_all(answer.as_bytes()).unwrap();
+                // the transport pools its connection, there is no QUIT to wait for
+                if verb == "DATA" {
+                    break;
+                }
+            }
+            received
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn deliver_smtp_hands_over_the_message() {
+        let (port, sink) = smtp_sink();
+        let account = account(&format!(
+            r#"
+            [smtp]
+            host = "127.0.0.1"
+            port = {port}
+            tls = "none"
+            "#
+        ));
+        let outgoing = OutgoingMessage {
+            to: "ann@example.com".into(),
+            bcc: "hidden@example.com".into(),
+            subject: "Hello".into(),
+            body: "Hi Ann".into(),
+            ..Default::default()
+        };
+        let raw = build(&account, &outgoing, Utc::now()).unwrap().formatted();
+        let entry = OutboxEntry {
+            recipients: "ann@example.com, hidden@example.com".into(),
+            raw,
+            ..Default::default()
+        };
+        deliver(&account, &entry).unwrap();
+
+        let received = sink.join().unwrap();
+        assert!(received.iter().any(|line| line.starts_with("AUTH PLAIN")));
+        assert!(received.contains(&"MAIL FROM:<me@example.com>".to_string()));
+        assert!(received.contains(&"RCPT TO:<ann@example.com>".to_string()));
+        assert!(received.contains(&"RCPT TO:<hidden@example.com>".to_string()));
+        let data = received
+            .iter()
+            .find(|line| line.contains("Subject: Hello"))
+            .unwrap();
+        assert!(data.contains("Hi Ann"));
+        assert!(!data.contains("hidden@example.com"));
+    }
+
+    #[test]
+    fn smtp_needs_a_host() {
+        let config = r#"
+            name = "test"
+            default = true
+            [account]
+            email = "me@example.com"
+            [imap]
+            login = "me@example.com"
+            [smtp]
+            port = 465
+            "#;
+        assert!(toml::from_str::<AccountConfig>(config).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc, Weekday,
+};
+
+use crate::{config::DatabaseConfig, database, log::log};
+
+// everything is worked out in local time, what gets stored is utc
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnoozePreset {
+    LaterToday,
+    TomorrowMorning,
+    ThisWeekend,
+    NextWeek,
+    Custom(NaiveDateTime),
+}
+
+pub const PRESETS: [SnoozePreset; 4] = [
+    SnoozePreset::LaterToday,
+    SnoozePreset::TomorrowMorning,
+    SnoozePreset::ThisWeekend,
+    SnoozePreset::NextWeek,
+];
+
+const MORNING: u32 = 8;
+
+impl SnoozePreset {
+    pub fn label(&self) -> String {
+        match self {
+            SnoozePreset::LaterToday => "Later today".into(),
+            SnoozePreset::TomorrowMorning => "Tomorrow morning".into(),
+            SnoozePreset::ThisWeekend => "This weekend".into(),
+            SnoozePreset::NextWeek => "Next week".into(),
+            SnoozePreset::Custom(at) => at.format("%a, %b %d, %I:%M %p").to_string(),
+        }
+    }
+
+    pub fn until<Tz: TimeZone>(&self, now: DateTime<Tz>) -> DateTime<Utc> {
+        let zone = now.timezone();
+        let today = now.date_naive();
+        let local = match self {
+            // three hours out, on the hour
+            SnoozePreset::LaterToday => {
+                let later = now.naive_local() + Duration::hours(3);
+                later
+                    .date()
+                    .and_hms_opt(later.time().hour(), 0, 0)
+                    .unwrap_or(later)
+            }
+            SnoozePreset::TomorrowMorning => at_morning(today + Duration::days(1)),
+            // the coming saturday, or next week's when it is already the weekend
+            SnoozePreset::ThisWeekend => {
+                let days = match today.weekday() {
+                    Weekday::Sat => 7,
+                    Weekday::Sun => 6,
+                    other => 5 - other.num_days_from_monday() as i64,
+                };
+                at_morning(today + Duration::days(days))
+            }
+            SnoozePreset::NextWeek => {
+                let days = 7 - today.weekday().num_days_from_monday() as i64;
+                at_morning(today + Duration::days(days))
+            }
+            SnoozePreset::Custom(at) => *at,
+        };
+        zone.from_local_datetime(&local)
+            .earliest()
+            .map(|d| d.with_timezone(&Utc))
+            // skipped by a dst change, an hour later always exists
+            .or_else(|| {
+                zone.from_local_datetime(&(local + Duration::hours(1)))
+                    .earliest()
+                    .map(|d| d.with_timezone(&Utc))
+            })
+            .unwrap_or_else(|| Utc.from_utc_datetime(&local))
+    }
+}
+
+fn at_morning(date: chrono::NaiveDate) -> NaiveDateTime {
+    date.and_time(NaiveTime::from_hms_opt(MORNING, 0, 0).unwrap_or_default())
+}
+
+// the datetime-local input format
+pub const CUSTOM_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+// "YYYY-MM-DDTHH:MM" from a datetime-local input, nothing for a time that has passed
+pub fn parse_custom(value: &str, now: DateTime<Local>) -> Option<SnoozePreset> {
+    NaiveDateTime::parse_from_str(value, CUSTOM_FORMAT)
+        .ok()
+        .map(SnoozePreset::Custom)
+        .filter(|preset| preset.until(now) > now)
+}
+
+// moves everything that is due back into the inbox. all state lives in sqlite so
+// anything that came due while nothing was running wakes on the first call.
+pub fn wake_due(database_config: &DatabaseConfig) -> Result<usize, String> {
+    let woken = database::wake_snoozed(database_config, Utc::now())?;
+    if woken > 0 {
+        log(format!("woke {woken} snoozed messages"));
+    }
+    Ok(woken)
+}
+
+// headless wake up loop
+pub async fn run(database_config: DatabaseConfig, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = wake_due(&database_config) {
+            log(format!("snooze wake up failed: {e}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, LocalResult, NaiveDate};
+
+    use super::*;
+
+    // central europe in 2023: +1, and +2 from 2023-03-26 01:00 to 2023-10-29 01:00 utc,
+    // so 02:00-03:00 local is skipped in march and happens twice in october
+    #[derive(Debug, Clone, Copy)]
+    struct Berlin2023;
+
+    const WINTER: i32 = 60 * 60;
+    const SUMMER: i32 = 2 * 60 * 60;
+
+    fn offset_at(utc: &NaiveDateTime) -> FixedOffset {
+        let spring = naive("2023-03-26T01:00");
+        let autumn = naive("2023-10-29T01:00");
+        let secs = if (spring..autumn).contains(utc) {
+            SUMMER
+        } else {
+            WINTER
+        };
+        FixedOffset::east_opt(secs).unwrap()
+    }
+
+    impl TimeZone for Berlin2023 {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Berlin2023
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(12, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            // summer first, it is the earlier instant when both fit
+            let fits = [SUMMER, WINTER]
+                .into_iter()
+                .map(|secs| FixedOffset::east_opt(secs).unwrap())
+                .filter(|offset| {
+                    offset_at(&(*local - Duration::seconds(offset.local_minus_utc() as i64)))
+                        == *offset
+                })
+                .collect::<Vec<_>>();
+            match fits[..] {
+                [] => LocalResult::None,
+                [offset] => LocalResult::Single(offset),
+                [earlier, later, ..] => LocalResult::Ambiguous(earlier, later),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            offset_at(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            offset_at(utc)
+        }
+    }
+
+    fn naive(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, CUSTOM_FORMAT).unwrap()
+    }
+
+    fn until<Tz: TimeZone>(preset: SnoozePreset, zone: Tz, now: &str) -> String {
+        let now = zone.from_local_datetime(&naive(now)).unwrap();
+        preset.until(now).format(CUSTOM_FORMAT).to_string()
+    }
+
+    #[test]
+    fn weekend_is_the_coming_saturday() {
+        // 2023-03-17 is a friday
+        let weekend = |now| until(SnoozePreset::ThisWeekend, Utc, now);
+        assert_eq!(weekend("2023-03-13T10:00"), "2023-03-18T08:00");
+        assert_eq!(weekend("2023-03-17T23:30"), "2023-03-18T08:00");
+        // already the weekend, the next one
+        assert_eq!(weekend("2023-03-18T07:00"), "2023-03-25T08:00");
+        assert_eq!(weekend("2023-03-18T10:00"), "2023-03-25T08:00");
+        assert_eq!(weekend("2023-03-19T10:00"), "2023-03-25T08:00");
+    }
+
+    #[test]
+    fn other_presets() {
+        assert_eq!(
+            until(SnoozePreset::LaterToday, Utc, "2023-03-17T22:40"),
+            "2023-03-18T01:00"
+        );
+        assert_eq!(
+            until(SnoozePreset::TomorrowMorning, Utc, "2023-03-17T23:59"),
+            "2023-03-18T08:00"
+        );
+        assert_eq!(
+            until(SnoozePreset::NextWeek, Utc, "2023-03-19T10:00"),
+            "2023-03-20T08:00"
+        );
+        assert_eq!(
+            until(SnoozePreset::NextWeek, Utc, "2023-03-20T10:00"),
+            "2023-03-27T08:00"
+        );
+    }
+
+    #[test]
+    fn dst_gap_moves_an_hour_later() {
+        // 02:30 doesn't exist on 2023-03-26, 03:30 summer time does
+        let skipped = SnoozePreset::Custom(naive("2023-03-26T02:30"));
+        assert_eq!(
+            until(skipped, Berlin2023, "2023-03-25T12:00"),
+            "2023-03-26T01:30"
+        );
+        // later today lands on the skipped hour too
+        assert_eq!(
+            until(SnoozePreset::LaterToday, Berlin2023, "2023-03-25T23:10"),
+            "2023-03-26T01:00"
+        );
+        // the morning after the change is at +2
+        assert_eq!(
+            until(
+                SnoozePreset::TomorrowMorning,
+                Berlin2023,
+                "2023-03-25T22:00"
+            ),
+            "2023-03-26T06:00"
+        );
+    }
+
+    #[test]
+    fn dst_overlap_takes_the_first() {
+        // 02:30 happens twice on 2023-10-29, at +2 and then at +1
+        let twice = SnoozePreset::Custom(naive("2023-10-29T02:30"));
+        assert_eq!(
+            until(twice, Berlin2023, "2023-10-28T12:00"),
+            "2023-10-29T00:30"
+        );
+        assert_eq!(
+            until(
+                SnoozePreset::TomorrowMorning,
+                Berlin2023,
+                "2023-10-28T22:00"
+            ),
+            "2023-10-29T07:00"
+        );
+    }
+}
@@ -1,72 +1,996 @@
-use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    thread,
+    time::Instant,
+};
 
-use chrono::{DateTime, Utc};
+use chrono::{NaiveDate, Utc};
+use futures::{stream, StreamExt};
+use himalaya_lib::{Email, Emails};
+use imap_proto::NameAttribute;
+use itertools::Itertools;
+use serde_derive::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
 
 use crate::{
-    config::{self, DatabaseConfig},
-    database::{message_to_db, save_records},
+    config::{AccountConfig, DatabaseConfig},
+    database::{self, message_to_db, save_records, SaveOutcome},
+    imap_session::{self, GmailAttributes, Session},
+    log::log,
     messages::{self, MessageFilter},
+    models::{BackfillState, SyncState},
+    threading, write_back,
 };
 
-pub async fn sync_count(
-    database_config: &DatabaseConfig,
-    count: u32,
+// newest messages a folder starts with when background sync finds it never synced
+pub const INITIAL_COUNT: u32 = 50;
+// messages fetched between two saves of the backfill progress
+const BACKFILL_PAGE: u32 = 200;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SyncReport {
+    pub accounts: Vec<AccountReport>,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AccountReport {
+    pub account: String,
+    pub folders: Vec<FolderReport>,
+    // set when the account could not be synced at all
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FolderReport {
+    pub folder: String,
+    // came from the server or disk
+    pub fetched: u32,
+    pub inserted: u32,
+    pub updated: u32,
+    // could not be parsed into a message
+    pub skipped: u32,
+    pub failed: u32,
+    // already stored, not fetched again
+    pub present: u32,
+    // set when the folder stopped early
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+impl SyncReport {
+    pub fn inserted(&self) -> u32 {
+        self.accounts.iter().map(|a| a.inserted()).sum()
+    }
+}
+
+impl AccountReport {
+    pub fn inserted(&self) -> u32 {
+        self.folders.iter().map(|f| f.inserted).sum()
+    }
+}
+
+impl FolderReport {
+    fn new(folder: &str) -> Self {
+        FolderReport {
+            folder: folder.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn count(&mut self, outcome: SaveOutcome) {
+        match outcome {
+            SaveOutcome::Inserted => self.inserted += 1,
+            SaveOutcome::Updated => self.updated += 1,
+        }
+    }
+
+    // one message in, failures are kept in sync_errors with the raw bytes so
+    // `sync --retry-failed` can try them again
+    fn store(
+        &mut self,
+        database_config: &DatabaseConfig,
+        account: &str,
+        uid: Option<u32>,
+        raw: Vec<u8>,
+        stored: Option<Result<SaveOutcome, String>>,
+    ) {
+        self.fetched += 1;
+        let error = match stored {
+            Some(Ok(outcome)) => return self.count(outcome),
+            Some(Err(e)) => {
+                self.failed += 1;
+                e
+            }
+            None => {
+                self.skipped += 1;
+                "could not parse the message".to_string()
+            }
+        };
+        log(format!("{} {}: {}", account, self.folder, error));
+        if let Err(e) = database::record_sync_error(
+            database_config,
+            account,
+            &self.folder,
+            uid,
+            &error,
+            Some(raw),
+        ) {
+            log(format!("recording the failure: {e}"));
+        }
+    }
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for account in self.accounts.iter() {
+            match &account.error {
+                Some(error) => writeln!(f, "{}: {}", account.account, error)?,
+                None => writeln!(f, "{} ({}ms)", account.account, account.elapsed_ms)?,
+            }
+            for folder in account.folders.iter() {
+                write!(
+                    f,
+                    "  {}: {} fetched, {} new, {} updated, {} skipped, {} failed",
+                    folder.folder,
+                    folder.fetched,
+                    folder.inserted,
+                    folder.updated,
+                    folder.skipped,
+                    folder.failed,
+                )?;
+                if folder.present > 0 {
+                    write!(f, ", {} already stored", folder.present)?;
+                }
+                write!(f, " ({}ms)", folder.elapsed_ms)?;
+                match &folder.error {
+                    Some(error) => writeln!(f, " {error}")?,
+                    None => writeln!(f)?,
+                }
+            }
+        }
+        write!(
+            f,
+            "{} new messages in {}ms",
+            self.inserted(),
+            self.elapsed_ms
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncProgress {
+    pub account: String,
+    pub folder: String,
+    pub done: u32,
+    pub total: u32,
+}
+
+// where sync reports how far along a folder is, the app shows it as a progress bar
+#[derive(Debug, Clone, Default)]
+pub struct Progress(Option<UnboundedSender<SyncProgress>>);
+
+impl Progress {
+    pub fn new(sender: UnboundedSender<SyncProgress>) -> Self {
+        Progress(Some(sender))
+    }
+
+    fn send(&self, account: &str, folder: &str, done: u32, total: u32) {
+        if let Some(sender) = &self.0 {
+            sender
+                .send(SyncProgress {
+                    account: account.to_owned(),
+                    folder: folder.to_owned(),
+                    done,
+                    total,
+                })
+                .ok();
+        }
+    }
+}
+
+fn elapsed_ms(start: Instant) -> u64 {
+    start.elapsed().as_millis() as u64
+}
+
+fn filter_accounts(
+    accounts: &[AccountConfig],
     account_names: Option<Vec<String>>,
-) -> Result<u32, String> {
-    let accounts = config::get_accounts(config::default_config_path());
-    let accounts = if let Some(filter) = account_names {
+) -> Vec<AccountConfig> {
+    let accounts = accounts.to_vec();
+    if let Some(filter) = account_names {
         accounts
             .into_iter()
             .filter(|account| filter.contains(&account.name))
             .collect::<Vec<_>>()
     } else {
         accounts
+    }
+}
+
+// fetches only what arrived since the last run. folders without a stored mark,
+// or whose UIDVALIDITY changed, start over with the newest `initial_count` messages.
+pub async fn sync_incremental(
+    database_config: &DatabaseConfig,
+    initial_count: u32,
+    accounts: &[AccountConfig],
+    account_names: Option<Vec<String>>,
+    jobs: usize,
+    progress: &Progress,
+) -> Result<SyncReport, String> {
+    let start = Instant::now();
+    let accounts = filter_accounts(accounts, account_names);
+
+    // local changes go up before anything comes down
+    if let Err(e) = write_back::replay(database_config, &accounts) {
+        log(format!("write back failed: {e}"));
+    }
+
+    let accounts = for_each_account(accounts, jobs, |account| {
+        let database_config = database_config.clone();
+        let progress = progress.clone();
+        move || sync_account(&database_config, &account, initial_count, &progress)
+    })
+    .await;
+    Ok(SyncReport {
+        accounts,
+        elapsed_ms: elapsed_ms(start),
+    })
+}
+
+// runs `sync` for up to `jobs` accounts at a time on the blocking pool. reports come
+// back in account order.
+async fn for_each_account<F, S>(
+    accounts: Vec<AccountConfig>,
+    jobs: usize,
+    sync: F,
+) -> Vec<AccountReport>
+where
+    F: Fn(AccountConfig) -> S,
+    S: FnOnce() -> AccountReport + Send + 'static,
+{
+    stream::iter(accounts.into_iter().map(|account| {
+        let name = account.name.clone();
+        let task = tokio::task::spawn_blocking(sync(account));
+        async move {
+            task.await.unwrap_or_else(|e| AccountReport {
+                account: name,
+                error: Some(e.to_string()),
+                ..Default::default()
+            })
+        }
+    }))
+    .buffered(jobs.max(1))
+    .collect::<Vec<_>>()
+    .await
+}
+
+// incremental sync of every configured folder of one account
+pub fn sync_account(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    initial_count: u32,
+    progress: &Progress,
+) -> AccountReport {
+    each_folder(account, |session, gmail, report| {
+        sync_folder(
+            database_config,
+            account,
+            session,
+            initial_count,
+            gmail,
+            progress,
+            report,
+        )
+    })
+}
+
+// runs `sync` for every configured folder on one connection
+fn each_folder<F>(account: &AccountConfig, mut sync: F) -> AccountReport
+where
+    F: FnMut(&mut Session, bool, &mut FolderReport) -> Result<(), String>,
+{
+    let start = Instant::now();
+    let mut report = AccountReport {
+        account: account.name.clone(),
+        ..Default::default()
+    };
+    let mut session = match imap_session::connect(account) {
+        Ok(session) => session,
+        Err(e) => {
+            report.error = Some(e);
+            report.elapsed_ms = elapsed_ms(start);
+            return report;
+        }
+    };
+    let gmail = imap_session::has_gmail_extensions(&mut session);
+    for folder in folders_to_sync(account).into_iter() {
+        let folder_start = Instant::now();
+        let mut folder_report = FolderReport::new(&folder);
+        let mut result = sync(&mut session, gmail, &mut folder_report);
+        // a dropped connection is tried again on a new one, backing off like connect
+        let mut delay = imap_session::RETRY_START;
+        let mut attempt = 1;
+        while result.is_err()
+            && attempt < imap_session::CONNECT_ATTEMPTS
+            && !imap_session::is_alive(&mut session)
+        {
+            log(format!(
+                "{} {}: connection lost, trying again in {}s",
+                account.name,
+                folder,
+                delay.as_secs()
+            ));
+            thread::sleep(delay);
+            delay *= 2;
+            attempt += 1;
+            match imap_session::connect(account) {
+                Ok(reconnected) => {
+                    session = reconnected;
+                    folder_report = FolderReport::new(&folder);
+                    result = sync(&mut session, gmail, &mut folder_report);
+                }
+                Err(e) => {
+                    folder_report.error = result.err();
+                    folder_report.elapsed_ms = elapsed_ms(folder_start);
+                    report.folders.push(folder_report);
+                    report.error = Some(e);
+                    report.elapsed_ms = elapsed_ms(start);
+                    return report;
+                }
+            }
+        }
+        if let Err(e) = result {
+            // a folder missing on this server shouldn't stop the others
+            log(format!("{} {}: {}", account.name, folder, e));
+            folder_report.error = Some(e);
+        }
+        folder_report.elapsed_ms = elapsed_ms(folder_start);
+        report.folders.push(folder_report);
+    }
+    session.logout().ok();
+    report.elapsed_ms = elapsed_ms(start);
+    report
+}
+
+pub fn folders_to_sync(account: &AccountConfig) -> Vec<String> {
+    let Some(configured) = &account.sync.folders else {
+        // the inbox plus whatever the server marks as sent and all mail
+        let mut folders = vec!["INBOX".to_string()];
+        let special = [NameAttribute::Sent, NameAttribute::All];
+        match messages::special_use_folders(account, &special) {
+            Ok(special) => folders.extend(special),
+            Err(e) => log(format!("listing folders for {}: {}", account.name, e)),
+        }
+        return folders;
     };
+    if configured.iter().any(|f| f == "*") {
+        match messages::list_folders(account) {
+            Ok(folders) => return folders,
+            Err(e) => log(format!("listing folders for {}: {}", account.name, e)),
+        }
+    }
+    configured.iter().filter(|f| *f != "*").cloned().collect()
+}
 
-    for account in accounts.into_iter() {
+// counts go into `report` as they happen so an error halfway keeps them
+fn sync_folder(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    session: &mut Session,
+    initial_count: u32,
+    gmail: bool,
+    progress: &Progress,
+    report: &mut FolderReport,
+) -> Result<(), String> {
+    let folder = report.folder.clone();
+    let status = imap_session::select(session, &folder)?;
+    let state = database::get_sync_state(database_config, &account.name, &folder);
+    let (after_uid, limit) = match &state {
+        Some(state) if state.uid_validity == status.uid_validity as i64 => {
+            (state.highest_uid as u32, None)
+        }
+        Some(state) => {
+            log(format!(
+                "{} {}: UIDVALIDITY changed from {} to {}, starting over",
+                account.name, folder, state.uid_validity, status.uid_validity
+            ));
+            (0, Some(initial_count))
+        }
+        None => (0, Some(initial_count)),
+    };
+
+    let uids = imap_session::uids_after(session, after_uid, limit)?;
+    let total = uids.len() as u32;
+    progress.send(&account.name, &folder, 0, total);
+    let fetched = imap_session::fetch_uids(session, &uids)?;
+    let mut gmail_attributes = if gmail {
+        imap_session::fetch_gmail(session, &uids)?
+    } else {
+        HashMap::new()
+    };
+
+    let mut highest_uid = after_uid;
+    for (done, message) in fetched.into_iter().enumerate() {
+        let uid = message.uid;
+        let seen = message.flags.iter().any(|f| f == "\\Seen");
+        let gmail = gmail_attributes.remove(&uid);
+        let stored = store_raw(
+            database_config,
+            account,
+            &folder,
+            message.raw.clone(),
+            Some(uid),
+            Some(seen),
+            gmail.as_ref(),
+        );
+        report.store(
+            database_config,
+            &account.name,
+            Some(uid),
+            message.raw,
+            stored,
+        );
+        // failures are in sync_errors, the mark moves past them
+        highest_uid = highest_uid.max(uid);
+        progress.send(&account.name, &folder, done as u32 + 1, total);
+    }
+    // the search ran after the SELECT, so everything below UIDNEXT has been seen.
+    // mail that was expunged right away would otherwise look new to IDLE forever.
+    if let Some(uid_next) = status.uid_next {
+        highest_uid = highest_uid.max(uid_next.saturating_sub(1));
+    }
+    // moved or deleted on the server, it no longer shows under this folder
+    let present = imap_session::all_uids(session)?;
+    database::prune_folder(database_config, &account.name, &folder, &present)?;
+    // read or unread elsewhere since it was fetched
+    let seen = imap_session::seen_uids(session)?;
+    database::refresh_seen(database_config, &account.name, &folder, &seen)?;
+    // and relabeled, the labels of new mail came with it above
+    if gmail {
+        let stored = present
+            .iter()
+            .copied()
+            .filter(|uid| *uid <= after_uid)
+            .collect::<Vec<_>>();
+        // both marks are needed, the one from the last sync and the server's now
+        let changed_since = state
+            .filter(|_| after_uid > 0 && status.highest_modseq.is_some())
+            .and_then(|state| state.highest_modseq)
+            .map(|modseq| modseq as u64);
+        let labels = imap_session::fetch_gmail_labels(session, &stored, changed_since)?
+            .into_iter()
+            .map(|(uid, attributes)| (uid, attributes.labels))
+            .collect();
+        database::refresh_labels(database_config, &account.name, &folder, &labels)?;
+    }
+
+    database::save_sync_state(
+        database_config,
+        SyncState {
+            id: None,
+            account: account.name.clone(),
+            folder: folder.clone(),
+            uid_validity: status.uid_validity as i64,
+            highest_uid: highest_uid as i64,
+            last_synced_at: Some(Utc::now().to_rfc3339()),
+            highest_modseq: status.highest_modseq.map(|modseq| modseq as i64),
+        },
+    )
+}
+
+pub fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+        .map_err(|e| format!("\"{value}\" is not a YYYY-MM-DD date: {e}"))
+}
+
+// pulls everything with an internal date in `since..=until` that isn't stored yet.
+// progress is saved after every page so an interrupted run picks up where it stopped.
+pub async fn backfill(
+    database_config: &DatabaseConfig,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    accounts: &[AccountConfig],
+    account_names: Option<Vec<String>>,
+    jobs: usize,
+    progress: &Progress,
+) -> Result<SyncReport, String> {
+    let start = Instant::now();
+    if let (Some(since), Some(until)) = (since, until) {
+        if since > until {
+            return Err(format!("{since} is after {until}"));
+        }
+    }
+    let accounts = filter_accounts(accounts, account_names);
+    let accounts = for_each_account(accounts, jobs, |account| {
+        let database_config = database_config.clone();
+        let progress = progress.clone();
+        move || {
+            each_folder(&account, |session, gmail, report| {
+                let filter = MessageFilter {
+                    since,
+                    // BEFORE leaves the day itself out
+                    before: until.and_then(|until| until.succ_opt()),
+                    limit: Some(BACKFILL_PAGE),
+                    page: Some(0),
+                    folder: report.folder.clone(),
+                };
+                backfill_folder(
+                    &database_config,
+                    &account,
+                    session,
+                    filter,
+                    gmail,
+                    &progress,
+                    report,
+                )
+            })
+        }
+    })
+    .await;
+    Ok(SyncReport {
+        accounts,
+        elapsed_ms: elapsed_ms(start),
+    })
+}
+
+fn backfill_folder(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    session: &mut Session,
+    mut filter: MessageFilter,
+    gmail: bool,
+    progress: &Progress,
+    report: &mut FolderReport,
+) -> Result<(), String> {
+    let folder = report.folder.clone();
+    let status = imap_session::select(session, &folder)?;
+    let window = (
+        filter.since.map(|d| d.to_string()).unwrap_or_default(),
+        filter.before.map(|d| d.to_string()).unwrap_or_default(),
+    );
+    let after_uid = database::get_backfill_state(
+        database_config,
+        &account.name,
+        &folder,
+        &window.0,
+        &window.1,
+    )
+    .filter(|state| state.uid_validity == status.uid_validity as i64)
+    .map(|state| state.last_uid as u32)
+    .unwrap_or_default();
+    // stored uids belong to the UIDVALIDITY incremental sync last saw
+    let stored = match database::get_sync_state(database_config, &account.name, &folder) {
+        Some(state) if state.uid_validity != status.uid_validity as i64 => HashSet::new(),
+        _ => database::folder_uids(database_config, &account.name, &folder)?,
+    };
+
+    let uids = imap_session::search_dates(session, &filter, after_uid)?;
+    let total = uids.len() as u32;
+    let mut done = 0;
+    progress.send(&account.name, &folder, done, total);
+    loop {
+        let page = page_of(&uids, &filter);
+        let Some(last_uid) = page.last().copied() else {
+            break;
+        };
+        let (known, missing): (Vec<u32>, Vec<u32>) =
+            page.iter().copied().partition(|uid| stored.contains(uid));
+        report.present += known.len() as u32;
+        done += known.len() as u32;
+
+        let fetched = imap_session::fetch_uids(session, &missing)?;
+        let mut gmail_attributes = if gmail {
+            imap_session::fetch_gmail(session, &missing)?
+        } else {
+            HashMap::new()
+        };
+        for message in fetched.into_iter() {
+            let uid = message.uid;
+            let seen = message.flags.iter().any(|f| f == "\\Seen");
+            let gmail = gmail_attributes.remove(&uid);
+            let stored = store_raw(
+                database_config,
+                account,
+                &folder,
+                message.raw.clone(),
+                Some(uid),
+                Some(seen),
+                gmail.as_ref(),
+            );
+            report.store(
+                database_config,
+                &account.name,
+                Some(uid),
+                message.raw,
+                stored,
+            );
+            done += 1;
+            progress.send(&account.name, &folder, done, total);
+        }
+
+        database::save_backfill_state(
+            database_config,
+            BackfillState {
+                id: None,
+                account: account.name.clone(),
+                folder: folder.clone(),
+                since: window.0.clone(),
+                before: window.1.clone(),
+                uid_validity: status.uid_validity as i64,
+                last_uid: last_uid as i64,
+                updated_at: Some(Utc::now().to_rfc3339()),
+            },
+        )?;
+        filter.page = Some(filter.page.unwrap_or_default() + 1);
+    }
+    Ok(())
+}
+
+// `filter.page` of `filter.limit` uids, empty past the end
+fn page_of<'a>(uids: &'a [u32], filter: &MessageFilter) -> &'a [u32] {
+    let limit = filter.limit.unwrap_or(BACKFILL_PAGE).max(1) as usize;
+    uids.chunks(limit)
+        .nth(filter.page.unwrap_or_default() as usize)
+        .unwrap_or_default()
+}
+
+// None when the message could not be parsed, an error when part of it couldn't be
+// decoded. `seen` is left alone when unknown.
+fn store_raw(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    folder: &str,
+    raw: Vec<u8>,
+    uid: Option<u32>,
+    seen: Option<bool>,
+    gmail: Option<&GmailAttributes>,
+) -> Option<Result<SaveOutcome, String>> {
+    let emails = Emails::from(vec![raw]);
+    let email = emails.to_vec().into_iter().next()?;
+    let (raw, mut record) =
+        match message_to_db(email, account, folder, database_config).transpose()? {
+            Ok(stored) => stored,
+            Err(e) => return Some(Err(e)),
+        };
+    if let Some(seen) = seen {
+        record.seen_at = seen.then(|| database::db_timestamp(Utc::now()));
+    }
+    if let Some(gmail) = gmail {
+        record.gm_msgid = gmail.msgid.map(|id| id as i64);
+        record.gm_thrid = gmail.thrid.map(|id| id as i64);
+        if let Some(thrid) = gmail.thrid {
+            record.parent_thread_key = Some(threading::gmail_thread_key(thrid));
+        }
+    }
+    let message_id = record.message_id.clone();
+    let outcome = save_records(database_config, raw, record, uid);
+    if let (Ok(_), Some(gmail)) = (&outcome, gmail) {
+        if let Err(e) =
+            database::save_labels(database_config, &account.name, &message_id, &gmail.labels)
+        {
+            return Some(Err(e));
+        }
+    }
+    Some(outcome)
+}
+
+// goes through sync_errors again. what stores now is cleared, the rest stays
+// with one more attempt counted.
+pub async fn retry_failed(
+    database_config: &DatabaseConfig,
+    accounts: &[AccountConfig],
+    progress: &Progress,
+) -> Result<SyncReport, String> {
+    let start = Instant::now();
+    let failures = database::sync_errors(database_config)?;
+
+    let mut report = SyncReport::default();
+    for ((account_name, folder), failures) in &failures
+        .into_iter()
+        .group_by(|f| (f.account.clone(), f.folder.clone()))
+    {
+        let folder_start = Instant::now();
+        let account = match accounts.iter().find(|a| a.name == account_name) {
+            Some(account) => account,
+            None => {
+                log(format!("retry: no account named {account_name}"));
+                continue;
+            }
+        };
+        let failures = failures.collect::<Vec<_>>();
+        let total = failures.len() as u32;
+        let mut folder_report = FolderReport::new(&folder);
+        progress.send(&account.name, &folder, 0, total);
+        for (done, failure) in failures.into_iter().enumerate() {
+            folder_report.fetched += 1;
+            let id = failure.id.unwrap_or_default();
+            let uid = failure.uid.map(|uid| uid as u32);
+            let stored = match failure.raw {
+                Some(raw) => store_raw(database_config, account, &folder, raw, uid, None, None),
+                None => Some(Err("no raw message was kept".to_string())),
+            };
+            match stored {
+                Some(Ok(outcome)) => {
+                    folder_report.count(outcome);
+                    database::clear_sync_error(database_config, id)?;
+                }
+                Some(Err(e)) => {
+                    folder_report.failed += 1;
+                    database::fail_sync_error(database_config, id, &e)?;
+                }
+                None => {
+                    folder_report.skipped += 1;
+                    database::fail_sync_error(database_config, id, "could not parse the message")?;
+                }
+            }
+            progress.send(&account.name, &folder, done as u32 + 1, total);
+        }
+        folder_report.elapsed_ms = elapsed_ms(folder_start);
+        match report
+            .accounts
+            .iter_mut()
+            .find(|a| a.account == account_name)
+        {
+            Some(account_report) => {
+                account_report.elapsed_ms += folder_report.elapsed_ms;
+                account_report.folders.push(folder_report);
+            }
+            None => report.accounts.push(AccountReport {
+                account: account_name,
+                elapsed_ms: folder_report.elapsed_ms,
+                folders: vec![folder_report],
+                error: None,
+            }),
+        }
+    }
+    report.elapsed_ms = elapsed_ms(start);
+    Ok(report)
+}
+
+pub async fn sync_count(
+    database_config: &DatabaseConfig,
+    count: u32,
+    accounts: &[AccountConfig],
+    account_names: Option<Vec<String>>,
+    jobs: usize,
+    progress: &Progress,
+) -> Result<SyncReport, String> {
+    let start = Instant::now();
+    let accounts = filter_accounts(accounts, account_names);
+
+    let accounts = for_each_account(accounts, jobs, |account| {
+        let database_config = database_config.clone();
+        let progress = progress.clone();
+        move || count_account(&database_config, &account, count, &progress)
+    })
+    .await;
+    Ok(SyncReport {
+        accounts,
+        elapsed_ms: elapsed_ms(start),
+    })
+}
+
+// the newest `count` messages of every folder through himalaya
+fn count_account(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    count: u32,
+    progress: &Progress,
+) -> AccountReport {
+    let start = Instant::now();
+    let mut report = AccountReport {
+        account: account.name.clone(),
+        ..Default::default()
+    };
+    for folder in folders_to_sync(account).into_iter() {
+        let folder_start = Instant::now();
         let messages = messages::get_messages(
-            &account,
+            account,
             MessageFilter {
                 limit: Some(count),
-                folder: "INBOX".into(),
+                folder: folder.clone(),
                 ..Default::default()
             },
         );
-
-        for message in messages.to_vec().into_iter() {
-            if let Some((raw, record)) = message_to_db(&message, &account, &database_config) {
-                save_records(database_config, raw, record).map_err(|e| format!("{:?}", e))?;
+        let mut folder_report = match messages {
+            // himalaya only hands out the bytes of mail it could parse
+            Ok(messages) => store_all(
+                database_config,
+                account,
+                &folder,
+                messages
+                    .to_vec()
+                    .into_iter()
+                    .map(|message| message.raw().map(<[u8]>::to_vec).unwrap_or_default())
+                    .collect(),
+                progress,
+            ),
+            Err(e) => {
+                log(format!("{} {}: {}", account.name, folder, e));
+                FolderReport {
+                    error: Some(e),
+                    ..FolderReport::new(&folder)
+                }
             }
-        }
+        };
+        folder_report.elapsed_ms = elapsed_ms(folder_start);
+        report.folders.push(folder_report);
     }
-
-    Ok(4)
+    report.elapsed_ms = elapsed_ms(start);
+    report
 }
 
 pub async fn load_files(
     database_config: &DatabaseConfig,
     path: &PathBuf,
+    accounts: &[AccountConfig],
     account_names: Option<Vec<String>>,
-) -> Result<u32, String> {
-    let accounts = config::get_accounts(config::default_config_path());
-    let accounts = if let Some(filter) = account_names {
-        accounts
-            .into_iter()
-            .filter(|account| filter.contains(&account.name))
-            .collect::<Vec<_>>()
-    } else {
-        accounts
-    };
+    progress: &Progress,
+) -> Result<SyncReport, String> {
+    let start = Instant::now();
+    let accounts = filter_accounts(accounts, account_names);
 
+    let mut report = SyncReport::default();
     for account in accounts.into_iter() {
-        let messages = messages::load_messages(path);
-
-        for message in messages.to_vec().into_iter() {
-            if let Some((raw, record)) = message_to_db(&message, &account, &database_config) {
-                save_records(database_config, raw, record).map_err(|e| format!("{:?}", e))?;
+        let account_start = Instant::now();
+        let (folders, error) = match messages::load_messages(path) {
+            Ok(messages) => {
+                let mut folder_report =
+                    store_all(database_config, &account, "INBOX", messages, progress);
+                folder_report.elapsed_ms = elapsed_ms(account_start);
+                (vec![folder_report], None)
             }
+            Err(e) => (vec![], Some(e)),
+        };
+        report.accounts.push(AccountReport {
+            account: account.name.clone(),
+            folders,
+            error,
+            elapsed_ms: elapsed_ms(account_start),
+        });
+    }
+    report.elapsed_ms = elapsed_ms(start);
+    Ok(report)
+}
+
+// messages that come without uids, from himalaya or files on disk. the bytes are kept
+// as they came so a message that doesn't parse is recorded whole.
+fn store_all(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    folder: &str,
+    messages: Vec<Vec<u8>>,
+    progress: &Progress,
+) -> FolderReport {
+    let mut report = FolderReport::new(folder);
+    let total = messages.len() as u32;
+    progress.send(&account.name, folder, 0, total);
+    for (done, raw) in messages.into_iter().enumerate() {
+        let stored = message_to_db(
+            &Email::from(raw.as_slice()),
+            account,
+            folder,
+            database_config,
+        )
+        .transpose()
+        .map(|stored| {
+            stored.and_then(|(raw, record)| save_records(database_config, raw, record, None))
+        });
+        report.store(database_config, &account.name, None, raw, stored);
+        progress.send(&account.name, folder, done as u32 + 1, total);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account() -> AccountConfig {
+        toml::from_str(
+            r#"
+            name = "test"
+            default = true
+            [account]
+            email = "me@example.com"
+            display_name = "Me"
+            [imap]
+            login = "me@example.com"
+            passwd = "secret"
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn filter(limit: Option<u32>, page: Option<u32>) -> MessageFilter {
+        MessageFilter {
+            since: None,
+            before: None,
+            limit,
+            page,
+            folder: "INBOX".to_owned(),
+        }
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(
+            parse_date("2023-03-01"),
+            Ok(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap())
+        );
+        assert_eq!(
+            parse_date(" 2024-02-29\n"),
+            Ok(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_other_dates() {
+        for value in [
+            "",
+            "2023-02-29",
+            "2023-13-01",
+            "01-03-2023",
+            "2023/03/01",
+            "yesterday",
+        ] {
+            let e = parse_date(value).unwrap_err();
+            assert!(e.contains("is not a YYYY-MM-DD date"), "{value}: {e}");
         }
     }
 
-    Ok(4)
+    #[test]
+    fn pages_through_uids() {
+        let uids = (1..=5).collect::<Vec<u32>>();
+        assert_eq!(page_of(&uids, &filter(Some(2), None)), &[1, 2]);
+        assert_eq!(page_of(&uids, &filter(Some(2), Some(1))), &[3, 4]);
+        assert_eq!(page_of(&uids, &filter(Some(2), Some(2))), &[5]);
+        assert!(page_of(&uids, &filter(Some(2), Some(3))).is_empty());
+        assert!(page_of(&[], &filter(Some(2), Some(0))).is_empty());
+    }
+
+    #[test]
+    fn pages_default_to_backfill_size() {
+        let uids = (1..=BACKFILL_PAGE + 1).collect::<Vec<u32>>();
+        assert_eq!(
+            page_of(&uids, &filter(None, None)).len(),
+            BACKFILL_PAGE as usize
+        );
+        assert_eq!(page_of(&uids, &filter(None, Some(1))), &[BACKFILL_PAGE + 1]);
+        // a zero limit would never move forward
+        assert_eq!(page_of(&uids, &filter(Some(0), Some(0))), &[1]);
+    }
+
+    #[test]
+    fn malformed_message_fails_alone() {
+        let (database_config, _keep) = crate::database::tests::scratch_database("malformed");
+        let multipart = |id: &str, body: &str| {
+            format!(
+                "Message-ID: <{id}@example.com>\r\nSubject: {id}\r\nMIME-Version: 1.0\r\n\
+                 Content-Type: multipart/alternative; boundary=b\r\n\r\n\
+                 --b\r\nContent-Type: text/plain\r\nContent-Transfer-Encoding: base64\r\n\r\n\
+                 {body}\r\n--b--\r\n"
+            )
+            .into_bytes()
+        };
+        let report = store_all(
+            &database_config,
+            &account(),
+            "INBOX",
+            vec![
+                multipart("bad", "!!!not base64!!!"),
+                multipart("good", "aGVsbG8="),
+            ],
+            &Progress(None),
+        );
+        assert_eq!((report.fetched, report.failed, report.inserted), (2, 1, 1));
+        let errors = crate::database::sync_errors(&database_config).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].error.contains("text/plain"));
+        assert!(errors[0].raw.is_some());
+    }
 }
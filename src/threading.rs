@@ -0,0 +1,329 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+// https://www.jwz.org/doc/threading.html
+// messages are linked through every id in References/In-Reply-To, ids we have
+// never seen become empty containers so replies that arrive before their parent
+// still land in the right thread. the key of a thread is the hash of its root id.
+
+#[derive(Debug, Clone, Default)]
+pub struct ThreadMessage {
+    pub message_id: String,
+    // oldest ancestor first, direct parent last
+    pub references: Vec<String>,
+    pub subject: Option<String>,
+    pub sent_date: Option<i64>,
+    // lowercased From/To/Cc addresses
+    pub participants: Vec<String>,
+}
+
+// a reply without references joins a thread on subject only when it came this soon
+// after the thread started
+pub const SUBJECT_WINDOW: i64 = 30 * 24 * 60 * 60;
+
+// whether `reply`, which lost its references, can belong to the thread `original`
+// started: same subject is not enough, it has to be recent and share a participant
+pub fn same_conversation(original: &ThreadMessage, reply: &ThreadMessage) -> bool {
+    let recent = match (original.sent_date, reply.sent_date) {
+        (Some(original), Some(reply)) => (0..=SUBJECT_WINDOW).contains(&(reply - original)),
+        _ => false,
+    };
+    recent
+        && original
+            .participants
+            .iter()
+            .any(|address| reply.participants.contains(address))
+}
+
+pub fn thread_key(root_id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(root_id);
+    format!("{:X}", hasher.finalize())
+}
+
+// gmail already threads the conversation, X-GM-THRID is used as is
+pub fn gmail_thread_key(thrid: u64) -> String {
+    format!("gm-{thrid:x}")
+}
+
+// References followed by In-Reply-To when it isn't already in there.
+pub fn references(references: Option<&String>, in_reply_to: Option<&String>) -> Vec<String> {
+    let mut ids = references.map(|r| message_ids(r)).unwrap_or_default();
+    if let Some(parent) = in_reply_to.and_then(|r| message_ids(r).pop()) {
+        if !ids.contains(&parent) {
+            ids.push(parent);
+        }
+    }
+    ids
+}
+
+pub fn message_ids(header: &str) -> Vec<String> {
+    let mut ids = vec![];
+    let mut rest = header;
+    while let Some(start) = rest.find('<') {
+        match rest[start..].find('>') {
+            Some(end) => {
+                let id = &rest[start..start + end + 1];
+                if id.len() > 2 && !ids.iter().any(|i| i == id) {
+                    ids.push(id.to_owned());
+                }
+                rest = &rest[start + end + 1..];
+            }
+            None => break,
+        }
+    }
+    ids
+}
+
+// lowercased subject without any number of re:/fwd: prefixes, and whether it had one
+pub fn normalize_subject(subject: &str) -> (String, bool) {
+    let mut subject = subject.trim().to_lowercase();
+    let mut reply = false;
+    loop {
+        let stripped = ["re:", "fwd:", "fw:", "aw:"].iter().find_map(|prefix| {
+            subject
+                .strip_prefix(prefix)
+                .map(|s| s.trim_start().to_owned())
+        });
+        match stripped {
+            Some(s) => {
+                subject = s;
+                reply = true;
+            }
+            None => break,
+        }
+    }
+    (subject, reply)
+}
+
+#[derive(Debug, Default)]
+struct Container {
+    parent: Option<String>,
+}
+
+fn is_ancestor(containers: &HashMap<String, Container>, ancestor: &str, id: &str) -> bool {
+    let mut seen = HashSet::new();
+    let mut current = Some(id.to_owned());
+    while let Some(node) = current {
+        if node == ancestor {
+            return true;
+        }
+        if !seen.insert(node.clone()) {
+            return false;
+        }
+        current = containers.get(&node).and_then(|c| c.parent.clone());
+    }
+    false
+}
+
+fn root(containers: &HashMap<String, Container>, id: &str) -> String {
+    let mut seen = HashSet::new();
+    let mut current = id.to_owned();
+    while let Some(parent) = containers.get(&current).and_then(|c| c.parent.clone()) {
+        if !seen.insert(current.clone()) {
+            break;
+        }
+        current = parent;
+    }
+    current
+}
+
+// message_id -> thread key for every message passed in
+pub fn thread(messages: &[ThreadMessage]) -> HashMap<String, String> {
+    let mut containers: HashMap<String, Container> = HashMap::new();
+
+    for message in messages.iter() {
+        containers.entry(message.message_id.clone()).or_default();
+        for id in message.references.iter() {
+            containers.entry(id.clone()).or_default();
+        }
+        // link the reference chain without overriding links we already know
+        for pair in message.references.windows(2) {
+            let (parent, child) = (&pair[0], &pair[1]);
+            if parent == child
+                || containers[child].parent.is_some()
+                || is_ancestor(&containers, child, parent)
+            {
+                continue;
+            }
+            containers.get_mut(child).unwrap().parent = Some(parent.clone());
+        }
+        // the message itself always hangs off its last reference
+        let parent = message
+            .references
+            .last()
+            .filter(|p| {
+                **p != message.message_id && !is_ancestor(&containers, &message.message_id, p)
+            })
+            .cloned();
+        if parent.is_some() || message.references.is_empty() {
+            containers.get_mut(&message.message_id).unwrap().parent = parent;
+        }
+    }
+
+    // group by root, the oldest message decides the subject of the thread
+    let mut roots: HashMap<String, &ThreadMessage> = HashMap::new();
+    for message in messages.iter() {
+        let root = root(&containers, &message.message_id);
+        roots
+            .entry(root)
+            .and_modify(|oldest| {
+                if message.sent_date.unwrap_or(i64::MAX) < oldest.sent_date.unwrap_or(i64::MAX) {
+                    *oldest = message;
+                }
+            })
+            .or_insert(message);
+    }
+
+    // a root that is a reply lost its references, put it under the latest thread that
+    // started with the same subject and is the same conversation. two roots that both
+    // started fresh stay apart.
+    let mut originals: HashMap<String, Vec<(&String, &ThreadMessage)>> = HashMap::new();
+    for (root, oldest) in roots.iter() {
+        let (subject, reply) = normalize_subject(oldest.subject.as_deref().unwrap_or_default());
+        if !reply && !subject.is_empty() {
+            originals.entry(subject).or_default().push((root, oldest));
+        }
+    }
+    let mut merged: HashMap<String, String> = HashMap::new();
+    for (root, oldest) in roots.iter() {
+        let (subject, reply) = normalize_subject(oldest.subject.as_deref().unwrap_or_default());
+        let target = reply
+            .then(|| originals.get(&subject))
+            .flatten()
+            .and_then(|candidates| {
+                candidates
+                    .iter()
+                    .filter(|(_, original)| same_conversation(original, oldest))
+                    .max_by(|a, b| a.1.sent_date.cmp(&b.1.sent_date).then(b.0.cmp(a.0)))
+            })
+            .map(|(original, _)| (*original).clone())
+            .unwrap_or_else(|| root.clone());
+        merged.insert(root.clone(), target);
+    }
+
+    messages
+        .iter()
+        .map(|message| {
+            let root = root(&containers, &message.message_id);
+            let root = merged.get(&root).cloned().unwrap_or(root);
+            (message.message_id.clone(), thread_key(&root))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn message(id: &str, references: &[&str]) -> ThreadMessage {
+        ThreadMessage {
+            message_id: id.to_owned(),
+            references: references.iter().map(|r| r.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn with_subject(
+        mut message: ThreadMessage,
+        subject: &str,
+        day: i64,
+        from: &str,
+    ) -> ThreadMessage {
+        message.subject = Some(subject.to_owned());
+        message.sent_date = Some(day * DAY);
+        message.participants = vec![from.to_owned()];
+        message
+    }
+
+    #[test]
+    fn follows_reference_chains() {
+        let threads = thread(&[
+            message("<a>", &[]),
+            message("<b>", &["<a>"]),
+            message("<c>", &["<a>", "<b>"]),
+            message("<d>", &[]),
+        ]);
+        assert_eq!(threads["<a>"], thread_key("<a>"));
+        assert_eq!(threads["<b>"], thread_key("<a>"));
+        assert_eq!(threads["<c>"], thread_key("<a>"));
+        assert_eq!(threads["<d>"], thread_key("<d>"));
+    }
+
+    #[test]
+    fn missing_parents_still_group() {
+        // <x> was never seen, its replies share it as the root
+        let threads = thread(&[message("<b>", &["<x>"]), message("<c>", &["<x>", "<b>"])]);
+        assert_eq!(threads["<b>"], thread_key("<x>"));
+        assert_eq!(threads["<c>"], thread_key("<x>"));
+
+        // a reply that arrives before its parent
+        let threads = thread(&[message("<c>", &["<a>", "<b>"]), message("<a>", &[])]);
+        assert_eq!(threads["<c>"], thread_key("<a>"));
+        assert_eq!(threads["<a>"], thread_key("<a>"));
+    }
+
+    #[test]
+    fn cycles_end() {
+        let threads = thread(&[
+            message("<a>", &["<b>"]),
+            message("<b>", &["<a>"]),
+            message("<c>", &["<c>"]),
+            message("<d>", &["<e>", "<d>", "<e>"]),
+        ]);
+        assert_eq!(threads["<a>"], threads["<b>"]);
+        assert_eq!(threads["<c>"], thread_key("<c>"));
+        assert_eq!(threads.len(), 4);
+    }
+
+    #[test]
+    fn reparents_when_the_chain_shows_up() {
+        // <c> only knew <b>, <b> later says it answered <a>
+        let threads = thread(&[
+            message("<c>", &["<b>"]),
+            message("<b>", &["<a>"]),
+            message("<a>", &[]),
+        ]);
+        assert_eq!(threads["<c>"], thread_key("<a>"));
+        assert_eq!(threads["<b>"], thread_key("<a>"));
+
+        // a link that is already known isn't overridden by a later chain
+        let threads = thread(&[message("<b>", &["<a>"]), message("<c>", &["<z>", "<b>"])]);
+        assert_eq!(threads["<b>"], thread_key("<a>"));
+        assert_eq!(threads["<c>"], thread_key("<a>"));
+    }
+
+    #[test]
+    fn groups_lost_replies_by_subject() {
+        let original = with_subject(message("<a>", &[]), "Lunch", 0, "ann@example.com");
+        let reply = with_subject(message("<b>", &[]), "Re: lunch", 2, "ann@example.com");
+        let threads = thread(&[original.clone(), reply]);
+        assert_eq!(threads["<b>"], thread_key("<a>"));
+
+        // too late, or nobody in common
+        let late = with_subject(message("<c>", &[]), "Re: Lunch", 40, "ann@example.com");
+        let stranger = with_subject(message("<d>", &[]), "RE: Lunch", 1, "bob@example.com");
+        let threads = thread(&[original.clone(), late, stranger]);
+        assert_eq!(threads["<c>"], thread_key("<c>"));
+        assert_eq!(threads["<d>"], thread_key("<d>"));
+
+        // two threads that both started fresh stay apart, a reply goes to the latest
+        let again = with_subject(message("<e>", &[]), "lunch", 3, "ann@example.com");
+        let reply = with_subject(message("<f>", &[]), "Fwd: Re: Lunch", 4, "ann@example.com");
+        let threads = thread(&[original, again, reply]);
+        assert_eq!(threads["<a>"], thread_key("<a>"));
+        assert_eq!(threads["<e>"], thread_key("<e>"));
+        assert_eq!(threads["<f>"], thread_key("<e>"));
+    }
+
+    #[test]
+    fn normalizes_subjects() {
+        assert_eq!(
+            normalize_subject(" Re: FW: aw:Hello "),
+            ("hello".to_string(), true)
+        );
+        assert_eq!(normalize_subject("Hello"), ("hello".to_string(), false));
+    }
+}
@@ -0,0 +1,381 @@
+use chrono::{Duration, Utc};
+
+use crate::{
+    config::{AccountConfig, DatabaseConfig, WriteBack},
+    database::{self, db_timestamp},
+    imap_session::{self, Session},
+    log::log,
+    models::PendingChange,
+};
+
+// a change is given up on after this many failed attempts
+const MAX_ATTEMPTS: i32 = 5;
+// a replay that claimed changes this long ago never finished
+const STALE_CLAIM_MINS: i64 = 10;
+
+// local state changes mirrored on the server, queued in pending_changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Pin(bool),
+    Done(bool),
+    Snooze(bool),
+    Seen(bool),
+}
+
+impl Change {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Change::Pin(true) => "pin",
+            Change::Pin(false) => "unpin",
+            Change::Done(true) => "done",
+            Change::Done(false) => "restore",
+            Change::Snooze(true) => "snooze",
+            Change::Snooze(false) => "unsnooze",
+            Change::Seen(true) => "seen",
+            Change::Seen(false) => "unseen",
+        }
+    }
+
+    pub fn parse(change: &str) -> Option<Change> {
+        match change {
+            "pin" => Some(Change::Pin(true)),
+            "unpin" => Some(Change::Pin(false)),
+            "done" => Some(Change::Done(true)),
+            "restore" => Some(Change::Done(false)),
+            "snooze" => Some(Change::Snooze(true)),
+            "unsnooze" => Some(Change::Snooze(false)),
+            "seen" => Some(Change::Seen(true)),
+            "unseen" => Some(Change::Seen(false)),
+            _ => None,
+        }
+    }
+}
+
+// pushes queued changes to the server in the order they were made. accounts
+// that can't connect keep theirs for the next call, as do accounts another
+// replay is working on.
+pub fn replay(
+    database_config: &DatabaseConfig,
+    accounts: &[AccountConfig],
+) -> Result<usize, String> {
+    let pending = database::pending_change_accounts(database_config)?;
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let mut applied = 0;
+    for account_name in pending.into_iter() {
+        let account = match accounts.iter().find(|a| a.name == account_name) {
+            Some(account) => account,
+            None => {
+                log(format!("write back: no account named {account_name}"));
+                continue;
+            }
+        };
+        let now = Utc::now();
+        let claimed = database::claim_changes(
+            database_config,
+            &account_name,
+            &db_timestamp(now),
+            &db_timestamp(now - Duration::minutes(STALE_CLAIM_MINS)),
+        );
+        let changes = match claimed {
+            Ok(changes) if changes.is_empty() => continue,
+            Ok(changes) => changes,
+            Err(e) => {
+                log(format!("write back for {account_name}: {e}"));
+                continue;
+            }
+        };
+        if !account.write_back.enabled {
+            for change in changes {
+                finish(database_config, &change);
+            }
+            continue;
+        }
+        let mut session = match imap_session::connect(account) {
+            Ok(session) => session,
+            Err(e) => {
+                log(format!("write back for {account_name} postponed: {e}"));
+                let ids = changes.iter().filter_map(|c| c.id).collect::<Vec<_>>();
+                if let Err(e) = database::release_changes(database_config, &ids) {
+                    log(format!("write back for {account_name}: {e}"));
+                }
+                continue;
+            }
+        };
+        for change in changes {
+            let result = match Change::parse(&change.change) {
+                Some(parsed) => apply(
+                    database_config,
+                    account,
+                    &mut session,
+                    &change.message_id,
+                    parsed,
+                ),
+                None => Err(format!("unknown change {}", change.change)),
+            };
+            match result {
+                Ok(()) => {
+                    finish(database_config, &change);
+                    applied += 1;
+                }
+                Err(e) if change.attempts + 1 >= MAX_ATTEMPTS => {
+                    log(format!(
+                        "write back: giving up on {} for {}: {}",
+                        change.change, change.message_id, e
+                    ));
+                    finish(database_config, &change);
+                }
+                Err(e) => {
+                    log(format!(
+                        "write back: {} for {} failed: {}",
+                        change.change, change.message_id, e
+                    ));
+                    // released, the next replay tries it again
+                    if let Err(e) =
+                        database::fail_change(database_config, change.id.unwrap_or_default(), &e)
+                    {
+                        log(format!("write back: recording the failure: {e}"));
+                    }
+                }
+            }
+        }
+        session.logout().ok();
+    }
+    Ok(applied)
+}
+
+// bookkeeping that fails leaves the change claimed, it is tried again once the claim
+// runs out
+fn finish(database_config: &DatabaseConfig, change: &PendingChange) {
+    if let Err(e) = database::finish_change(database_config, change.id.unwrap_or_default()) {
+        log(format!(
+            "write back: removing {} for {}: {}",
+            change.change, change.message_id, e
+        ));
+    }
+}
+
+fn apply(
+    database_config: &DatabaseConfig,
+    account: &AccountConfig,
+    session: &mut Session,
+    message_id: &str,
+    change: Change,
+) -> Result<(), String> {
+    let write_back = &account.write_back;
+    let stored = database::message_folders(database_config, &account.name, message_id);
+    let candidates = candidates(write_back, stored, change);
+    let (folder, uid) = match locate(session, &candidates, message_id)? {
+        Some(found) => found,
+        None => {
+            // gone from the server, nothing left to change
+            log(format!("write back: {message_id} not found on the server"));
+            return Ok(());
+        }
+    };
+    let uid = uid.to_string();
+    let move_message = |session: &mut Session, to: &str| -> Result<(), String> {
+        move_to(session, &uid, to)?;
+        database::move_folder(database_config, &account.name, message_id, &folder, to)
+    };
+
+    match change {
+        Change::Pin(pinned) => store(session, &uid, pinned, "\\Flagged"),
+        Change::Seen(seen) => store(session, &uid, seen, "\\Seen"),
+        Change::Done(done) => match &write_back.done_flag {
+            Some(flag) => store(session, &uid, done, flag),
+            None if done && folder != write_back.archive_folder => {
+                move_message(session, &write_back.archive_folder)
+            }
+            None if !done && folder != write_back.inbox_folder => {
+                move_message(session, &write_back.inbox_folder)
+            }
+            None => Ok(()),
+        },
+        Change::Snooze(snoozed) => match &write_back.snooze_folder {
+            Some(snooze_folder) if snoozed && folder != *snooze_folder => {
+                move_message(session, snooze_folder)
+            }
+            Some(snooze_folder) if !snoozed && folder == *snooze_folder => {
+                move_message(session, &write_back.inbox_folder)
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+// where to look for the message, in order. a message can be in more than one folder
+// (gmail's All Mail has everything), the inbox comes first so done moves it out of
+// there and restore finds it already back. unsnoozing looks in the snooze folder first.
+fn candidates(write_back: &WriteBack, mut stored: Vec<String>, change: Change) -> Vec<String> {
+    let first = match (change, &write_back.snooze_folder) {
+        (Change::Snooze(false), Some(snooze_folder)) => snooze_folder,
+        _ => &write_back.inbox_folder,
+    };
+    stored.sort();
+    let mut candidates = vec![first.clone()];
+    for folder in [&write_back.inbox_folder]
+        .into_iter()
+        .chain(stored.iter())
+        .chain([&write_back.archive_folder])
+        .chain(write_back.snooze_folder.iter())
+    {
+        if !candidates.contains(folder) {
+            candidates.push(folder.clone());
+        }
+    }
+    candidates
+}
+
+// first folder holding the message, left selected
+fn locate(
+    session: &mut Session,
+    folders: &[String],
+    message_id: &str,
+) -> Result<Option<(String, u32)>, String> {
+    for folder in folders.iter() {
+        // folders that don't exist on this server are skipped
+        if imap_session::select(session, folder).is_err() {
+            continue;
+        }
+        if let Some(uid) = imap_session::find_uid(session, message_id)? {
+            return Ok(Some((folder.clone(), uid)));
+        }
+    }
+    Ok(None)
+}
+
+fn store(session: &mut Session, uid: &str, set: bool, flag: &str) -> Result<(), String> {
+    let query = format!("{}FLAGS ({})", if set { "+" } else { "-" }, flag);
+    session
+        .uid_store(uid, query)
+        .map(|_| ())
+        .map_err(|e| format!("store {flag}: {e}"))
+}
+
+fn move_to(session: &mut Session, uid: &str, folder: &str) -> Result<(), String> {
+    session
+        .uid_mv(uid, folder)
+        .map_err(|e| format!("move to {folder}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{QueryDsl, RunQueryDsl};
+
+    use super::*;
+    use crate::{database::tests::scratch_database, schema::pending_changes};
+
+    fn write_back(snooze_folder: Option<&str>) -> WriteBack {
+        WriteBack {
+            archive_folder: "[Gmail]/All Mail".to_string(),
+            snooze_folder: snooze_folder.map(|f| f.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn stored(folders: &[&str]) -> Vec<String> {
+        folders.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn changes_round_trip() {
+        for change in [
+            Change::Pin(true),
+            Change::Pin(false),
+            Change::Done(true),
+            Change::Done(false),
+            Change::Snooze(true),
+            Change::Snooze(false),
+            Change::Seen(true),
+            Change::Seen(false),
+        ] {
+            assert_eq!(Change::parse(change.as_str()), Some(change));
+        }
+        assert_eq!(Change::parse("archive"), None);
+    }
+
+    #[test]
+    fn looks_in_the_inbox_first() {
+        // stored in no particular order, all mail holds every message
+        let found = stored(&["[Gmail]/All Mail", "Work", "INBOX"]);
+        for change in [
+            Change::Done(true),
+            Change::Done(false),
+            Change::Snooze(true),
+        ] {
+            assert_eq!(
+                candidates(&write_back(Some("Snoozed")), found.clone(), change),
+                stored(&["INBOX", "Work", "[Gmail]/All Mail", "Snoozed"])
+            );
+        }
+        // nothing stored, the configured folders in order
+        assert_eq!(
+            candidates(&write_back(None), vec![], Change::Pin(true)),
+            stored(&["INBOX", "[Gmail]/All Mail"])
+        );
+    }
+
+    #[test]
+    fn unsnooze_looks_in_the_snooze_folder_first() {
+        let found = stored(&["Snoozed", "[Gmail]/All Mail"]);
+        assert_eq!(
+            candidates(
+                &write_back(Some("Snoozed")),
+                found.clone(),
+                Change::Snooze(false)
+            ),
+            stored(&["Snoozed", "INBOX", "[Gmail]/All Mail"])
+        );
+        // without a snooze folder there is nothing to move back
+        assert_eq!(
+            candidates(&write_back(None), found, Change::Snooze(false)),
+            stored(&["INBOX", "Snoozed", "[Gmail]/All Mail"])
+        );
+    }
+
+    #[test]
+    fn replays_only_the_accounts_passed() {
+        let (database_config, mut conn) = scratch_database("replay");
+        let change = |account: &str| PendingChange {
+            id: None,
+            account: account.to_string(),
+            message_id: "<a@example.com>".to_string(),
+            change: "pin".to_string(),
+            created_at: db_timestamp(Utc::now()),
+            attempts: 0,
+            last_error: None,
+            claimed_at: None,
+        };
+        diesel::insert_into(pending_changes::table)
+            .values(&vec![change("off"), change("elsewhere")])
+            .execute(&mut conn)
+            .unwrap();
+
+        // write back is off for this account, its changes are dropped without connecting
+        let mut account: AccountConfig = toml::from_str(
+            r#"
+            name = "off"
+            default = true
+            [account]
+            email = "me@example.com"
+            display_name = "Me"
+            [imap]
+            login = "me@example.com"
+            passwd = "secret"
+            "#,
+        )
+        .unwrap();
+        account.write_back.enabled = false;
+        assert_eq!(replay(&database_config, &[account]), Ok(0));
+
+        // an account that wasn't passed keeps its changes
+        let left = pending_changes::table
+            .select(pending_changes::account)
+            .load::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(left, vec!["elsewhere".to_string()]);
+    }
+}